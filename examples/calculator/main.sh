@@ -14,9 +14,6 @@ schema=$((cat - | jq -c) <<EOF
     "properties": {
         "input": {
             "type": "string"
-        },
-        "output": {
-            "type": "string"
         }
     },
     "required": [
@@ -64,7 +61,7 @@ function calculation_worker {
             payload=$(printf '{"id": "%s"}' $line);
             calc=$(call_opencore catalog.Resources/Get "$payload");
             
-            if [[ $(echo $calc | jq -r .data | jq -r .output) == "null" ]]; then
+            if [[ $(echo $calc | jq -r .status | jq -r .output) == "null" ]]; then
                 echo "Worker $1: The calculation task is in fact not ready, lets do it"
                 echo "Resource data: $calc"
                 data=$(echo $calc | jq -r .data | jq -c .)
@@ -76,11 +73,12 @@ function calculation_worker {
                 echo "$input | BC_LINE_LENGTH=0 bc -l"
                 output=$(echo $input | BC_LINE_LENGTH=0 bc -l);
                 
-                # save the result by patching the resource
+                # report the result in the status, the data stays owned by the user
                 echo "Worker $1: Send result"
+                generation=$(echo $calc | jq -r .generation)
                 patch=$(jq -cn --arg output $output '{output: $output}')
-                payload=$(jq -cn --arg id "$calc_id" --arg data "$patch" '{id: $id, data: $data}')
-                call_opencore catalog.Resources/Update "$payload" >/dev/null;
+                payload=$(jq -cn --arg id "$calc_id" --arg status "$patch" --arg generation "$generation" '{id: $id, status: $status, observed_generation: $generation}')
+                call_opencore catalog.Resources/UpdateStatus "$payload" >/dev/null;
             else
                 echo "Worker $1: The calculation task is already done in the meantime, nothing to do here."
             fi
@@ -102,7 +100,7 @@ export TOKEN=$(call_opencore idp.Authentication/Login "$payload" | jq -r .access
 
 echo "Main: Create calculation resource with input '${CALC_TASK}'"
 data=$(printf '{"input": "%s"}' "${CALC_TASK}" | jq -c)
-payload=$(jq -n --arg data "$data" '{kind: "calculation", data: $data, shares: [{principal_id: "calculator", actions: ["read", "write"]}]}')
+payload=$(jq -n --arg data "$data" '{kind: "calculation", data: $data, shares: [{principal_id: "calculator", actions: ["read", "status"]}]}')
 
 start_ms=$(($(date +%s%N)/1000000)) # timestamp in milliseconds
 
@@ -111,8 +109,9 @@ calculation_id=$(echo $calculation | jq -r .id)
 echo "Main: new calculation id: $calculation_id"
 echo $calculation
 
-payload=$(printf '{"resource_id": "%s", "event_type": "UPDATE"}' $calculation_id)
-echo "Main: listen for update events on $calculation_id"
+payload=$(printf '{"resource_id": "%s", "event_type": "STATUS_UPDATE"}' $calculation_id)
+echo "Main: listen for status updates on $calculation_id"
+# the data of a STATUS_UPDATE event is the status
 call_opencore catalog.Events/Subscribe "$payload" | jq -c --unbuffered | {
     read -r line
     echo $line | jq -r .data | jq -r .output
@@ -0,0 +1,169 @@
+use async_graphql::http::{WebSocket, WsMessage};
+use axum::extract::ws::{Message, WebSocketUpgrade};
+use axum::extract::Extension;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use futures::{SinkExt, StreamExt};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::managers;
+use crate::rest::Error;
+use crate::token;
+
+pub mod schema;
+
+/// Shared state of the GraphQL endpoint.
+#[derive(Debug)]
+pub struct State {
+    resources: Arc<managers::resources::Manager>,
+    schemas: Arc<managers::schemas::Manager>,
+    events: Arc<managers::events::Manager>,
+    validator: Arc<token::Validator>,
+    // generated schema together with the schema revision it was built from
+    cache: Mutex<Option<(String, async_graphql::dynamic::Schema)>>,
+}
+
+impl State {
+    pub fn new(
+        resources: Arc<managers::resources::Manager>,
+        schemas: Arc<managers::schemas::Manager>,
+        events: Arc<managers::events::Manager>,
+        validator: Arc<token::Validator>,
+    ) -> State {
+        State {
+            resources,
+            schemas,
+            events,
+            validator,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Returns the GraphQL schema, regenerating it if the stored schemas changed.
+    async fn schema(&self) -> Result<async_graphql::dynamic::Schema, Error> {
+        use futures::TryStreamExt;
+
+        let revision = self.schemas.revision().await?;
+        let mut cache = self.cache.lock().await;
+        if let Some((cached_revision, schema)) = cache.as_ref() {
+            if *cached_revision == revision {
+                return Ok(schema.clone());
+            }
+        }
+
+        let schemas: Vec<_> = self.schemas.list().await?.try_collect().await?;
+        let schema = match schema::build(&schemas, self.resources.clone(), self.events.clone()) {
+            Ok(schema) => schema,
+            Err(err) => {
+                return Err(Error::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("failed to build graphql schema: {}", err.0),
+                ))
+            }
+        };
+        *cache = Some((revision, schema.clone()));
+        Ok(schema)
+    }
+}
+
+/// Serves the GraphQL endpoint at `/graphql`. Queries and mutations are sent via
+/// POST, subscriptions use a websocket (graphql-ws or graphql-transport-ws protocol).
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/graphql", get(subscribe).post(execute))
+        .layer(Extension(state))
+}
+
+#[tracing::instrument(name = "graphql::execute", skip(state))]
+async fn execute(
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<State>>,
+    Json(request): Json<async_graphql::Request>,
+) -> Result<Json<async_graphql::Response>, Error> {
+    let context = state.validator.get_context_from_headers(&headers)?;
+    let schema = state.schema().await?;
+    Ok(Json(schema.execute(request.data(context)).await))
+}
+
+/// Upgrades to a websocket for subscriptions. The access token is taken from the
+/// upgrade request or, for browsers, from the `Authorization` key of the
+/// connection_init payload.
+#[tracing::instrument(name = "graphql::subscribe", skip(state, upgrade))]
+async fn subscribe(
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<State>>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, Error> {
+    let protocol = headers
+        .get("sec-websocket-protocol")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .find_map(|protocol| protocol.trim().parse().ok())
+        })
+        .unwrap_or(async_graphql::http::WebSocketProtocols::SubscriptionsTransportWS);
+
+    let schema = state.schema().await?;
+    let context = state.validator.get_context_from_headers(&headers).ok();
+
+    Ok(upgrade
+        .protocols(["graphql-transport-ws", "graphql-ws"])
+        .on_upgrade(move |socket| async move {
+            let (mut sink, stream) = socket.split();
+            let input = stream
+                .take_while(|msg| futures::future::ready(msg.is_ok()))
+                .map(Result::unwrap)
+                .filter_map(|msg| {
+                    futures::future::ready(match msg {
+                        Message::Text(text) => Some(text.into_bytes()),
+                        Message::Binary(bytes) => Some(bytes),
+                        _ => None,
+                    })
+                });
+
+            let mut output = WebSocket::new(schema, input, protocol).on_connection_init(
+                move |payload| async move {
+                    let mut data = async_graphql::Data::default();
+                    match context {
+                        Some(context) => data.insert(context),
+                        None => {
+                            let token = payload
+                                .get("Authorization")
+                                .or_else(|| payload.get("authorization"))
+                                .and_then(|value| value.as_str())
+                                .unwrap_or_default();
+                            let mut headers = HeaderMap::new();
+                            if let Ok(value) = token.parse() {
+                                headers.insert("Authorization", value);
+                            }
+                            match state.validator.get_context_from_headers(&headers) {
+                                Ok(context) => data.insert(context),
+                                Err(err) => return Err(async_graphql::Error::new(err.to_string())),
+                            }
+                        }
+                    }
+                    Ok(data)
+                },
+            );
+
+            while let Some(msg) = output.next().await {
+                let msg = match msg {
+                    WsMessage::Text(text) => Message::Text(text),
+                    WsMessage::Close(code, reason) => {
+                        Message::Close(Some(axum::extract::ws::CloseFrame {
+                            code,
+                            reason: reason.into(),
+                        }))
+                    }
+                };
+                if sink.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        })
+        .into_response())
+}
@@ -0,0 +1,865 @@
+use async_graphql::dynamic::{
+    Enum, Field, FieldFuture, FieldValue, InputValue, Interface, InterfaceField, Object,
+    ResolverContext, Scalar, Schema, SchemaError, Subscription, SubscriptionField,
+    SubscriptionFieldFuture, TypeRef,
+};
+use futures::StreamExt;
+use sqlx::types::Uuid;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::api;
+use crate::managers;
+use crate::token::Context;
+
+use api::catalog::{Event, EventType, Resource, ResourceFilter};
+
+const JSON: &str = "JSON";
+const RESOURCE: &str = "Resource";
+const GENERIC_RESOURCE: &str = "GenericResource";
+const EVENT: &str = "Event";
+const EVENT_TYPE: &str = "EventType";
+
+/// Type names which are used by the static part of the schema or are built in.
+const RESERVED: &[&str] = &[
+    TypeRef::STRING,
+    TypeRef::INT,
+    TypeRef::FLOAT,
+    TypeRef::BOOLEAN,
+    TypeRef::ID,
+    JSON,
+    RESOURCE,
+    GENERIC_RESOURCE,
+    EVENT,
+    EVENT_TYPE,
+    "Query",
+    "Mutation",
+    "Subscription",
+];
+
+/// Maps resource kinds to the names of their generated object types.
+type KindTypes = Arc<HashMap<String, String>>;
+
+/// Builds the GraphQL schema from the stored JSON schemas. Every kind gets an
+/// object type implementing the `Resource` interface with a typed `data` field,
+/// a query for a single resource and for lists, and create/update/delete mutations.
+pub fn build(
+    schemas: &[api::catalog::Schema],
+    resources: Arc<managers::resources::Manager>,
+    events: Arc<managers::events::Manager>,
+) -> Result<Schema, SchemaError> {
+    // a schema which is no valid JSON is left out, resources of its kind are
+    // exposed as generic resources
+    let schemas: Vec<(&api::catalog::Schema, serde_json::Value)> = schemas
+        .iter()
+        .filter_map(|schema| match serde_json::from_str(&schema.data) {
+            Ok(data) => Some((schema, data)),
+            Err(err) => {
+                log::warn!(
+                    "left out kind {} from the GraphQL schema: invalid schema: {}",
+                    schema.kind,
+                    err
+                );
+                None
+            }
+        })
+        .collect();
+
+    // every generated type name is recorded, a clash would fail the whole schema
+    let mut names: HashSet<String> = RESERVED.iter().map(|name| name.to_string()).collect();
+    let mut kinds = HashMap::new();
+    for (schema, _) in &schemas {
+        let mut type_name = pascal_case(&schema.kind);
+        while names.contains(&type_name) {
+            type_name.push_str("Kind");
+        }
+        names.insert(type_name.clone());
+        kinds.insert(schema.kind.clone(), type_name);
+    }
+    let kinds: KindTypes = Arc::new(kinds);
+
+    let mut query = Object::new("Query").field(
+        Field::new("resource", TypeRef::named(RESOURCE), {
+            let kinds = kinds.clone();
+            move |ctx| {
+                let kinds = kinds.clone();
+                FieldFuture::new(async move {
+                    let context = ctx.data::<Context>()?;
+                    let resources = ctx.data::<Arc<managers::resources::Manager>>()?;
+                    let id = parse_uuid(ctx.args.try_get("id")?.string()?)?;
+                    let res = resources.get(&context.claims, &id).await?;
+                    Ok(Some(resource_value(&kinds, res)))
+                })
+            }
+        })
+        .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID))),
+    );
+    let mut mutation = Object::new("Mutation");
+    let mut types: Vec<Object> = vec![];
+
+    for (schema, data) in &schemas {
+        let kind = schema.kind.clone();
+        let type_name = kinds[&kind].clone();
+        let field_name = camel_case(&type_name);
+
+        let data_type = unique_name(&mut names, format!("{}Data", type_name));
+        let (data_type_ref, typed) = match data_object(&data_type, data, &mut types, &mut names) {
+            Some(object) => {
+                types.push(object);
+                (TypeRef::named(&data_type), true)
+            }
+            None => (TypeRef::named(JSON), false),
+        };
+
+        types.push(
+            resource_fields(Object::new(&type_name), kinds.clone())
+                .implement(RESOURCE)
+                .field(Field::new("data", data_type_ref, move |ctx| {
+                    FieldFuture::new(async move {
+                        let res = ctx.parent_value.try_downcast_ref::<Resource>()?;
+                        let data: serde_json::Value = serde_json::from_str(&res.data)?;
+                        // the object type resolves its fields from the JSON value,
+                        // the scalar needs a GraphQL value
+                        match typed {
+                            true => Ok(Some(FieldValue::owned_any(data))),
+                            false => Ok(Some(FieldValue::value(async_graphql::Value::from_json(
+                                data,
+                            )?))),
+                        }
+                    })
+                })),
+        );
+
+        query = query
+            .field(
+                Field::new(&field_name, TypeRef::named(&type_name), {
+                    let kind = kind.clone();
+                    move |ctx| {
+                        let kind = kind.clone();
+                        FieldFuture::new(async move {
+                            let context = ctx.data::<Context>()?;
+                            let resources = ctx.data::<Arc<managers::resources::Manager>>()?;
+                            let id = parse_uuid(ctx.args.try_get("id")?.string()?)?;
+                            let res = resources.get(&context.claims, &id).await?;
+                            if res.kind != kind {
+                                return Ok(None);
+                            }
+                            Ok(Some(FieldValue::owned_any(res)))
+                        })
+                    }
+                })
+                .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID))),
+            )
+            .field(
+                Field::new(
+                    format!("{}List", field_name),
+                    TypeRef::named_nn_list_nn(&type_name),
+                    {
+                        let kind = kind.clone();
+                        move |ctx| {
+                            let kind = kind.clone();
+                            FieldFuture::new(async move {
+                                let context = ctx.data::<Context>()?;
+                                let resources = ctx.data::<Arc<managers::resources::Manager>>()?;
+                                let labels = labels_arg(&ctx)?;
+                                let filter = string_arg(&ctx, "filter")?;
+                                let query = string_arg(&ctx, "query")?;
+                                let where_filter = where_arg(&ctx)?;
+                                let mut stream = resources
+                                    .list(managers::resources::ListOptions {
+                                        claims: &context.claims,
+                                        labels: &labels,
+                                        filter: &filter,
+                                        kind: &kind,
+                                        search_term: &query,
+                                        highlight: false,
+                                        parent_id: None,
+                                        descendants_of: None,
+                                        max_depth: 0,
+                                        include_deleted: false,
+                                        fields: &[],
+                                        where_filter: where_filter.as_ref(),
+                                        expand: &[],
+                                    })
+                                    .await?;
+                                let mut res = vec![];
+                                while let Some(resource) = stream.next().await {
+                                    res.push(FieldValue::owned_any(resource?));
+                                }
+                                Ok(Some(FieldValue::list(res)))
+                            })
+                        }
+                    },
+                )
+                .argument(InputValue::new("labels", TypeRef::named(JSON)))
+                .argument(InputValue::new("filter", TypeRef::named(TypeRef::STRING)))
+                .argument(InputValue::new("query", TypeRef::named(TypeRef::STRING)))
+                .argument(InputValue::new("where", TypeRef::named(JSON))),
+            );
+
+        mutation = mutation
+            .field(
+                Field::new(
+                    format!("create{}", type_name),
+                    TypeRef::named_nn(&type_name),
+                    {
+                        let kind = kind.clone();
+                        move |ctx| {
+                            let kind = kind.clone();
+                            FieldFuture::new(async move {
+                                let context = ctx.data::<Context>()?;
+                                let resources = ctx.data::<Arc<managers::resources::Manager>>()?;
+                                let data: serde_json::Value =
+                                    ctx.args.try_get("data")?.deserialize()?;
+                                let labels = labels_arg(&ctx)?;
+                                let parent_id = optional_uuid_arg(&ctx, "parentId")?;
+                                let permission_parent_id =
+                                    optional_uuid_arg(&ctx, "permissionParentId")?;
+                                let res = resources
+                                    .create(managers::resources::CreateOptions {
+                                        context,
+                                        kind: &kind,
+                                        parent_id: parent_id.as_ref(),
+                                        permission_parent_id: permission_parent_id.as_ref(),
+                                        data: &data,
+                                        labels: &labels,
+                                        shares: &vec![],
+                                        expires_at: None,
+                                    })
+                                    .await?;
+                                Ok(Some(FieldValue::owned_any(res)))
+                            })
+                        }
+                    },
+                )
+                .argument(InputValue::new("data", TypeRef::named_nn(JSON)))
+                .argument(InputValue::new("labels", TypeRef::named(JSON)))
+                .argument(InputValue::new("parentId", TypeRef::named(TypeRef::ID)))
+                .argument(InputValue::new(
+                    "permissionParentId",
+                    TypeRef::named(TypeRef::ID),
+                )),
+            )
+            .field(
+                Field::new(
+                    format!("update{}", type_name),
+                    TypeRef::named_nn(&type_name),
+                    {
+                        let kind = kind.clone();
+                        move |ctx| {
+                            let kind = kind.clone();
+                            FieldFuture::new(async move {
+                                let context = ctx.data::<Context>()?;
+                                let resources = ctx.data::<Arc<managers::resources::Manager>>()?;
+                                let id = parse_uuid(ctx.args.try_get("id")?.string()?)?;
+                                check_kind(resources, context, &id, &kind).await?;
+                                let data: serde_json::Value = match ctx.args.get("data") {
+                                    Some(data) if !data.is_null() => data.deserialize()?,
+                                    _ => serde_json::Value::Object(serde_json::Map::new()),
+                                };
+                                let labels = labels_arg(&ctx)?;
+                                let res = resources
+                                    .update(
+                                        context,
+                                        &id,
+                                        &managers::resources::Patch::Merge(data),
+                                        &[],
+                                        &labels,
+                                        None,
+                                    )
+                                    .await?;
+                                Ok(Some(FieldValue::owned_any(res)))
+                            })
+                        }
+                    },
+                )
+                .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID)))
+                .argument(InputValue::new("data", TypeRef::named(JSON)))
+                .argument(InputValue::new("labels", TypeRef::named(JSON))),
+            )
+            .field(
+                Field::new(
+                    format!("delete{}", type_name),
+                    TypeRef::named_nn(&type_name),
+                    {
+                        let kind = kind.clone();
+                        move |ctx| {
+                            let kind = kind.clone();
+                            FieldFuture::new(async move {
+                                let context = ctx.data::<Context>()?;
+                                let resources = ctx.data::<Arc<managers::resources::Manager>>()?;
+                                let id = parse_uuid(ctx.args.try_get("id")?.string()?)?;
+                                check_kind(resources, context, &id, &kind).await?;
+                                let res = resources.delete(context, &id).await?;
+                                Ok(Some(FieldValue::owned_any(res)))
+                            })
+                        }
+                    },
+                )
+                .argument(InputValue::new("id", TypeRef::named_nn(TypeRef::ID))),
+            );
+    }
+
+    let subscription = Subscription::new("Subscription").field(
+        SubscriptionField::new("events", TypeRef::named_nn(EVENT), |ctx| {
+            SubscriptionFieldFuture::new(async move {
+                use managers::events::SubscribeFilter;
+
+                let context = ctx.data::<Context>()?;
+                let events = ctx.data::<Arc<managers::events::Manager>>()?;
+
+                let mut filters = vec![];
+                let resource_id = string_arg(&ctx, "resourceId")?;
+                if !resource_id.is_empty() {
+                    filters.push(SubscribeFilter::Resource(resource_id));
+                }
+                let kind = string_arg(&ctx, "kind")?;
+                if !kind.is_empty() {
+                    filters.push(SubscribeFilter::Kind(kind));
+                }
+                if let Some(event_type) = ctx.args.get("eventType") {
+                    if !event_type.is_null() {
+                        filters.push(SubscribeFilter::Type(event_type_from_name(
+                            event_type.enum_name()?,
+                        )));
+                    }
+                }
+
+                let stream = events.subscribe(&context.claims, &filters).await?;
+                Ok(stream.map(|event| match event {
+                    Ok(event) => Ok(FieldValue::owned_any(event)),
+                    Err(err) => Err(async_graphql::Error::new(err.message())),
+                }))
+            })
+        })
+        .argument(InputValue::new("resourceId", TypeRef::named(TypeRef::ID)))
+        .argument(InputValue::new("kind", TypeRef::named(TypeRef::STRING)))
+        .argument(InputValue::new("eventType", TypeRef::named(EVENT_TYPE))),
+    );
+
+    let has_mutations = !schemas.is_empty();
+    let mut builder = Schema::build(
+        "Query",
+        match has_mutations {
+            true => Some("Mutation"),
+            false => None,
+        },
+        Some("Subscription"),
+    )
+    .register(Scalar::new(JSON).description("arbitrary JSON value"))
+    .register(resource_interface())
+    .register(resource_fields(Object::new(GENERIC_RESOURCE), kinds.clone()).implement(RESOURCE))
+    .register(event_type_enum())
+    .register(event_object())
+    .register(query)
+    .register(subscription)
+    .data(resources)
+    .data(events);
+
+    if has_mutations {
+        builder = builder.register(mutation);
+    }
+
+    for object in types {
+        builder = builder.register(object);
+    }
+
+    builder.finish()
+}
+
+fn resource_interface() -> Interface {
+    Interface::new(RESOURCE)
+        .field(InterfaceField::new("id", TypeRef::named_nn(TypeRef::ID)))
+        .field(InterfaceField::new("parentId", TypeRef::named(TypeRef::ID)))
+        .field(InterfaceField::new(
+            "permissionParentId",
+            TypeRef::named(TypeRef::ID),
+        ))
+        .field(InterfaceField::new(
+            "creatorId",
+            TypeRef::named_nn(TypeRef::ID),
+        ))
+        .field(InterfaceField::new(
+            "kind",
+            TypeRef::named_nn(TypeRef::STRING),
+        ))
+        .field(InterfaceField::new("labels", TypeRef::named_nn(JSON)))
+        .field(InterfaceField::new("rawData", TypeRef::named_nn(JSON)))
+        .field(InterfaceField::new(
+            "createdAt",
+            TypeRef::named(TypeRef::STRING),
+        ))
+        .field(InterfaceField::new(
+            "updatedAt",
+            TypeRef::named(TypeRef::STRING),
+        ))
+        .field(
+            InterfaceField::new("children", TypeRef::named_nn_list_nn(RESOURCE))
+                .argument(InputValue::new("kind", TypeRef::named(TypeRef::STRING)))
+                .argument(InputValue::new("where", TypeRef::named(JSON))),
+        )
+}
+
+/// Adds the fields of the `Resource` interface to an object.
+fn resource_fields(object: Object, kinds: KindTypes) -> Object {
+    fn string_field(
+        name: &str,
+        ty: TypeRef,
+        getter: fn(&Resource) -> async_graphql::Value,
+    ) -> Field {
+        Field::new(name, ty, move |ctx| {
+            FieldFuture::new(async move {
+                let res = ctx.parent_value.try_downcast_ref::<Resource>()?;
+                Ok(Some(getter(res)))
+            })
+        })
+    }
+
+    object
+        .field(string_field("id", TypeRef::named_nn(TypeRef::ID), |r| {
+            r.id.clone().into()
+        }))
+        .field(string_field("parentId", TypeRef::named(TypeRef::ID), |r| {
+            optional_id(&r.id, &r.parent_id)
+        }))
+        .field(string_field(
+            "permissionParentId",
+            TypeRef::named(TypeRef::ID),
+            |r| optional_id(&r.id, &r.permission_parent_id),
+        ))
+        .field(string_field(
+            "creatorId",
+            TypeRef::named_nn(TypeRef::ID),
+            |r| r.creator_id.clone().into(),
+        ))
+        .field(string_field(
+            "kind",
+            TypeRef::named_nn(TypeRef::STRING),
+            |r| r.kind.clone().into(),
+        ))
+        .field(string_field("labels", TypeRef::named_nn(JSON), |r| {
+            async_graphql::Value::from_json(serde_json::to_value(&r.labels).unwrap_or_default())
+                .unwrap_or_default()
+        }))
+        .field(string_field("rawData", TypeRef::named_nn(JSON), |r| {
+            serde_json::from_str(&r.data)
+                .ok()
+                .and_then(|data| async_graphql::Value::from_json(data).ok())
+                .unwrap_or_default()
+        }))
+        .field(string_field(
+            "createdAt",
+            TypeRef::named(TypeRef::STRING),
+            |r| timestamp_value(&r.created_at),
+        ))
+        .field(string_field(
+            "updatedAt",
+            TypeRef::named(TypeRef::STRING),
+            |r| timestamp_value(&r.updated_at),
+        ))
+        .field(
+            Field::new(
+                "children",
+                TypeRef::named_nn_list_nn(RESOURCE),
+                move |ctx| {
+                    let kinds = kinds.clone();
+                    FieldFuture::new(async move {
+                        let context = ctx.data::<Context>()?;
+                        let resources = ctx.data::<Arc<managers::resources::Manager>>()?;
+                        let res = ctx.parent_value.try_downcast_ref::<Resource>()?;
+                        let kind = string_arg(&ctx, "kind")?;
+                        let where_filter = where_arg(&ctx)?;
+                        let mut stream = resources
+                            .list(managers::resources::ListOptions {
+                                claims: &context.claims,
+                                labels: &HashMap::new(),
+                                filter: "",
+                                kind: &kind,
+                                search_term: "",
+                                highlight: false,
+                                parent_id: Some(&parse_uuid(&res.id)?),
+                                descendants_of: None,
+                                max_depth: 0,
+                                include_deleted: false,
+                                fields: &[],
+                                where_filter: where_filter.as_ref(),
+                                expand: &[],
+                            })
+                            .await?;
+                        let mut children = vec![];
+                        while let Some(child) = stream.next().await {
+                            children.push(resource_value(&kinds, child?));
+                        }
+                        Ok(Some(FieldValue::list(children)))
+                    })
+                },
+            )
+            .argument(InputValue::new("kind", TypeRef::named(TypeRef::STRING)))
+            .argument(InputValue::new("where", TypeRef::named(JSON))),
+        )
+}
+
+fn event_type_enum() -> Enum {
+    Enum::new(EVENT_TYPE)
+        .item("CREATE")
+        .item("UPDATE")
+        .item("DELETE")
+        .item("SCHEDULED")
+        .item("STATUS_UPDATE")
+}
+
+fn event_type_from_name(name: &str) -> EventType {
+    match name {
+        "CREATE" => EventType::Create,
+        "UPDATE" => EventType::Update,
+        "DELETE" => EventType::Delete,
+        "SCHEDULED" => EventType::Scheduled,
+        "STATUS_UPDATE" => EventType::StatusUpdate,
+        _ => EventType::None,
+    }
+}
+
+fn event_object() -> Object {
+    fn event_field(name: &str, ty: TypeRef, getter: fn(&Event) -> async_graphql::Value) -> Field {
+        Field::new(name, ty, move |ctx| {
+            FieldFuture::new(async move {
+                let evt = ctx.parent_value.try_downcast_ref::<Event>()?;
+                Ok(Some(getter(evt)))
+            })
+        })
+    }
+
+    Object::new(EVENT)
+        .field(event_field("id", TypeRef::named_nn(TypeRef::ID), |e| {
+            e.id.clone().into()
+        }))
+        .field(event_field(
+            "resourceId",
+            TypeRef::named_nn(TypeRef::ID),
+            |e| e.resource_id.clone().into(),
+        ))
+        .field(event_field(
+            "resourceKind",
+            TypeRef::named_nn(TypeRef::STRING),
+            |e| e.resource_kind.clone().into(),
+        ))
+        .field(event_field(
+            "resourceLabels",
+            TypeRef::named_nn(JSON),
+            |e| {
+                async_graphql::Value::from_json(
+                    serde_json::to_value(&e.resource_labels).unwrap_or_default(),
+                )
+                .unwrap_or_default()
+            },
+        ))
+        .field(event_field(
+            "eventType",
+            TypeRef::named_nn(EVENT_TYPE),
+            |e| {
+                let name = match EventType::from(e.event_type) {
+                    EventType::Create => "CREATE",
+                    EventType::Update => "UPDATE",
+                    EventType::Delete => "DELETE",
+                    EventType::Scheduled => "SCHEDULED",
+                    EventType::StatusUpdate => "STATUS_UPDATE",
+                    EventType::None => return async_graphql::Value::Null,
+                };
+                async_graphql::Value::Enum(async_graphql::Name::new(name))
+            },
+        ))
+        .field(event_field("data", TypeRef::named(JSON), |e| {
+            serde_json::from_str(&e.data)
+                .ok()
+                .and_then(|data| async_graphql::Value::from_json(data).ok())
+                .unwrap_or_default()
+        }))
+        .field(event_field(
+            "createdAt",
+            TypeRef::named(TypeRef::STRING),
+            |e| timestamp_value(&e.created_at),
+        ))
+}
+
+/// Generates an object type for an object JSON schema. Nested object schemas get
+/// their own types which are appended to `types`. Returns None if the schema has
+/// no properties, in which case the data is exposed as JSON scalar.
+fn data_object(
+    type_name: &str,
+    schema: &serde_json::Value,
+    types: &mut Vec<Object>,
+    names: &mut HashSet<String>,
+) -> Option<Object> {
+    let properties = schema.get("properties")?.as_object()?;
+    if properties.is_empty() {
+        return None;
+    }
+
+    let mut object = Object::new(type_name);
+    let mut used_names = vec![];
+    for (property, spec) in properties {
+        let field_name = field_name(property);
+        if used_names.contains(&field_name) {
+            continue;
+        }
+        used_names.push(field_name.clone());
+
+        let nested_name = format!("{}{}", type_name, pascal_case(property));
+        let ty = match data_type(&nested_name, spec, types, names) {
+            (name, true) => TypeRef::named_list(name),
+            (name, false) => TypeRef::named(name),
+        };
+
+        let property = property.clone();
+        object = object.field(Field::new(field_name, ty, move |ctx| {
+            let property = property.clone();
+            FieldFuture::new(async move {
+                let data = ctx.parent_value.try_downcast_ref::<serde_json::Value>()?;
+                Ok(data.get(&property).and_then(json_field_value))
+            })
+        }));
+    }
+    Some(object)
+}
+
+/// Returns the GraphQL type name for a property schema and whether it is a list.
+fn data_type(
+    nested_name: &str,
+    spec: &serde_json::Value,
+    types: &mut Vec<Object>,
+    names: &mut HashSet<String>,
+) -> (String, bool) {
+    let ty = match spec.get("type") {
+        Some(serde_json::Value::String(ty)) => ty.as_str(),
+        Some(serde_json::Value::Array(tys)) => tys
+            .iter()
+            .filter_map(|ty| ty.as_str())
+            .find(|ty| *ty != "null")
+            .unwrap_or(""),
+        _ => "",
+    };
+    match ty {
+        "string" => (TypeRef::STRING.to_string(), false),
+        "integer" => (TypeRef::INT.to_string(), false),
+        "number" => (TypeRef::FLOAT.to_string(), false),
+        "boolean" => (TypeRef::BOOLEAN.to_string(), false),
+        "object" => {
+            let nested_name = unique_name(names, nested_name.to_string());
+            match data_object(&nested_name, spec, types, names) {
+                Some(object) => {
+                    types.push(object);
+                    (nested_name, false)
+                }
+                None => (JSON.to_string(), false),
+            }
+        }
+        "array" => match spec.get("items") {
+            Some(items) => match data_type(nested_name, items, types, names) {
+                (name, false) => (name, true),
+                (_, true) => (JSON.to_string(), true),
+            },
+            None => (JSON.to_string(), true),
+        },
+        _ => (JSON.to_string(), false),
+    }
+}
+
+/// Records the type name as used, a number is appended to it if it is taken.
+fn unique_name(names: &mut HashSet<String>, name: String) -> String {
+    let mut res = name.clone();
+    let mut n = 1;
+    while names.contains(&res) {
+        n += 1;
+        res = format!("{}{}", name, n);
+    }
+    names.insert(res.clone());
+    res
+}
+
+/// Converts a JSON value of a data property into a field value. Objects stay
+/// JSON values so nested types can resolve their own properties.
+fn json_field_value(value: &serde_json::Value) -> Option<FieldValue<'static>> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::Object(_) => Some(FieldValue::owned_any(value.clone())),
+        serde_json::Value::Array(items) => {
+            Some(FieldValue::list(items.iter().map(|item| {
+                json_field_value(item).unwrap_or(FieldValue::NULL)
+            })))
+        }
+        _ => async_graphql::Value::from_json(value.clone())
+            .ok()
+            .map(FieldValue::value),
+    }
+}
+
+fn resource_value(kinds: &KindTypes, res: Resource) -> FieldValue<'static> {
+    let type_name = kinds
+        .get(&res.kind)
+        .cloned()
+        .unwrap_or_else(|| GENERIC_RESOURCE.to_string());
+    FieldValue::owned_any(res).with_type(type_name)
+}
+
+async fn check_kind(
+    resources: &managers::resources::Manager,
+    context: &Context,
+    id: &Uuid,
+    kind: &str,
+) -> async_graphql::Result<()> {
+    let res = resources.get(&context.claims, id).await?;
+    if res.kind != kind {
+        return Err(async_graphql::Error::new("not found"));
+    }
+    Ok(())
+}
+
+fn parse_uuid(id: &str) -> async_graphql::Result<Uuid> {
+    Uuid::parse_str(id).map_err(|err| format!("failed to parse uuid: {}", err).into())
+}
+
+fn string_arg(ctx: &ResolverContext<'_>, name: &str) -> async_graphql::Result<String> {
+    match ctx.args.get(name) {
+        Some(value) if !value.is_null() => Ok(value.string()?.to_string()),
+        _ => Ok(String::new()),
+    }
+}
+
+fn optional_uuid_arg(ctx: &ResolverContext<'_>, name: &str) -> async_graphql::Result<Option<Uuid>> {
+    match string_arg(ctx, name)?.as_str() {
+        "" => Ok(None),
+        id => Ok(Some(parse_uuid(id)?)),
+    }
+}
+
+fn labels_arg(ctx: &ResolverContext<'_>) -> async_graphql::Result<HashMap<String, String>> {
+    match ctx.args.get("labels") {
+        Some(value) if !value.is_null() => Ok(value.deserialize()?),
+        _ => Ok(HashMap::new()),
+    }
+}
+
+/// Reads the typed filter passed as JSON in the `where` argument.
+fn where_arg(ctx: &ResolverContext<'_>) -> async_graphql::Result<Option<ResourceFilter>> {
+    match ctx.args.get("where") {
+        Some(value) if !value.is_null() => {
+            let value: serde_json::Value = value.deserialize()?;
+            Ok(Some(managers::resources::filter_from_json(&value)?))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Root resources store their own id as parent, which is exposed as null.
+fn optional_id(id: &str, other: &str) -> async_graphql::Value {
+    match id == other {
+        true => async_graphql::Value::Null,
+        false => other.to_string().into(),
+    }
+}
+
+fn timestamp_value(ts: &Option<prost_types::Timestamp>) -> async_graphql::Value {
+    use chrono::TimeZone;
+    match ts {
+        Some(ts) => chrono::Utc
+            .timestamp(ts.seconds, ts.nanos as u32)
+            .to_rfc3339()
+            .into(),
+        None => async_graphql::Value::Null,
+    }
+}
+
+fn field_name(name: &str) -> String {
+    let mut res: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    // names must not start with a digit and "__" is reserved for introspection
+    if res.is_empty() || res.starts_with(|c: char| c.is_ascii_digit()) || res.starts_with("__") {
+        res.insert(0, 'f');
+    }
+    res
+}
+
+fn pascal_case(name: &str) -> String {
+    let res: String = name
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect();
+    match res.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        true => res,
+        false => format!("T{}", res),
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    let pascal = pascal_case(name);
+    let mut chars = pascal.chars();
+    match chars.next() {
+        Some(first) => first.to_ascii_lowercase().to_string() + chars.as_str(),
+        None => pascal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_name_is_a_valid_graphql_name() {
+        assert_eq!(field_name("input"), "input");
+        assert_eq!(field_name("first-name"), "first_name");
+        assert_eq!(field_name("a.b c"), "a_b_c");
+        assert_eq!(field_name("1st"), "f1st");
+        assert_eq!(field_name("__typename"), "f__typename");
+        assert_eq!(field_name(""), "f");
+    }
+
+    #[test]
+    fn pascal_case_joins_the_words() {
+        assert_eq!(pascal_case("calculation"), "Calculation");
+        assert_eq!(pascal_case("todo-list_item"), "TodoListItem");
+        assert_eq!(pascal_case("a..b"), "AB");
+        assert_eq!(pascal_case("1st"), "T1st");
+        assert_eq!(pascal_case(""), "T");
+        assert_eq!(camel_case("todo-list"), "todoList");
+    }
+
+    #[test]
+    fn generated_type_names_are_unique() {
+        let mut names: HashSet<String> = RESERVED.iter().map(|name| name.to_string()).collect();
+        assert_eq!(
+            unique_name(&mut names, "OrderData".to_string()),
+            "OrderData"
+        );
+        assert_eq!(
+            unique_name(&mut names, "OrderData".to_string()),
+            "OrderData2"
+        );
+        assert_eq!(unique_name(&mut names, "String".to_string()), "String2");
+
+        // "a" with the nested "b" and "a-b" both map to XDataAB
+        let schema = serde_json::json!({
+            "properties": {
+                "a": {"type": "object", "properties": {"b": {"type": "object", "properties": {"c": {"type": "string"}}}}},
+                "a-b": {"type": "object", "properties": {"d": {"type": "string"}}}
+            }
+        });
+        let mut types = vec![];
+        let object = data_object("XData", &schema, &mut types, &mut names).unwrap();
+        types.push(object);
+        let type_names: HashSet<&str> = types.iter().map(|object| object.type_name()).collect();
+        assert_eq!(types.len(), 4);
+        assert_eq!(type_names.len(), 4);
+    }
+}
@@ -1,4 +1,6 @@
+pub mod graphql;
 pub mod managers;
+pub mod rest;
 pub mod services;
 pub mod token;
 
@@ -22,7 +22,9 @@ use tower_http::{services::ServeDir, trace::TraceLayer};
 extern crate lazy_static;
 
 use opencore::api;
+use opencore::graphql;
 use opencore::managers;
+use opencore::rest;
 use opencore::services;
 use opencore::token;
 
@@ -41,6 +43,10 @@ struct Opts {
     static_dir: String,
     #[clap(long, default_value = "")]
     schema_dir: String,
+    #[clap(long, default_value = "blobs")]
+    blob_dir: String,
+    #[clap(long, default_value = "104857600")]
+    max_blob_bytes: u64,
 }
 
 lazy_static! {
@@ -52,11 +58,16 @@ struct ManagersContainer {
     pub schemas: Arc<managers::schemas::Manager>,
     pub permissions: Arc<managers::permissions::Manager>,
     pub locks: Arc<managers::locks::Manager>,
+    pub elections: Arc<managers::elections::Manager>,
     pub events: Arc<managers::events::Manager>,
+    pub hooks: Arc<managers::hooks::Manager>,
     pub resources: Arc<managers::resources::Manager>,
+    pub tasks: Arc<managers::tasks::Manager>,
+    pub schedules: Arc<managers::schedules::Manager>,
     pub users: Arc<managers::users::Manager>,
     pub service_accounts: Arc<managers::service_accounts::Manager>,
     pub groups: Arc<managers::groups::Manager>,
+    pub blobs: Arc<managers::blobs::Manager>,
 }
 
 impl ManagersContainer {
@@ -71,11 +82,13 @@ impl ManagersContainer {
         let validator = Arc::new(token::Validator::new(&OPTS.secret));
         let schemas = Arc::new(managers::schemas::Manager::new(pool.clone()).await?);
         let permissions = Arc::new(managers::permissions::Manager::new(pool.clone()).await?);
-        let locks = Arc::new(managers::locks::Manager::new(pool.clone()).await?);
+        let locks = Arc::new(managers::locks::Manager::new(pool.clone(), permissions.clone()).await?);
+        let elections = Arc::new(managers::elections::Manager::new(locks.clone()).await?);
         let events = Arc::new(
             managers::events::Manager::new(pool.clone(), permissions.clone(), &OPTS.database)
                 .await?,
         );
+        let hooks = Arc::new(managers::hooks::Manager::new(pool.clone()).await?);
         let resources = Arc::new(
             managers::resources::Manager::new(
                 pool.clone(),
@@ -83,6 +96,27 @@ impl ManagersContainer {
                 schemas.clone(),
                 events.clone(),
                 locks.clone(),
+                hooks.clone(),
+            )
+            .await?,
+        );
+        let tasks = Arc::new(
+            managers::tasks::Manager::new(
+                pool.clone(),
+                permissions.clone(),
+                schemas.clone(),
+                events.clone(),
+                resources.clone(),
+            )
+            .await?,
+        );
+        let schedules = Arc::new(
+            managers::schedules::Manager::new(
+                pool.clone(),
+                permissions.clone(),
+                resources.clone(),
+                events.clone(),
+                locks.clone(),
             )
             .await?,
         );
@@ -93,30 +127,51 @@ impl ManagersContainer {
 
         let groups = Arc::new(managers::groups::Manager::new(pool.clone()).await?);
 
+        let blob_backend = Arc::new(managers::blobs::FilesystemBackend::new(&OPTS.blob_dir).await?);
+        let blobs = Arc::new(
+            managers::blobs::Manager::new(
+                pool.clone(),
+                permissions.clone(),
+                blob_backend,
+                OPTS.max_blob_bytes,
+            )
+            .await?,
+        );
+
         Ok(Self {
             validator,
             schemas,
             permissions,
             locks,
+            elections,
             events,
+            hooks,
             resources,
+            tasks,
+            schedules,
             users,
             service_accounts,
             groups,
+            blobs,
         })
     }
 }
 
 struct ServicesContainer {
     pub schemas: services::schemas::Service,
+    pub hooks: services::hooks::Service,
     pub resources: services::resources::Service,
+    pub tasks: services::tasks::Service,
+    pub schedules: services::schedules::Service,
     pub permissions: services::permissions::Service,
     pub events: services::events::Service,
     pub locks: services::locks::Service,
+    pub elections: services::elections::Service,
     pub users: services::users::Service,
     pub service_accounts: services::service_accounts::Service,
     pub groups: services::groups::Service,
     pub auth: services::auth::Service,
+    pub blobs: services::blobs::Service,
 }
 
 impl ServicesContainer {
@@ -126,11 +181,22 @@ impl ServicesContainer {
         let schemas =
             services::schemas::Service::new(managers.schemas.clone(), managers.validator.clone())?;
 
+        let hooks =
+            services::hooks::Service::new(managers.hooks.clone(), managers.validator.clone())?;
+
         let resources = services::resources::Service::new(
             managers.resources.clone(),
             managers.validator.clone(),
         )?;
 
+        let tasks =
+            services::tasks::Service::new(managers.tasks.clone(), managers.validator.clone())?;
+
+        let schedules = services::schedules::Service::new(
+            managers.schedules.clone(),
+            managers.validator.clone(),
+        )?;
+
         let permissions = services::permissions::Service::new(
             managers.permissions.clone(),
             managers.validator.clone(),
@@ -142,6 +208,11 @@ impl ServicesContainer {
         let locks =
             services::locks::Service::new(managers.locks.clone(), managers.validator.clone())?;
 
+        let elections = services::elections::Service::new(
+            managers.elections.clone(),
+            managers.validator.clone(),
+        )?;
+
         let users =
             services::users::Service::new(managers.users.clone(), managers.validator.clone())?;
 
@@ -161,16 +232,24 @@ impl ServicesContainer {
             &OPTS.secret.clone(),
         )?;
 
+        let blobs =
+            services::blobs::Service::new(managers.blobs.clone(), managers.validator.clone())?;
+
         Ok(Self {
             schemas,
+            hooks,
             resources,
+            tasks,
+            schedules,
             permissions,
             events,
             locks,
+            elections,
             users,
             service_accounts,
             groups,
             auth,
+            blobs,
         })
     }
 }
@@ -213,6 +292,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 services.schemas,
             )),
         )
+        .add_service(
+            grpc_web_config.enable(api::catalog::hooks_server::HooksServer::new(services.hooks)),
+        )
         .add_service(
             grpc_web_config.enable(api::catalog::events_server::EventsServer::new(
                 services.events,
@@ -221,6 +303,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .add_service(
             grpc_web_config.enable(api::catalog::locks_server::LocksServer::new(services.locks)),
         )
+        .add_service(grpc_web_config.enable(
+            api::catalog::elections_server::ElectionsServer::new(services.elections),
+        ))
+        .add_service(
+            grpc_web_config.enable(api::catalog::tasks_server::TasksServer::new(services.tasks)),
+        )
+        .add_service(grpc_web_config.enable(
+            api::catalog::schedules_server::SchedulesServer::new(services.schedules),
+        ))
+        .add_service(
+            grpc_web_config.enable(api::catalog::blobs_server::BlobsServer::new(services.blobs)),
+        )
         .add_service(grpc_web_config.enable(
             api::catalog::permissions_server::PermissionsServer::new(services.permissions),
         ))
@@ -246,9 +340,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             Response::from_parts(parts, axum::body::boxed(body))
         });
 
+    let rest_state = Arc::new(rest::State {
+        resources: managers.resources.clone(),
+        schemas: managers.schemas.clone(),
+        validator: managers.validator.clone(),
+    });
+
+    let graphql_state = Arc::new(graphql::State::new(
+        managers.resources.clone(),
+        managers.schemas.clone(),
+        managers.events.clone(),
+        managers.validator.clone(),
+    ));
+
     let axum_service = Router::new()
-        .nest(
-            "/",
+        .merge(rest::router(rest_state))
+        .merge(graphql::router(graphql_state))
+        // static files are served as fallback, nesting them at "/" would conflict with the REST routes
+        .fallback(
             get_service(ServeDir::new(&OPTS.static_dir)).handle_error(
                 |error: std::io::Error| async move {
                     (
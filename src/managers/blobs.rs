@@ -0,0 +1,547 @@
+use super::errors::Error;
+
+use futures::{Stream, TryStreamExt};
+use sha2::{Digest, Sha256};
+use sqlx::types::Uuid;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tonic::Status;
+use tracing::Instrument;
+
+use crate::api;
+use crate::managers;
+use crate::token::Claims;
+
+use api::catalog::Blob;
+
+/// Content of a blob as a stream of chunks.
+pub type ChunkStream = Pin<Box<dyn Stream<Item = Result<Vec<u8>, Error>> + Send>>;
+
+/// Stores the content of blobs. The metadata is kept in the database, a backend
+/// only maps keys to bytes.
+#[tonic::async_trait]
+pub trait Backend: std::fmt::Debug + Send + Sync {
+    /// Stores the chunks under key, replacing previous content.
+    async fn put(&self, key: &str, chunks: ChunkStream) -> Result<(), Error>;
+    async fn get(&self, key: &str) -> Result<ChunkStream, Error>;
+    /// Removes the content of key, missing keys are ignored.
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+}
+
+// Size of the chunks read from the filesystem.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Stores every blob as a file in a directory.
+#[derive(Debug)]
+pub struct FilesystemBackend {
+    dir: PathBuf,
+}
+
+impl FilesystemBackend {
+    pub async fn new(dir: &str) -> Result<FilesystemBackend, Error> {
+        tokio::fs::create_dir_all(dir).await?;
+        Ok(FilesystemBackend { dir: dir.into() })
+    }
+}
+
+#[tonic::async_trait]
+impl Backend for FilesystemBackend {
+    async fn put(&self, key: &str, mut chunks: ChunkStream) -> Result<(), Error> {
+        let path = self.dir.join(key);
+        let mut file = tokio::fs::File::create(&path).await?;
+        let res = async {
+            while let Some(chunk) = chunks.try_next().await? {
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            Ok(())
+        }
+        .await;
+        if res.is_err() {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+        res
+    }
+
+    async fn get(&self, key: &str) -> Result<ChunkStream, Error> {
+        let mut file = match tokio::fs::File::open(self.dir.join(key)).await {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+            Err(err) => return Err(err.into()),
+        };
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let res = match file.read(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        chunk.truncate(n);
+                        Ok(chunk)
+                    }
+                    Err(err) => Err(err.into()),
+                };
+                let failed = res.is_err();
+                if tx.send(res).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.dir.join(key)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct BlobRow {
+    blob_id: Uuid,
+    resource_id: Uuid,
+    name: String,
+    mime_type: String,
+    size: i64,
+    sha256: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<BlobRow> for Blob {
+    fn from(r: BlobRow) -> Blob {
+        Blob {
+            id: r.blob_id.to_hyphenated().to_string(),
+            resource_id: r.resource_id.to_hyphenated().to_string(),
+            name: r.name,
+            mime_type: r.mime_type,
+            size: r.size as u64,
+            sha256: r.sha256,
+            created_at: Some(prost_types::Timestamp {
+                seconds: r.created_at.timestamp(),
+                nanos: 0,
+            }),
+        }
+    }
+}
+
+// Interval in which the blobs of purged resources are removed.
+const CLEANUP_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug)]
+pub struct Manager {
+    pool: Arc<sqlx::PgPool>,
+    permissions: Arc<managers::permissions::Manager>,
+    backend: Arc<dyn Backend>,
+    /// uploads are aborted once they exceed this size in bytes
+    max_size: u64,
+}
+
+impl Manager {
+    pub async fn new(
+        pool: Arc<sqlx::PgPool>,
+        permissions: Arc<managers::permissions::Manager>,
+        backend: Arc<dyn Backend>,
+        max_size: u64,
+    ) -> Result<Manager, Error> {
+        let res = Manager {
+            pool,
+            permissions,
+            backend,
+            max_size,
+        };
+        res.init_tables().await?;
+
+        let pool = res.pool.clone();
+        let backend = res.backend.clone();
+        tokio::spawn(
+            async move {
+                use tokio::time::{sleep, Duration};
+                loop {
+                    sleep(Duration::from_secs(CLEANUP_INTERVAL_SECS)).await;
+                    match remove_orphans(&pool, backend.deref()).await {
+                        Ok(0) => (),
+                        Ok(count) => log::info!("removed {} blobs of purged resources", count),
+                        Err(err) => log::error!("failed to remove blobs: {}", err),
+                    }
+                }
+            }
+            .instrument(tracing::debug_span!("remove_orphaned_blobs")),
+        );
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(name = "mgr::blobs::init_tables", skip(self))]
+    async fn init_tables(&self) -> Result<(), Error> {
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS
+            blobs(
+                blob_id UUID PRIMARY KEY,
+                resource_id UUID NOT NULL,
+                name TEXT NOT NULL,
+                mime_type TEXT NOT NULL,
+                size INT8 NOT NULL,
+                sha256 TEXT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                UNIQUE(resource_id, name)
+            );"#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Stores the chunks as blob of the resource. An existing blob with the same
+    /// name is replaced. The mime type is guessed from the name if it is empty.
+    /// Uploads larger than the maximum size fail without storing anything.
+    #[tracing::instrument(name = "mgr::blobs::upload", skip(self, chunks))]
+    pub async fn upload(
+        &self,
+        claims: &Claims,
+        resource_id: &Uuid,
+        name: &str,
+        mime_type: &str,
+        chunks: ChunkStream,
+    ) -> Result<Blob, Error> {
+        if name.is_empty() {
+            return Err(Error::InvalidArgument("'name' must be specified".into()));
+        }
+        self.permissions.check(resource_id, "write", claims).await?;
+        let exists: (bool,) = sqlx::query_as(
+            "SELECT EXISTS (SELECT 1 FROM resources WHERE resource_id = $1 AND deleted_at IS NULL)",
+        )
+        .bind(resource_id)
+        .fetch_one(self.pool.deref())
+        .await?;
+        if !exists.0 {
+            return Err(Error::NotFound);
+        }
+
+        let mime_type = match mime_type.is_empty() {
+            true => mime_guess::from_path(name)
+                .first_or_octet_stream()
+                .to_string(),
+            false => mime_type.to_string(),
+        };
+
+        // the content is hashed and measured while it is written, the backend
+        // discards what it has written when the stream fails
+        let digest = Arc::new(Mutex::new((Sha256::new(), 0usize)));
+        let chunks = {
+            let digest = digest.clone();
+            let max_size = self.max_size;
+            chunks.and_then(move |chunk| {
+                let mut digest = digest.lock().unwrap();
+                digest.0.update(&chunk);
+                digest.1 += chunk.len();
+                futures::future::ready(match digest.1 as u64 > max_size {
+                    true => Err(Error::ResourceExhausted(format!(
+                        "blobs must not be larger than {} bytes",
+                        max_size
+                    ))),
+                    false => Ok(chunk),
+                })
+            })
+        };
+
+        let blob_id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+        let key = blob_id.to_hyphenated().to_string();
+        self.backend.put(&key, Box::pin(chunks)).await?;
+
+        let (sha256, size) = {
+            let mut digest = digest.lock().unwrap();
+            let hasher = std::mem::take(&mut digest.0);
+            (hex::encode(hasher.finalize()), digest.1)
+        };
+
+        let res = self
+            .insert(&blob_id, resource_id, name, &mime_type, size, &sha256)
+            .await;
+        match res {
+            Ok((blob, replaced)) => {
+                if let Some(replaced) = replaced {
+                    self.backend
+                        .delete(&replaced.to_hyphenated().to_string())
+                        .await?;
+                }
+                Ok(blob)
+            }
+            Err(err) => {
+                self.backend.delete(&key).await?;
+                Err(err)
+            }
+        }
+    }
+
+    // Records the metadata of an uploaded blob, returns the id of the blob it
+    // replaces.
+    async fn insert(
+        &self,
+        blob_id: &Uuid,
+        resource_id: &Uuid,
+        name: &str,
+        mime_type: &str,
+        size: usize,
+        sha256: &str,
+    ) -> Result<(Blob, Option<Uuid>), Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let replaced: Option<(Uuid,)> = sqlx::query_as(
+            "DELETE FROM blobs WHERE resource_id = $1 AND name = $2 RETURNING blob_id",
+        )
+        .bind(resource_id)
+        .bind(name)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let row: BlobRow = sqlx::query_as(
+            r#"INSERT INTO blobs(blob_id, resource_id, name, mime_type, size, sha256)
+            VALUES($1, $2, $3, $4, $5, $6)
+            RETURNING *"#,
+        )
+        .bind(blob_id)
+        .bind(resource_id)
+        .bind(name)
+        .bind(mime_type)
+        .bind(size as i64)
+        .bind(sha256)
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((row.into(), replaced.map(|(id,)| id)))
+    }
+
+    #[tracing::instrument(name = "mgr::blobs::get", skip(self))]
+    pub async fn get(&self, claims: &Claims, id: &Uuid) -> Result<Blob, Error> {
+        let row: BlobRow = sqlx::query_as("SELECT * FROM blobs WHERE blob_id = $1")
+            .bind(id)
+            .fetch_one(self.pool.deref())
+            .await?;
+        self.permissions
+            .check(&row.resource_id, "read", claims)
+            .await?;
+        Ok(row.into())
+    }
+
+    /// Returns the metadata of the blob together with its content.
+    #[tracing::instrument(name = "mgr::blobs::download", skip(self))]
+    pub async fn download(&self, claims: &Claims, id: &Uuid) -> Result<(Blob, ChunkStream), Error> {
+        let blob = self.get(claims, id).await?;
+        let chunks = self.backend.get(&blob.id).await?;
+        Ok((blob, chunks))
+    }
+
+    #[tracing::instrument(name = "mgr::blobs::delete", skip(self))]
+    pub async fn delete(&self, claims: &Claims, id: &Uuid) -> Result<Blob, Error> {
+        let blob = self.get(claims, id).await?;
+        self.permissions
+            .check(&Uuid::parse_str(&blob.resource_id)?, "write", claims)
+            .await?;
+
+        sqlx::query("DELETE FROM blobs WHERE blob_id = $1")
+            .bind(id)
+            .execute(self.pool.deref())
+            .await?;
+        self.backend.delete(&blob.id).await?;
+
+        Ok(blob)
+    }
+
+    #[tracing::instrument(name = "mgr::blobs::list", skip(self))]
+    pub async fn list(
+        &self,
+        claims: &Claims,
+        resource_id: &Uuid,
+    ) -> Result<Pin<Box<impl Stream<Item = Result<Blob, Status>> + Send + Sync + 'static>>, Status>
+    {
+        self.permissions.check(resource_id, "read", claims).await?;
+
+        let (tx, rx) = mpsc::channel(4);
+        let pool = self.pool.clone();
+        let resource_id = *resource_id;
+        tokio::spawn(async move {
+            let mut rows =
+                sqlx::query_as("SELECT * FROM blobs WHERE resource_id = $1 ORDER BY name")
+                    .bind(resource_id)
+                    .fetch(pool.deref());
+            loop {
+                let row: BlobRow = match rows.try_next().await {
+                    Ok(row) => match row {
+                        Some(row) => row,
+                        None => {
+                            break;
+                        }
+                    },
+                    Err(err) => {
+                        log::error!("failed to read blob row: {}", err);
+                        break;
+                    }
+                };
+                match tx.send(Ok(row.into())).await {
+                    Ok(_) => (),
+                    Err(err) => {
+                        log::error!("failed to send blob: {}", err);
+                        break;
+                    }
+                };
+            }
+        });
+
+        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+}
+
+// Removes the blobs of resources which don't exist anymore. Deleted resources
+// keep their blobs until they are purged, so restoring them brings them back.
+async fn remove_orphans(pool: &sqlx::PgPool, backend: &dyn Backend) -> Result<usize, Error> {
+    let rows: Vec<(Uuid,)> = sqlx::query_as(
+        r#"SELECT blob_id FROM blobs
+            WHERE NOT EXISTS (SELECT 1 FROM resources WHERE resources.resource_id = blobs.resource_id)"#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let count = rows.len();
+    for (blob_id,) in rows {
+        backend.delete(&blob_id.to_hyphenated().to_string()).await?;
+        sqlx::query("DELETE FROM blobs WHERE blob_id = $1")
+            .bind(blob_id)
+            .execute(pool)
+            .await?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(data: &[&[u8]]) -> ChunkStream {
+        let chunks: Vec<Result<Vec<u8>, Error>> = data.iter().map(|c| Ok(c.to_vec())).collect();
+        Box::pin(futures::stream::iter(chunks))
+    }
+
+    async fn read(backend: &dyn Backend, key: &str) -> Result<Vec<u8>, Error> {
+        let chunks: Vec<Vec<u8>> = backend.get(key).await?.try_collect().await?;
+        Ok(chunks.concat())
+    }
+
+    async fn temp_backend() -> (FilesystemBackend, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("opencore-blobs-{}", uuid::Uuid::new_v4()));
+        let backend = FilesystemBackend::new(dir.to_str().unwrap()).await.unwrap();
+        (backend, dir)
+    }
+
+    #[tokio::test]
+    async fn filesystem_backend_round_trip() {
+        let (backend, dir) = temp_backend().await;
+        let large = vec![7u8; CHUNK_SIZE * 2 + 1];
+
+        backend
+            .put("a", chunks(&[b"hello ", b"world"]))
+            .await
+            .unwrap();
+        backend.put("b", chunks(&[&large])).await.unwrap();
+        assert_eq!(read(&backend, "a").await.unwrap(), b"hello world");
+        assert_eq!(read(&backend, "b").await.unwrap(), large);
+
+        backend.put("a", chunks(&[b"replaced"])).await.unwrap();
+        assert_eq!(read(&backend, "a").await.unwrap(), b"replaced");
+
+        backend.delete("a").await.unwrap();
+        backend.delete("a").await.unwrap();
+        assert!(matches!(read(&backend, "a").await, Err(Error::NotFound)));
+        assert_eq!(read(&backend, "b").await.unwrap(), large);
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn filesystem_backend_discards_failed_uploads() {
+        let (backend, dir) = temp_backend().await;
+        let failing: ChunkStream = Box::pin(futures::stream::iter(vec![
+            Ok(b"partial".to_vec()),
+            Err(Error::ResourceExhausted("too large".into())),
+        ]));
+
+        assert!(matches!(
+            backend.put("a", failing).await,
+            Err(Error::ResourceExhausted(_))
+        ));
+        assert!(matches!(read(&backend, "a").await, Err(Error::NotFound)));
+
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    // Runs against the database in DATABASE_URL, the tables are created in a
+    // schema of their own which is dropped afterwards.
+    #[tokio::test]
+    #[ignore]
+    async fn remove_orphans_removes_the_blobs_of_purged_resources() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        // a single connection keeps the search_path for all queries
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .connect(&url)
+            .await
+            .unwrap();
+        let schema = format!("test_blobs_{}", uuid::Uuid::new_v4().simple());
+        for query in [
+            format!("CREATE SCHEMA {}", schema),
+            format!("SET search_path TO {}", schema),
+            "CREATE TABLE resources(resource_id UUID)".to_string(),
+            "CREATE TABLE blobs(blob_id UUID, resource_id UUID)".to_string(),
+        ] {
+            sqlx::query(&query).execute(&pool).await.unwrap();
+        }
+
+        let (backend, dir) = temp_backend().await;
+        let (resource, purged) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let (kept, orphan) = (Uuid::from_u128(3), Uuid::from_u128(4));
+        sqlx::query("INSERT INTO resources VALUES($1)")
+            .bind(resource)
+            .execute(&pool)
+            .await
+            .unwrap();
+        for (blob_id, resource_id) in [(kept, resource), (orphan, purged)] {
+            sqlx::query("INSERT INTO blobs VALUES($1, $2)")
+                .bind(blob_id)
+                .bind(resource_id)
+                .execute(&pool)
+                .await
+                .unwrap();
+            let key = blob_id.to_hyphenated().to_string();
+            backend.put(&key, chunks(&[b"content"])).await.unwrap();
+        }
+
+        assert_eq!(remove_orphans(&pool, &backend).await.unwrap(), 1);
+
+        let blobs: Vec<(Uuid,)> = sqlx::query_as("SELECT blob_id FROM blobs")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(blobs, vec![(kept,)]);
+        let kept = kept.to_hyphenated().to_string();
+        let orphan = orphan.to_hyphenated().to_string();
+        assert_eq!(read(&backend, &kept).await.unwrap(), b"content");
+        assert!(matches!(
+            read(&backend, &orphan).await,
+            Err(Error::NotFound)
+        ));
+
+        sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema))
+            .execute(&pool)
+            .await
+            .unwrap();
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
@@ -0,0 +1,254 @@
+use super::errors::Error;
+use crate::api;
+use crate::managers;
+use crate::token::Claims;
+
+use futures::{Stream, StreamExt};
+use managers::locks::LockOptions;
+use sqlx::types::Uuid;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tonic::Status;
+use tracing::Instrument;
+
+use api::catalog::{CampaignResponse, CampaignStatus, Leader, LockInfo, LockMode};
+
+/// Namespace of the locks backing elections.
+pub const NAMESPACE: &str = "elections";
+
+// Interval in which Observe checks for a new leader.
+const OBSERVE_INTERVAL_MS: u64 = 500;
+
+#[derive(Debug)]
+pub struct Manager {
+    locks: Arc<managers::locks::Manager>,
+}
+
+impl Manager {
+    pub async fn new(locks: Arc<managers::locks::Manager>) -> Result<Manager, Error> {
+        Ok(Manager { locks })
+    }
+
+    /// Waits for the lock of the election and streams the status of the
+    /// candidate. The lock is held, i.e. the candidate leads, until the returned
+    /// stream is dropped. An election is bound to the resource passed by its
+    /// first candidate, campaigning then requires write permission on it.
+    /// Elections without resource are reserved for admins.
+    #[tracing::instrument(name = "mgr::elections::campaign", skip(self))]
+    pub async fn campaign(
+        &self,
+        claims: &Claims,
+        name: &str,
+        candidate: &str,
+        resource_id: Option<Uuid>,
+    ) -> Result<
+        Pin<Box<impl Stream<Item = Result<CampaignResponse, Status>> + Send + Sync + 'static>>,
+        Status,
+    > {
+        if name.is_empty() {
+            return Err(Status::invalid_argument("'name' must be specified"));
+        }
+        if resource_id.is_none() && !claims.adm {
+            return Err(Error::Forbidden.into());
+        }
+
+        let (tx, rx) = mpsc::channel(4);
+        let locks = self.locks.clone();
+        let claims = claims.clone();
+        let name = name.to_string();
+        let candidate = match candidate {
+            "" => claims.sub.to_string(),
+            candidate => candidate.to_string(),
+        };
+
+        tokio::spawn(
+            async move {
+                let opts = LockOptions {
+                    claims: &claims,
+                    holder_name: &candidate,
+                    namespace: NAMESPACE,
+                    lock_id: &name,
+                    resource_id,
+                    mode: LockMode::Exclusive,
+                    limit: 0,
+                    acquire_timeout_ms: 0,
+                };
+                let campaigning = CampaignResponse {
+                    status: CampaignStatus::Campaigning as i32,
+                    leader: None,
+                };
+                if tx.send(Ok(campaigning)).await.is_err() {
+                    return;
+                }
+
+                // dropping the lock future leaves the queue of the lock
+                let mut lock = tokio::select! {
+                    _ = tx.closed() => return,
+                    res = locks.lock(&opts) => match res {
+                        Ok(lock) => lock,
+                        Err(err) => {
+                            let _ = tx.send(Err(err)).await;
+                            return;
+                        }
+                    },
+                };
+
+                let term = match lock.next().await {
+                    Some(Ok(res)) => res.fencing_token,
+                    Some(Err(err)) => {
+                        let _ = tx.send(Err(err)).await;
+                        return;
+                    }
+                    None => return,
+                };
+                // the acquisition of the lease, so Observe reports the same time
+                let elected_at = match locks.get(&Claims::admin(), NAMESPACE, &name).await {
+                    Ok(info) => info
+                        .holders
+                        .into_iter()
+                        .find(|holder| holder.fencing_token == term)
+                        .and_then(|holder| holder.acquired_at),
+                    Err(err) => {
+                        let _ = tx.send(Err(err.into())).await;
+                        return;
+                    }
+                };
+                let leader = Leader {
+                    name: name.to_string(),
+                    holder_id: claims.sub.to_string(),
+                    candidate: candidate.to_string(),
+                    term,
+                    elected_at,
+                };
+                log::info!("elected as leader of {} in term {}", name, term);
+                let elected = CampaignResponse {
+                    status: CampaignStatus::Elected as i32,
+                    leader: Some(leader.clone()),
+                };
+                if tx.send(Ok(elected)).await.is_err() {
+                    return;
+                }
+
+                // dropping the lock stream releases the lock
+                loop {
+                    tokio::select! {
+                        _ = tx.closed() => return,
+                        res = lock.next() => match res {
+                            Some(Ok(_)) => continue,
+                            _ => break,
+                        },
+                    };
+                }
+
+                log::info!("lost leadership of {} in term {}", name, term);
+                let lost = CampaignResponse {
+                    status: CampaignStatus::Lost as i32,
+                    leader: Some(leader),
+                };
+                let _ = tx.send(Ok(lost)).await;
+            }
+            .instrument(tracing::debug_span!("campaign")),
+        );
+
+        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+
+    /// Streams the current leader of the election and every change of
+    /// leadership. A leader without holder means the election has no leader.
+    #[tracing::instrument(name = "mgr::elections::observe", skip(self))]
+    pub async fn observe(
+        &self,
+        name: &str,
+    ) -> Result<Pin<Box<impl Stream<Item = Result<Leader, Status>> + Send + Sync + 'static>>, Status>
+    {
+        if name.is_empty() {
+            return Err(Status::invalid_argument("'name' must be specified"));
+        }
+
+        let (tx, rx) = mpsc::channel(4);
+        let locks = self.locks.clone();
+        let name = name.to_string();
+
+        tokio::spawn(
+            async move {
+                use tokio::time::{sleep, Duration};
+                let mut last: Option<Leader> = None;
+                loop {
+                    let leader = match leader(&locks, &name).await {
+                        Ok(leader) => leader,
+                        Err(err) => {
+                            log::error!("failed to get leader: {}", err);
+                            let _ = tx.send(Err(err.into())).await;
+                            return;
+                        }
+                    };
+                    let changed = match &last {
+                        Some(last) => {
+                            last.holder_id != leader.holder_id || last.term != leader.term
+                        }
+                        None => true,
+                    };
+                    if changed {
+                        if tx.send(Ok(leader.clone())).await.is_err() {
+                            return;
+                        }
+                        last = Some(leader);
+                    }
+
+                    tokio::select! {
+                        _ = tx.closed() => return,
+                        _ = sleep(Duration::from_millis(OBSERVE_INTERVAL_MS)) => (),
+                    };
+                }
+            }
+            .instrument(tracing::debug_span!("observe")),
+        );
+
+        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+
+    /// Ends the term of the leader, fails if the term is not the current one or
+    /// the claims belong to neither the leader nor an admin.
+    #[tracing::instrument(name = "mgr::elections::resign", skip(self))]
+    pub async fn resign(&self, claims: &Claims, name: &str, term: i64) -> Result<Leader, Error> {
+        let leader = leader(&self.locks, name).await?;
+        if leader.term != term || leader.holder_id.is_empty() {
+            return Err(Error::FailedPrecondition(format!(
+                "{} is not the current term of election {}",
+                term, name
+            )));
+        }
+        if leader.holder_id != claims.sub && !claims.adm {
+            return Err(Error::Forbidden);
+        }
+
+        self.locks.release(claims, NAMESPACE, name, term).await?;
+        log::info!("resigned leadership of {} in term {}", name, term);
+
+        Ok(leader)
+    }
+}
+
+// Returns the holder of the lock of the election as leader.
+async fn leader(locks: &managers::locks::Manager, name: &str) -> Result<Leader, Error> {
+    let info = match locks.get(&Claims::admin(), NAMESPACE, name).await {
+        Ok(info) => info,
+        Err(Error::NotFound) => LockInfo::default(),
+        Err(err) => return Err(err),
+    };
+
+    Ok(match info.holders.into_iter().next() {
+        Some(holder) => Leader {
+            name: name.to_string(),
+            holder_id: holder.holder_id,
+            candidate: holder.holder_name,
+            term: holder.fencing_token,
+            elected_at: holder.acquired_at,
+        },
+        None => Leader {
+            name: name.to_string(),
+            ..Leader::default()
+        },
+    })
+}
@@ -6,6 +6,9 @@ pub enum Error {
     InvalidArgument(String),
     Send(String),
     InvalidFencingToken,
+    Rejected(String),
+    FailedPrecondition(String),
+    ResourceExhausted(String),
     NotFound,
     Forbidden,
 }
@@ -21,6 +24,9 @@ impl std::fmt::Display for Error {
             Error::NotFound => write!(f, "not found"),
             Error::Forbidden => write!(f, "forbidden"),
             Error::InvalidFencingToken => write!(f, "invalid fencing token"),
+            Error::Rejected(cause) => write!(f, "rejected by admission hook: {}", cause),
+            Error::FailedPrecondition(cause) => write!(f, "precondition failed: {}", cause),
+            Error::ResourceExhausted(cause) => write!(f, "resource exhausted: {}", cause),
         }
     }
 }
@@ -90,3 +96,15 @@ impl From<crate::token::error::Error> for Error {
         Error::InvalidArgument(format!("failed to handle claim data: {}", item))
     }
 }
+
+impl From<json_patch::PatchError> for Error {
+    fn from(item: json_patch::PatchError) -> Self {
+        error!("{}", item);
+        match item {
+            json_patch::PatchError::TestFailed => {
+                Error::FailedPrecondition(format!("failed to apply json patch: {}", item))
+            }
+            _ => Error::InvalidArgument(format!("failed to apply json patch: {}", item)),
+        }
+    }
+}
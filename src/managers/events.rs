@@ -64,6 +64,7 @@ pub enum SubscribeFilter {
     Type(catalog::EventType),
     Kind(String),
     Resource(String),
+    ExcludeStatus,
 }
 
 impl std::fmt::Debug for Manager {
@@ -178,62 +179,67 @@ impl Manager {
         if !claims.adm {
             return Err(Error::Forbidden);
         }
+        self.insert(
+            resource_id,
+            resource_kind,
+            resource_labels,
+            event_type,
+            data,
+        )
+        .await
+    }
 
-        let event_id = uuid::Uuid::new_v4();
-        let event_id = Uuid::from_bytes(event_id.into_bytes());
-        
-        let now = chrono::Utc::now();
+    /// Like publish, but the event is only published if the transaction commits.
+    #[tracing::instrument(name = "mgr::events::publish_with_tx", skip(self, tx))]
+    #[allow(clippy::too_many_arguments)]
+    pub async fn publish_with_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        claims: &Claims,
+        resource_id: &Uuid,
+        resource_kind: &str,
+        resource_labels: &HashMap<String, String>,
+        event_type: catalog::EventType,
+        data: &serde_json::Value,
+    ) -> Result<Event, Error> {
+        if !claims.adm {
+            return Err(Error::Forbidden);
+        }
+        insert_with_tx(
+            tx,
+            resource_id,
+            resource_kind,
+            resource_labels,
+            event_type,
+            data,
+        )
+        .await
+    }
 
+    async fn insert(
+        &self,
+        resource_id: &Uuid,
+        resource_kind: &str,
+        resource_labels: &HashMap<String, String>,
+        event_type: catalog::EventType,
+        data: &serde_json::Value,
+    ) -> Result<Event, Error> {
         let mut tx = self.pool.begin().await?;
-
-        let row: EventRow = sqlx::query_as(
-            r#"INSERT INTO events(
-            id,
+        let event = insert_with_tx(
+            &mut tx,
             resource_id,
             resource_kind,
+            resource_labels,
             event_type,
             data,
-            labels,
-            created_at) VALUES($1, $2, $3, $4, $5, $6, $7)
-            RETURNING events.*"#,
         )
-        .bind(&event_id)
-        .bind(resource_id)
-        .bind(resource_kind)
-        .bind(event_type as i32)
-        .bind(&data)
-        .bind(serde_json::to_value(resource_labels)?)
-        .bind(now)
-        .fetch_one(&mut tx)
         .await?;
-
-        let mut serializable_row = SerializableEventRow::from(row);
-        let mut payload = serde_json::to_string(&serializable_row)?;
-        if payload.len() > 8000 {
-            // This will be rejected by postgres otherwise, so we trucate it by setting the data part to null.
-            // If a client sees this null, it indicates that they should fetch the resource to get the data.
-            serializable_row.data = serde_json::Value::Null;
-            payload = serde_json::to_string(&serializable_row)?;
-        }
-        sqlx::query(r#"SELECT pg_notify($1, $2)"#)
-            .bind("event")
-            .bind(payload)
-            .execute(&mut tx)
-            .await?;
         tx.commit().await?;
-
-        Ok(Event {
-            id: event_id.to_hyphenated().to_string(),
-            resource_id: resource_id.to_hyphenated().to_string(),
-            resource_kind: resource_kind.to_string(),
-            resource_labels: resource_labels.clone(),
-            event_type: event_type as i32,
-            data: serde_json::to_string(data)?,
-            created_at: Some(prost_types::Timestamp {
-                seconds: now.timestamp(),
-                nanos: 0,
-            }),
-        })
+        Ok(event)
+    }
+    /// Returns a receiver of all events, without filters or permission checks.
+    pub fn receiver(&self) -> tokio::sync::broadcast::Receiver<Event> {
+        self.sender.subscribe()
     }
 
     #[tracing::instrument(name = "mgr::events::subscribe", skip(self))]
@@ -308,6 +314,11 @@ impl Manager {
                                     continue 'mainloop;
                                 }
                             }
+                            SubscribeFilter::ExcludeStatus => {
+                                if event.event_type == catalog::EventType::StatusUpdate as i32 {
+                                    continue 'mainloop;
+                                }
+                            }
                         }
                     }
 
@@ -328,3 +339,66 @@ impl Manager {
         Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
     }
 }
+
+// Stores the event and notifies the listeners once the transaction commits.
+async fn insert_with_tx(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    resource_id: &Uuid,
+    resource_kind: &str,
+    resource_labels: &HashMap<String, String>,
+    event_type: catalog::EventType,
+    data: &serde_json::Value,
+) -> Result<Event, Error> {
+    let event_id = uuid::Uuid::new_v4();
+    let event_id = Uuid::from_bytes(event_id.into_bytes());
+
+    let now = chrono::Utc::now();
+
+    let row: EventRow = sqlx::query_as(
+        r#"INSERT INTO events(
+        id,
+        resource_id,
+        resource_kind,
+        event_type,
+        data,
+        labels,
+        created_at) VALUES($1, $2, $3, $4, $5, $6, $7)
+        RETURNING events.*"#,
+    )
+    .bind(&event_id)
+    .bind(resource_id)
+    .bind(resource_kind)
+    .bind(event_type as i32)
+    .bind(&data)
+    .bind(serde_json::to_value(resource_labels)?)
+    .bind(now)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut serializable_row = SerializableEventRow::from(row);
+    let mut payload = serde_json::to_string(&serializable_row)?;
+    if payload.len() > 8000 {
+        // This will be rejected by postgres otherwise, so we trucate it by setting the data part to null.
+        // If a client sees this null, it indicates that they should fetch the resource to get the data.
+        serializable_row.data = serde_json::Value::Null;
+        payload = serde_json::to_string(&serializable_row)?;
+    }
+    sqlx::query(r#"SELECT pg_notify($1, $2)"#)
+        .bind("event")
+        .bind(payload)
+        .execute(&mut *tx)
+        .await?;
+
+    Ok(Event {
+        id: event_id.to_hyphenated().to_string(),
+        resource_id: resource_id.to_hyphenated().to_string(),
+        resource_kind: resource_kind.to_string(),
+        resource_labels: resource_labels.clone(),
+        event_type: event_type as i32,
+        data: serde_json::to_string(data)?,
+        created_at: Some(prost_types::Timestamp {
+            seconds: now.timestamp(),
+            nanos: 0,
+        }),
+    })
+}
@@ -0,0 +1,416 @@
+use super::errors::Error;
+
+use futures::{Stream, TryStreamExt};
+use log::info;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tonic::Status;
+
+use crate::api;
+use crate::token::Claims;
+
+use api::catalog::{
+    AdmissionReviewRequest, EventType, Hook, HookFailurePolicy, HookProtocol, HookType,
+};
+
+const DEFAULT_TIMEOUT_MS: u32 = 10_000;
+
+#[derive(sqlx::FromRow)]
+struct HookRow {
+    id: Uuid,
+    kind: String,
+    hook_type: i32,
+    protocol: i32,
+    url: String,
+    timeout_ms: i64,
+    failure_policy: i32,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<HookRow> for Hook {
+    fn from(r: HookRow) -> Hook {
+        Hook {
+            id: r.id.to_hyphenated().to_string(),
+            kind: r.kind,
+            hook_type: r.hook_type,
+            protocol: r.protocol,
+            url: r.url,
+            timeout_ms: r.timeout_ms as u32,
+            failure_policy: r.failure_policy,
+            created_at: Some(prost_types::Timestamp {
+                seconds: r.created_at.timestamp(),
+                nanos: 0,
+            }),
+            updated_at: Some(prost_types::Timestamp {
+                seconds: r.updated_at.timestamp(),
+                nanos: 0,
+            }),
+        }
+    }
+}
+
+/// JSON body exchanged with HTTP hooks, mirrors AdmissionReviewRequest.
+#[derive(serde::Serialize, Debug)]
+struct HttpReviewRequest<'a> {
+    operation: String,
+    resource_id: String,
+    kind: &'a str,
+    data: &'a serde_json::Value,
+    old_data: Option<&'a serde_json::Value>,
+    labels: &'a HashMap<String, String>,
+    subject: &'a str,
+}
+
+/// Answer of a hook. HTTP hooks return it as JSON body, mirrors AdmissionReviewResponse.
+#[derive(serde::Deserialize, Debug)]
+struct ReviewResponse {
+    allowed: bool,
+    #[serde(default)]
+    reason: String,
+    #[serde(default)]
+    data: Option<serde_json::Value>,
+}
+
+/// The write which is subject to admission.
+#[derive(Debug)]
+pub struct Review<'a> {
+    pub operation: EventType,
+    pub resource_id: &'a Uuid,
+    pub kind: &'a str,
+    pub old_data: Option<&'a serde_json::Value>,
+    pub labels: &'a HashMap<String, String>,
+    pub claims: &'a Claims,
+}
+
+#[derive(Debug)]
+pub struct Manager {
+    pool: Arc<sqlx::PgPool>,
+    client: reqwest::Client,
+}
+
+impl Manager {
+    pub async fn new(pool: Arc<sqlx::PgPool>) -> Result<Manager, Error> {
+        let res = Manager {
+            pool,
+            client: reqwest::Client::new(),
+        };
+        res.init_tables().await?;
+        Ok(res)
+    }
+
+    #[tracing::instrument(name = "mgr::hooks::init_tables", skip(self))]
+    async fn init_tables(&self) -> Result<(), Error> {
+        sqlx::query(
+            r#"CREATE TABLE IF NOT EXISTS
+            hooks(
+                id UUID PRIMARY KEY,
+                kind TEXT NOT NULL,
+                hook_type INT4 NOT NULL,
+                protocol INT4 NOT NULL,
+                url TEXT NOT NULL,
+                timeout_ms INT8 NOT NULL,
+                failure_policy INT4 NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            );"#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS hooks_kind_idx ON hooks(kind);")
+            .execute(self.pool.deref())
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "mgr::hooks::create", skip(self))]
+    pub async fn create(
+        &self,
+        kind: &str,
+        hook_type: HookType,
+        protocol: HookProtocol,
+        url: &str,
+        timeout_ms: u32,
+        failure_policy: HookFailurePolicy,
+    ) -> Result<Hook, Error> {
+        if kind.is_empty() {
+            return Err(Error::InvalidArgument("'kind' must be specified".into()));
+        }
+        if url.is_empty() {
+            return Err(Error::InvalidArgument("'url' must be specified".into()));
+        }
+
+        let id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+        let timeout_ms = match timeout_ms {
+            0 => DEFAULT_TIMEOUT_MS,
+            val => val,
+        };
+
+        let row: HookRow = sqlx::query_as(
+            r#"INSERT INTO hooks(id, kind, hook_type, protocol, url, timeout_ms, failure_policy)
+            VALUES($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *"#,
+        )
+        .bind(id)
+        .bind(kind)
+        .bind(hook_type as i32)
+        .bind(protocol as i32)
+        .bind(url)
+        .bind(timeout_ms as i64)
+        .bind(failure_policy as i32)
+        .fetch_one(self.pool.deref())
+        .await?;
+
+        Ok(row.into())
+    }
+
+    #[tracing::instrument(name = "mgr::hooks::get", skip(self))]
+    pub async fn get(&self, id: &Uuid) -> Result<Hook, Error> {
+        let row: HookRow = sqlx::query_as("SELECT * FROM hooks WHERE id = $1")
+            .bind(id)
+            .fetch_one(self.pool.deref())
+            .await?;
+        Ok(row.into())
+    }
+
+    #[tracing::instrument(name = "mgr::hooks::delete", skip(self))]
+    pub async fn delete(&self, id: &Uuid) -> Result<Hook, Error> {
+        let row: HookRow = sqlx::query_as("DELETE FROM hooks WHERE id = $1 RETURNING *")
+            .bind(id)
+            .fetch_one(self.pool.deref())
+            .await?;
+        Ok(row.into())
+    }
+
+    #[tracing::instrument(name = "mgr::hooks::list", skip(self))]
+    pub async fn list(
+        &self,
+        kind: &str,
+    ) -> Result<Pin<Box<impl Stream<Item = Result<Hook, Status>> + Send + Sync + 'static>>, Status>
+    {
+        let (tx, rx) = mpsc::channel(4);
+        let pool = self.pool.clone();
+        let kind = kind.to_string();
+        tokio::spawn(async move {
+            let mut rows = sqlx::query_as(
+                "SELECT * FROM hooks WHERE ($1 = '' OR kind = $1) ORDER BY created_at",
+            )
+            .bind(&kind)
+            .fetch(pool.deref());
+            loop {
+                let row: HookRow = match rows.try_next().await {
+                    Ok(row) => match row {
+                        Some(row) => row,
+                        None => {
+                            break;
+                        }
+                    },
+                    Err(err) => {
+                        log::error!("failed to read hook row: {}", err);
+                        break;
+                    }
+                };
+                match tx.send(Ok(row.into())).await {
+                    Ok(_) => (),
+                    Err(err) => {
+                        log::error!("failed to send hook: {}", err);
+                        break;
+                    }
+                };
+            }
+        });
+
+        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+
+    /// Runs all mutating hooks of the kind in creation order and returns the
+    /// resulting document. Each hook sees the output of the previous one.
+    #[tracing::instrument(name = "mgr::hooks::mutate", skip(self))]
+    pub async fn mutate(
+        &self,
+        review: &Review<'_>,
+        data: &serde_json::Value,
+    ) -> Result<serde_json::Value, Error> {
+        let mut data = data.clone();
+        for hook in self.hooks_for(review.kind, HookType::Mutating).await? {
+            if let Some(mutated) = self.call(&hook, review, &data).await? {
+                data = mutated;
+            }
+        }
+        Ok(data)
+    }
+
+    /// Runs all validating hooks of the kind. The first rejection aborts the write.
+    #[tracing::instrument(name = "mgr::hooks::validate", skip(self))]
+    pub async fn validate(
+        &self,
+        review: &Review<'_>,
+        data: &serde_json::Value,
+    ) -> Result<(), Error> {
+        for hook in self.hooks_for(review.kind, HookType::Validating).await? {
+            self.call(&hook, review, data).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether any hook is registered for the kind.
+    #[tracing::instrument(name = "mgr::hooks::exists", skip(self))]
+    pub async fn exists(&self, kind: &str) -> Result<bool, Error> {
+        let row: (bool,) = sqlx::query_as("SELECT EXISTS (SELECT 1 FROM hooks WHERE kind = $1)")
+            .bind(kind)
+            .fetch_one(self.pool.deref())
+            .await?;
+        Ok(row.0)
+    }
+
+    async fn hooks_for(&self, kind: &str, hook_type: HookType) -> Result<Vec<HookRow>, Error> {
+        let rows: Vec<HookRow> = sqlx::query_as(
+            "SELECT * FROM hooks WHERE kind = $1 AND hook_type = $2 ORDER BY created_at",
+        )
+        .bind(kind)
+        .bind(hook_type as i32)
+        .fetch_all(self.pool.deref())
+        .await?;
+        Ok(rows)
+    }
+
+    /// Calls a single hook and applies its failure policy. Returns the mutated
+    /// document if the hook returned one.
+    #[tracing::instrument(name = "mgr::hooks::call", skip(self, hook, data))]
+    async fn call(
+        &self,
+        hook: &HookRow,
+        review: &Review<'_>,
+        data: &serde_json::Value,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        let timeout = std::time::Duration::from_millis(hook.timeout_ms as u64);
+        let protocol = HookProtocol::from_i32(hook.protocol).unwrap_or(HookProtocol::Http);
+
+        let result = match protocol {
+            HookProtocol::Http => {
+                tokio::time::timeout(timeout, self.call_http(&hook.url, review, data)).await
+            }
+            HookProtocol::Grpc => {
+                tokio::time::timeout(timeout, self.call_grpc(&hook.url, review, data)).await
+            }
+        };
+
+        let response = match result {
+            Ok(Ok(response)) => response,
+            Ok(Err(cause)) => return self.apply_failure_policy(hook, cause),
+            Err(_) => return self.apply_failure_policy(hook, "timed out".to_string()),
+        };
+
+        if !response.allowed {
+            let mut reason = response.reason;
+            if reason.is_empty() {
+                reason = "no reason given".to_string();
+            }
+            return Err(Error::Rejected(reason));
+        }
+
+        if hook.hook_type == HookType::Mutating as i32 {
+            return Ok(response.data);
+        }
+
+        Ok(None)
+    }
+
+    fn apply_failure_policy(
+        &self,
+        hook: &HookRow,
+        cause: String,
+    ) -> Result<Option<serde_json::Value>, Error> {
+        if hook.failure_policy == HookFailurePolicy::Ignore as i32 {
+            info!("ignoring failed hook {} ({}): {}", hook.id, hook.url, cause);
+            return Ok(None);
+        }
+        Err(Error::Rejected(format!(
+            "failed to call hook {}: {}",
+            hook.url, cause
+        )))
+    }
+
+    async fn call_http(
+        &self,
+        url: &str,
+        review: &Review<'_>,
+        data: &serde_json::Value,
+    ) -> Result<ReviewResponse, String> {
+        let body = HttpReviewRequest {
+            operation: format!("{:?}", review.operation).to_uppercase(),
+            resource_id: review.resource_id.to_hyphenated().to_string(),
+            kind: review.kind,
+            data,
+            old_data: review.old_data,
+            labels: review.labels,
+            subject: &review.claims.sub,
+        };
+
+        let response = self
+            .client
+            .post(url)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|err| err.to_string())?;
+
+        if !response.status().is_success() {
+            return Err(format!("unexpected status code {}", response.status()));
+        }
+
+        response
+            .json::<ReviewResponse>()
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    async fn call_grpc(
+        &self,
+        url: &str,
+        review: &Review<'_>,
+        data: &serde_json::Value,
+    ) -> Result<ReviewResponse, String> {
+        use api::catalog::admission_hook_client::AdmissionHookClient;
+
+        let mut client = AdmissionHookClient::connect(url.to_string())
+            .await
+            .map_err(|err| err.to_string())?;
+
+        let old_data = match review.old_data {
+            Some(old) => serde_json::to_string(old).map_err(|err| err.to_string())?,
+            None => String::new(),
+        };
+
+        let response = client
+            .review(AdmissionReviewRequest {
+                operation: review.operation as i32,
+                resource_id: review.resource_id.to_hyphenated().to_string(),
+                kind: review.kind.to_string(),
+                data: serde_json::to_string(data).map_err(|err| err.to_string())?,
+                old_data,
+                labels: review.labels.clone(),
+                subject: review.claims.sub.clone(),
+            })
+            .await
+            .map_err(|err| err.to_string())?
+            .into_inner();
+
+        let data = match response.data.is_empty() {
+            true => None,
+            false => Some(serde_json::from_str(&response.data).map_err(|err| err.to_string())?),
+        };
+
+        Ok(ReviewResponse {
+            allowed: response.allowed,
+            reason: response.reason,
+            data,
+        })
+    }
+}
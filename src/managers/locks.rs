@@ -1,7 +1,10 @@
 use super::errors::Error;
 use crate::api;
+use crate::managers;
+use crate::token::{Claims, FencingToken};
 
 use futures::Stream;
+use sqlx::types::Uuid;
 use sqlx::{Postgres, Transaction};
 use std::pin::Pin;
 use tracing::Instrument;
@@ -11,15 +14,121 @@ use std::sync::Arc;
 use tokio::sync::mpsc;
 use tonic::Status;
 
-use api::catalog::LockResponse;
+use api::catalog::{Lease, LockHolder, LockInfo, LockMode, LockResponse, LockWaiter};
+
+// Lifetime of a lease acquired without a ttl.
+const DEFAULT_TTL_MS: u32 = 30_000;
+
+// Lifetime of the leases held by Lock and TryLock streams. They are renewed every
+// STREAM_RENEW_INTERVAL_MS, so a lock of a client which vanished without closing
+// the stream is free again after at most this time.
+const STREAM_TTL_MS: u32 = 10_000;
+const STREAM_RENEW_INTERVAL_MS: u64 = 1_000;
+
+// Interval in which waiters retry to acquire a held lock.
+const POLL_INTERVAL_MS: u64 = 250;
+
+// Lifetime of the registration of a waiter, renewed on every poll. It only
+// matters if the instance serving the waiter died, otherwise the registration is
+// removed as soon as waiting ends.
+const WAITER_TTL_MS: u32 = 2_000;
+
+#[derive(sqlx::FromRow)]
+struct LeaseRow {
+    holder: String,
+    holder_name: String,
+    fencing_token: i64,
+    mode: i32,
+    created_at: chrono::DateTime<chrono::Utc>,
+    renewed_at: chrono::DateTime<chrono::Utc>,
+    expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<LeaseRow> for LockHolder {
+    fn from(r: LeaseRow) -> LockHolder {
+        LockHolder {
+            holder_id: r.holder,
+            holder_name: r.holder_name,
+            fencing_token: r.fencing_token,
+            mode: r.mode,
+            acquired_at: Some(timestamp(&r.created_at)),
+            renewed_at: Some(timestamp(&r.renewed_at)),
+            expires_at: Some(timestamp(&r.expires_at)),
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct WaiterRow {
+    holder: String,
+    holder_name: String,
+    mode: i32,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<WaiterRow> for LockWaiter {
+    fn from(r: WaiterRow) -> LockWaiter {
+        LockWaiter {
+            holder_id: r.holder,
+            holder_name: r.holder_name,
+            mode: r.mode,
+            waiting_since: Some(timestamp(&r.created_at)),
+        }
+    }
+}
 
 #[derive(Debug)]
+pub struct LockOptions<'a> {
+    /// the subject of the claims is recorded as holder of the lease
+    pub claims: &'a Claims,
+    /// free-form name of the holder, e.g. the host of a replica
+    pub holder_name: &'a str,
+    pub namespace: &'a str,
+    pub lock_id: &'a str,
+    /// binds the lock to the resource, see try_acquire
+    pub resource_id: Option<Uuid>,
+    pub mode: LockMode,
+    /// number of concurrent holders of a semaphore
+    pub limit: u32,
+    /// how long Lock and Acquire wait in the queue of the lock. Lock waits
+    /// indefinitely and Acquire not at all if it is 0.
+    pub acquire_timeout_ms: u32,
+}
+
+// Removes the waiter from the queue of the lock when waiting ends, including
+// when the waiting future is dropped because the client went away.
+struct Waiter {
+    pool: Arc<sqlx::PgPool>,
+    waiter_id: Uuid,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        let pool = self.pool.clone();
+        let waiter_id = self.waiter_id;
+        tokio::spawn(async move {
+            if let Err(err) = sqlx::query("DELETE FROM lock_waiters WHERE waiter_id = $1")
+                .bind(waiter_id)
+                .execute(&*pool)
+                .await
+            {
+                log::error!("failed to remove lock waiter: {}", err);
+            }
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Manager {
     pool: Arc<sqlx::PgPool>,
+    permissions: Arc<managers::permissions::Manager>,
 }
 impl Manager {
-    pub async fn new(pool: Arc<sqlx::PgPool>) -> Result<Manager, Error> {
-        let res = Manager { pool };
+    pub async fn new(
+        pool: Arc<sqlx::PgPool>,
+        permissions: Arc<managers::permissions::Manager>,
+    ) -> Result<Manager, Error> {
+        let res = Manager { pool, permissions };
         res.init_table().await?;
         Ok(res)
     }
@@ -29,199 +138,906 @@ impl Manager {
             r#"
             CREATE TABLE IF NOT EXISTS locks (
                 id VARCHAR(255) PRIMARY KEY,
-                created_at TIMESTAMP NOT NULL DEFAULT NOW(),
-                updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
                 fencing_token BIGINT NOT NULL
             );
         "#,
         )
         .execute(&*self.pool)
         .await?;
+
+        // leases are stored per holder, locks only keeps the last issued fencing
+        // token. Its timestamps are compared with those of the leases, tables
+        // created with TIMESTAMP columns are rewritten once.
+        let (without_time_zone,): (bool,) = sqlx::query_as(
+            r#"SELECT EXISTS (
+                SELECT 1 FROM information_schema.columns
+                WHERE table_schema = current_schema() AND table_name = 'locks'
+                AND column_name IN ('created_at', 'updated_at')
+                AND data_type = 'timestamp without time zone'
+            )"#,
+        )
+        .fetch_one(&*self.pool)
+        .await?;
+        if without_time_zone {
+            sqlx::query(
+                "ALTER TABLE locks ALTER COLUMN created_at TYPE TIMESTAMPTZ, ALTER COLUMN updated_at TYPE TIMESTAMPTZ;",
+            )
+            .execute(&*self.pool)
+            .await?;
+        }
+
+        // locks are identified by namespace and id, see check_key
+        sqlx::query(
+            "ALTER TABLE locks ADD COLUMN IF NOT EXISTS namespace VARCHAR(255) NOT NULL DEFAULT '';",
+        )
+        .execute(&*self.pool)
+        .await?;
+        sqlx::query(
+            "CREATE UNIQUE INDEX IF NOT EXISTS locks_namespace_id_idx ON locks (namespace, id);",
+        )
+        .execute(&*self.pool)
+        .await?;
+        // the primary key on id alone is dropped once, a foreign key depending on it
+        // makes this fail instead of being dropped along
+        let (has_pkey,): (bool,) = sqlx::query_as(
+            r#"SELECT EXISTS (
+                SELECT 1 FROM information_schema.table_constraints
+                WHERE table_schema = current_schema() AND table_name = 'locks'
+                AND constraint_name = 'locks_pkey'
+            )"#,
+        )
+        .fetch_one(&*self.pool)
+        .await?;
+        if has_pkey {
+            sqlx::query("ALTER TABLE locks DROP CONSTRAINT locks_pkey;")
+                .execute(&*self.pool)
+                .await?;
+        }
+
+        // resource the lock is bound to
+        sqlx::query("ALTER TABLE locks ADD COLUMN IF NOT EXISTS resource_id UUID;")
+            .execute(&*self.pool)
+            .await?;
+
+        // limit of a semaphore, fixed by its first acquisition
+        sqlx::query(r#"ALTER TABLE locks ADD COLUMN IF NOT EXISTS "limit" INT4;"#)
+            .execute(&*self.pool)
+            .await?;
+
+        let _ = sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS lock_leases (
+                namespace VARCHAR(255) NOT NULL,
+                lock_id VARCHAR(255) NOT NULL,
+                fencing_token BIGINT NOT NULL,
+                mode INT4 NOT NULL,
+                holder VARCHAR(255) NOT NULL,
+                holder_name VARCHAR(255) NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                renewed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                expires_at TIMESTAMPTZ NOT NULL,
+                PRIMARY KEY (namespace, lock_id, fencing_token)
+            );
+        "#,
+        )
+        .execute(&*self.pool)
+        .await?;
+
+        // Lock streams waiting for a lock, kept alive while they poll
+        let _ = sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS lock_waiters (
+                waiter_id UUID PRIMARY KEY,
+                namespace VARCHAR(255) NOT NULL,
+                lock_id VARCHAR(255) NOT NULL,
+                mode INT4 NOT NULL,
+                holder VARCHAR(255) NOT NULL,
+                holder_name VARCHAR(255) NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+                expires_at TIMESTAMPTZ NOT NULL
+            );
+        "#,
+        )
+        .execute(&*self.pool)
+        .await?;
         Ok(())
     }
 
+    /// Waits until the lock can be taken in the requested mode and holds it as
+    /// long as the returned stream is consumed.
     #[tracing::instrument(name = "mgr::locks::lock", skip(self))]
     pub async fn lock(
         &self,
-        lock_id: &str,
+        opts: &LockOptions<'_>,
     ) -> Result<
         Pin<Box<impl Stream<Item = Result<LockResponse, Status>> + Send + Sync + 'static>>,
         Status,
     > {
         info!("call lock");
-        let (tx, rx) = mpsc::channel(4);
-        let lock_id = lock_id.to_string();
+        let lease = match self.wait(opts, STREAM_TTL_MS).await? {
+            Some(lease) => lease,
+            None => {
+                return Err(Status::resource_exhausted(format!(
+                    "timed out waiting for lock {}",
+                    opts.lock_id
+                )))
+            }
+        };
 
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
+        info!("got lock");
 
-        let mut s = DefaultHasher::new();
-        lock_id.hash(&mut s);
-        let lock_id_int = s.finish();
+        Ok(self.hold(opts.claims, lease))
+    }
 
-        let mut transaction = match self.pool.begin().await {
-            Ok(t) => t,
-            Err(err) => return Err(Status::internal(err.to_string())),
-        };
-        match sqlx::query("SELECT pg_advisory_xact_lock($1)")
-            .bind(lock_id_int as i64)
-            .execute(&mut transaction)
-            .await
-        {
-            Ok(row) => row,
-            Err(err) => return Err(Status::internal(err.to_string())),
+    /// Like lock, but fails if the lock is held.
+    #[tracing::instrument(name = "mgr::locks::try_lock", skip(self))]
+    pub async fn try_lock(
+        &self,
+        opts: &LockOptions<'_>,
+    ) -> Result<
+        Pin<Box<impl Stream<Item = Result<LockResponse, Status>> + Send + Sync + 'static>>,
+        Status,
+    > {
+        info!("call try_lock");
+
+        let lease = match self.try_acquire(opts, STREAM_TTL_MS, None).await? {
+            Some(lease) => lease,
+            None => return Err(Status::resource_exhausted("failed to get lock")),
         };
 
         info!("got lock");
 
-        let fencing_token = self.get_fencing_token(&lock_id).await?;
+        Ok(self.hold(opts.claims, lease))
+    }
+
+    // Queues up for the lock and polls until it is acquired or the acquire
+    // timeout of the options passed.
+    async fn wait(&self, opts: &LockOptions<'_>, ttl_ms: u32) -> Result<Option<Lease>, Error> {
+        use tokio::time::{sleep, Duration, Instant};
+        let deadline = match opts.acquire_timeout_ms {
+            0 => None,
+            val => Some(Instant::now() + Duration::from_millis(val as u64)),
+        };
+
+        let waiter = Waiter {
+            pool: self.pool.clone(),
+            waiter_id: Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes()),
+        };
+        loop {
+            // registers the waiter on the first iteration, afterwards keeps it alive
+            sqlx::query(
+                r#"
+                INSERT INTO lock_waiters (waiter_id, namespace, lock_id, mode, holder, holder_name, expires_at)
+                VALUES ($1, $6, $2, $3, $4, $7, now() + make_interval(secs => $5))
+                ON CONFLICT (waiter_id) DO UPDATE SET expires_at = EXCLUDED.expires_at"#,
+            )
+            .bind(waiter.waiter_id)
+            .bind(opts.lock_id)
+            .bind(opts.mode as i32)
+            .bind(&opts.claims.sub)
+            .bind(ttl_secs(WAITER_TTL_MS))
+            .bind(opts.namespace)
+            .bind(opts.holder_name)
+            .execute(&*self.pool)
+            .await?;
+
+            if let Some(lease) = self
+                .try_acquire(opts, ttl_ms, Some(&waiter.waiter_id))
+                .await?
+            {
+                return Ok(Some(lease));
+            }
+
+            let mut interval = Duration::from_millis(POLL_INTERVAL_MS);
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(None);
+                }
+                interval = interval.min(deadline - now);
+            }
+            sleep(interval).await;
+        }
+    }
+
+    // Streams the lease while renewing it, the lease is released when the
+    // receiver is dropped.
+    fn hold(
+        &self,
+        claims: &Claims,
+        lease: Lease,
+    ) -> Pin<Box<impl Stream<Item = Result<LockResponse, Status>> + Send + Sync + 'static>> {
+        let (tx, rx) = mpsc::channel(4);
+        let mgr = self.clone();
+        let claims = claims.clone();
 
         tokio::spawn(
             async move {
                 use tokio::time::{sleep, Duration};
+                let namespace = lease.namespace;
+                let lock_id = lease.lock_id;
+                let fencing_token = lease.fencing_token;
                 loop {
                     match tx
                         .send(Ok(LockResponse {
                             lock_id: lock_id.to_string(),
                             fencing_token,
+                            namespace: namespace.to_string(),
                         }))
                         .await
                     {
                         Ok(_) => {
-                            sleep(Duration::from_millis(100)).await;
+                            sleep(Duration::from_millis(STREAM_RENEW_INTERVAL_MS)).await;
                         }
-                        Err(err) => {
-                            log::error!("failed to send permission info: {}", err);
+                        Err(_) => {
+                            info!("lock stream closed");
                             break;
                         }
                     };
+
+                    if let Err(err) = mgr
+                        .renew(&claims, &namespace, &lock_id, fencing_token, STREAM_TTL_MS)
+                        .await
+                    {
+                        log::error!("failed to renew lock: {}", err);
+                        let _ = tx.send(Err(err.into())).await;
+                        return;
+                    }
                 }
 
-                match transaction.commit().await {
+                match mgr
+                    .release(&claims, &namespace, &lock_id, fencing_token)
+                    .await
+                {
                     Ok(_) => info!("released lock"),
-                    Err(_) => info!("failed to commit lock transaction"),
+                    Err(err) => info!("failed to release lock: {}", err),
                 };
             }
             .instrument(tracing::debug_span!("stream_lock_messages")),
         );
 
-        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+        Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx))
     }
 
-    #[tracing::instrument(name = "mgr::locks::try_lock", skip(self))]
-    pub async fn try_lock(
+    /// Acquires a lease on the lock which expires after ttl_ms, fails if the lock
+    /// is held in a conflicting mode after waiting for the acquire timeout.
+    #[tracing::instrument(name = "mgr::locks::acquire", skip(self))]
+    pub async fn acquire(&self, opts: &LockOptions<'_>, ttl_ms: u32) -> Result<Lease, Error> {
+        let res = match opts.acquire_timeout_ms {
+            0 => self.try_acquire(opts, ttl_ms, None).await?,
+            _ => self.wait(opts, ttl_ms).await?,
+        };
+        match res {
+            Some(lease) => Ok(lease),
+            None => Err(Error::ResourceExhausted(format!(
+                "lock {} is held",
+                opts.lock_id
+            ))),
+        }
+    }
+
+    // Adds a lease if the current holders admit the requested mode and nobody
+    // queued up before, so waiters get the lock in the order they arrived. Every
+    // lease gets a new fencing token, so it identifies the lease regardless of the
+    // mode.
+    //
+    // A lock is bound to the resource it is created with, requests passing
+    // another resource fail. Leases on a bound lock require write permission on
+    // the resource, whether the request passes the resource or not.
+    async fn try_acquire(
+        &self,
+        opts: &LockOptions<'_>,
+        ttl_ms: u32,
+        waiter_id: Option<&Uuid>,
+    ) -> Result<Option<Lease>, Error> {
+        let namespace = opts.namespace;
+        let lock_id = opts.lock_id;
+        check_key(namespace, lock_id)?;
+        if opts.holder_name.len() > 255 {
+            return Err(Error::InvalidArgument(
+                "'holder_name' must not be longer than 255 bytes".into(),
+            ));
+        }
+        if opts.mode == LockMode::Semaphore && opts.limit == 0 {
+            return Err(Error::InvalidArgument(
+                "'limit' must be specified for semaphores".into(),
+            ));
+        }
+
+        self.check_namespace(opts.claims, namespace, "write")
+            .await?;
+
+        // the permission is checked before the row of the lock is locked, so
+        // the binding is compared again afterwards
+        let binding = match self.binding(namespace, lock_id).await? {
+            None => opts.resource_id,
+            Some(bound) => {
+                check_binding(lock_id, bound, opts.resource_id)?;
+                bound
+            }
+        };
+        if let Some(resource_id) = &binding {
+            self.permissions
+                .check(resource_id, "write", opts.claims)
+                .await?;
+        }
+
+        let mut tx = self.pool.begin().await?;
+
+        // the row of the lock serializes concurrent acquisitions
+        let (resource_id, limit): (Option<Uuid>, Option<i32>) = sqlx::query_as(
+            r#"
+            INSERT INTO locks (namespace, id, fencing_token, resource_id, "limit") VALUES ($1, $2, 0, $3, $4)
+            ON CONFLICT (namespace, id) DO UPDATE
+            SET updated_at = NOW(), "limit" = COALESCE(locks."limit", EXCLUDED."limit")
+            RETURNING resource_id, "limit""#,
+        )
+        .bind(namespace)
+        .bind(lock_id)
+        .bind(binding)
+        .bind(match opts.mode {
+            LockMode::Semaphore => Some(opts.limit as i32),
+            _ => None,
+        })
+        .fetch_one(&mut tx)
+        .await?;
+        if resource_id != binding {
+            return Err(Error::FailedPrecondition(format!(
+                "lock {} was bound concurrently",
+                lock_id
+            )));
+        }
+
+        // holders with different limits would not agree on the free slots
+        if let (LockMode::Semaphore, Some(limit)) = (opts.mode, limit) {
+            if limit as u32 != opts.limit {
+                return Err(Error::FailedPrecondition(format!(
+                    "semaphore {} has a limit of {}",
+                    lock_id, limit
+                )));
+            }
+        }
+
+        sqlx::query(
+            "DELETE FROM lock_leases WHERE namespace = $1 AND lock_id = $2 AND expires_at <= now()",
+        )
+        .bind(namespace)
+        .bind(lock_id)
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query(
+            "DELETE FROM lock_waiters WHERE namespace = $1 AND lock_id = $2 AND expires_at <= now()",
+        )
+        .bind(namespace)
+        .bind(lock_id)
+        .execute(&mut tx)
+        .await?;
+
+        let holders: Vec<(i32,)> =
+            sqlx::query_as("SELECT mode FROM lock_leases WHERE namespace = $1 AND lock_id = $2")
+                .bind(namespace)
+                .bind(lock_id)
+                .fetch_all(&mut tx)
+                .await?;
+        let holders: Vec<i32> = holders.into_iter().map(|row| row.0).collect();
+
+        let (queued,): (bool,) = sqlx::query_as(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM lock_waiters w
+                WHERE w.namespace = $3 AND w.lock_id = $1 AND w.waiter_id IS DISTINCT FROM $2
+                AND ($2::UUID IS NULL OR (w.created_at, w.waiter_id) <
+                    (SELECT created_at, waiter_id FROM lock_waiters WHERE waiter_id = $2))
+            )"#,
+        )
+        .bind(lock_id)
+        .bind(waiter_id)
+        .bind(namespace)
+        .fetch_one(&mut tx)
+        .await?;
+
+        if queued || !admits(opts, &holders) {
+            tx.commit().await?;
+            return Ok(None);
+        }
+
+        if let Some(waiter_id) = waiter_id {
+            sqlx::query("DELETE FROM lock_waiters WHERE waiter_id = $1")
+                .bind(waiter_id)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        let (fencing_token, expires_at): (i64, chrono::DateTime<chrono::Utc>) = sqlx::query_as(
+            r#"
+            WITH token AS (
+                UPDATE locks SET fencing_token = fencing_token + 1
+                WHERE namespace = $5 AND id = $1
+                RETURNING fencing_token
+            )
+            INSERT INTO lock_leases (namespace, lock_id, fencing_token, mode, holder, holder_name, expires_at)
+            SELECT $5, $1, fencing_token, $2, $3, $6, now() + make_interval(secs => $4) FROM token
+            RETURNING fencing_token, expires_at"#,
+        )
+        .bind(lock_id)
+        .bind(opts.mode as i32)
+        .bind(&opts.claims.sub)
+        .bind(ttl_secs(ttl_ms))
+        .bind(namespace)
+        .bind(opts.holder_name)
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Some(lease(
+            namespace,
+            lock_id,
+            fencing_token,
+            opts.mode,
+            expires_at,
+        )))
+    }
+
+    /// Extends the lease identified by its fencing token to ttl_ms from now. Only
+    /// the holder of the lease and admins may renew it.
+    #[tracing::instrument(name = "mgr::locks::renew", skip(self))]
+    pub async fn renew(
+        &self,
+        claims: &Claims,
+        namespace: &str,
+        lock_id: &str,
+        fencing_token: i64,
+        ttl_ms: u32,
+    ) -> Result<Lease, Error> {
+        self.check_namespace(claims, namespace, "write").await?;
+        self.check_bound(claims, namespace, lock_id).await?;
+
+        let row: Option<(i32, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
+            r#"
+            UPDATE lock_leases SET expires_at = now() + make_interval(secs => $3), renewed_at = now()
+            WHERE namespace = $4 AND lock_id = $1 AND fencing_token = $2 AND expires_at > now()
+            AND (holder = $5 OR $6)
+            RETURNING mode, expires_at"#,
+        )
+        .bind(lock_id)
+        .bind(fencing_token)
+        .bind(ttl_secs(ttl_ms))
+        .bind(namespace)
+        .bind(&claims.sub)
+        .bind(claims.adm)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        match row {
+            Some((mode, expires_at)) => Ok(lease(
+                namespace,
+                lock_id,
+                fencing_token,
+                mode_of(mode),
+                expires_at,
+            )),
+            None => Err(Error::FailedPrecondition(format!(
+                "lock {} is not held with fencing token {}",
+                lock_id, fencing_token
+            ))),
+        }
+    }
+
+    /// Ends the lease identified by its fencing token. Only the holder of the
+    /// lease and admins may release it.
+    #[tracing::instrument(name = "mgr::locks::release", skip(self))]
+    pub async fn release(
+        &self,
+        claims: &Claims,
+        namespace: &str,
+        lock_id: &str,
+        fencing_token: i64,
+    ) -> Result<Lease, Error> {
+        self.check_namespace(claims, namespace, "write").await?;
+        self.check_bound(claims, namespace, lock_id).await?;
+
+        let row: Option<(i32, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
+            r#"
+            DELETE FROM lock_leases
+            WHERE namespace = $3 AND lock_id = $1 AND fencing_token = $2 AND expires_at > now()
+            AND (holder = $4 OR $5)
+            RETURNING mode, now()"#,
+        )
+        .bind(lock_id)
+        .bind(fencing_token)
+        .bind(namespace)
+        .bind(&claims.sub)
+        .bind(claims.adm)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        match row {
+            Some((mode, now)) => Ok(lease(namespace, lock_id, fencing_token, mode_of(mode), now)),
+            None => Err(Error::FailedPrecondition(format!(
+                "lock {} is not held with fencing token {}",
+                lock_id, fencing_token
+            ))),
+        }
+    }
+
+    // Returns the resource the lock is bound to, None if the lock doesn't exist.
+    async fn binding(&self, namespace: &str, lock_id: &str) -> Result<Option<Option<Uuid>>, Error> {
+        let row: Option<(Option<Uuid>,)> =
+            sqlx::query_as("SELECT resource_id FROM locks WHERE namespace = $1 AND id = $2")
+                .bind(namespace)
+                .bind(lock_id)
+                .fetch_optional(&*self.pool)
+                .await?;
+        Ok(row.map(|(resource_id,)| resource_id))
+    }
+
+    // Checks the write permission on the resource a lock is bound to.
+    async fn check_bound(
+        &self,
+        claims: &Claims,
+        namespace: &str,
+        lock_id: &str,
+    ) -> Result<(), Error> {
+        if let Some(Some(resource_id)) = self.binding(namespace, lock_id).await? {
+            self.permissions
+                .check(&resource_id, "write", claims)
+                .await?;
+        }
+        Ok(())
+    }
+
+    // A namespace which is the id of a resource belongs to the resource, its locks
+    // require the permission for the action on it. Other namespaces only scope
+    // the lock ids and are shared by everybody.
+    async fn check_namespace(
         &self,
+        claims: &Claims,
+        namespace: &str,
+        action: &str,
+    ) -> Result<(), Error> {
+        if let Ok(resource_id) = Uuid::parse_str(namespace) {
+            self.permissions.check(&resource_id, action, claims).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the holders and waiters of the lock.
+    #[tracing::instrument(name = "mgr::locks::get", skip(self))]
+    pub async fn get(
+        &self,
+        claims: &Claims,
+        namespace: &str,
         lock_id: &str,
+    ) -> Result<LockInfo, Error> {
+        self.check_namespace(claims, namespace, "read").await?;
+        info(&self.pool, namespace, lock_id).await
+    }
+
+    /// Streams all locks of the namespace which are held or waited for.
+    #[tracing::instrument(name = "mgr::locks::list", skip(self))]
+    pub async fn list(
+        &self,
+        claims: &Claims,
+        namespace: &str,
     ) -> Result<
-        Pin<Box<impl Stream<Item = Result<LockResponse, Status>> + Send + Sync + 'static>>,
+        Pin<Box<impl Stream<Item = Result<LockInfo, Status>> + Send + Sync + 'static>>,
         Status,
     > {
-        info!("call try_lock");
+        self.check_namespace(claims, namespace, "read").await?;
+        let ids: Vec<(String,)> = sqlx::query_as(
+            r#"
+            SELECT id FROM locks l
+            WHERE l.namespace = $1 AND (
+                EXISTS (
+                    SELECT 1 FROM lock_leases
+                    WHERE namespace = l.namespace AND lock_id = l.id AND expires_at > now()
+                ) OR EXISTS (
+                    SELECT 1 FROM lock_waiters
+                    WHERE namespace = l.namespace AND lock_id = l.id AND expires_at > now()
+                )
+            )
+            ORDER BY id"#,
+        )
+        .bind(namespace)
+        .fetch_all(&*self.pool)
+        .await
+        .map_err(Error::from)?;
 
         let (tx, rx) = mpsc::channel(4);
-        let lock_id = lock_id.to_string();
+        let pool = self.pool.clone();
+        let namespace = namespace.to_string();
+        tokio::spawn(async move {
+            for (lock_id,) in ids {
+                let res = match info(&pool, &namespace, &lock_id).await {
+                    Ok(res) => Ok(res),
+                    // released in the meantime
+                    Err(Error::NotFound) => continue,
+                    Err(err) => Err(err.into()),
+                };
+                if let Err(err) = tx.send(res).await {
+                    log::error!("failed to send lock: {}", err);
+                    break;
+                }
+            }
+        });
 
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-        let mut s = DefaultHasher::new();
-        lock_id.hash(&mut s);
-        let lock_id_int = s.finish();
+        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
 
-        let mut transaction = match self.pool.begin().await {
-            Ok(t) => t,
-            Err(err) => return Err(Status::internal(err.to_string())),
-        };
-        let row: (bool,) = match sqlx::query_as("SELECT pg_try_advisory_xact_lock($1)")
-            .bind(lock_id_int as i64)
-            .fetch_one(&mut transaction)
-            .await
-        {
-            Ok(row) => row,
-            Err(err) => return Err(Status::internal(err.to_string())),
-        };
+    /// Ends all leases of the lock and bumps its fencing token, so writes of the
+    /// former holders are rejected even if they still consider the lock theirs.
+    #[tracing::instrument(name = "mgr::locks::force_release", skip(self))]
+    pub async fn force_release(&self, namespace: &str, lock_id: &str) -> Result<LockInfo, Error> {
+        let mut tx = self.pool.begin().await?;
 
-        if !row.0 {
-            return Err(Status::resource_exhausted("failed to get lock"));
+        let row: Option<(i64,)> = sqlx::query_as(
+            "UPDATE locks SET fencing_token = fencing_token + 1, updated_at = NOW() WHERE namespace = $1 AND id = $2 RETURNING fencing_token",
+        )
+        .bind(namespace)
+        .bind(lock_id)
+        .fetch_optional(&mut tx)
+        .await?;
+        if row.is_none() {
+            return Err(Error::NotFound);
         }
 
-        info!("got lock");
+        sqlx::query("DELETE FROM lock_leases WHERE namespace = $1 AND lock_id = $2")
+            .bind(namespace)
+            .bind(lock_id)
+            .execute(&mut tx)
+            .await?;
 
-        let fencing_token = self.get_fencing_token(&lock_id).await?;
+        tx.commit().await?;
 
-        tokio::spawn(
-            async move {
-                use tokio::time::{sleep, Duration};
-                loop {
-                    match tx
-                        .send(Ok(LockResponse {
-                            lock_id: lock_id.to_string(),
-                            fencing_token,
-                        }))
-                        .await
-                    {
-                        Ok(_) => {
-                            sleep(Duration::from_millis(1000)).await;
-                        }
-                        Err(err) => {
-                            log::error!("failed to send permission info: {}", err);
-                            break;
-                        }
-                    };
-                }
-                match transaction.commit().await {
-                    Ok(_) => info!("released lock"),
-                    Err(_) => info!("failed to commit lock transaction"),
-                };
-            }
-            .instrument(tracing::debug_span!("stream_lock_messages")),
-        );
+        info!("force released lock");
 
-        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+        info(&self.pool, namespace, lock_id).await
     }
 
+    /// Returns whether the fencing token belongs to the current exclusive lease of
+    /// the lock and was issued to the subject, shared holders can't fence writes.
     #[tracing::instrument(name = "mgr::locks::check_fencing_token", skip(self))]
     pub async fn check_fencing_token(
         &self,
         tx: &mut Transaction<'_, Postgres>,
-        lock_id: &str,
-        fencing_token: i64,
+        fencing_token: &FencingToken,
+        subject: &str,
     ) -> Result<bool, Error> {
-        let row: (bool,) =
-            match sqlx::query_as("SELECT (fencing_token = $1) FROM locks WHERE id = $2")
-                .bind(fencing_token)
-                .bind(lock_id)
-                .fetch_one(tx)
-                .await
-            {
-                Ok(row) => row,
-                Err(err) => return Err(Error::Database(err.to_string())),
-            };
-
-        Ok(row.0)
-    }
-
-    #[tracing::instrument(name = "mgr::locks::get_fencing_token", skip(self))]
-    async fn get_fencing_token(&self, lock_id: &str) -> Result<i64, Status> {
-        // insert lock into db or increase fencing_token if lock already exists
-        // This is done outside of the transaction to make the new fencing token available to other
-        // Note that this is done AFTER the pg_advisory_lock is aquired.
-        let row: (i64,) = match sqlx::query_as(
+        let row: (bool,) = match sqlx::query_as(
             r#"
-            INSERT INTO locks (id, fencing_token) 
-            VALUES ($1, $2) 
-            ON CONFLICT (id) DO UPDATE 
-            SET fencing_token = locks.fencing_token + 1,
-                updated_at = NOW()
-            RETURNING fencing_token"#,
-        )
-        .bind(&lock_id)
-        .bind(1)
-        .fetch_one(&*self.pool)
+            SELECT EXISTS (
+                SELECT 1 FROM lock_leases
+                WHERE namespace = $4 AND lock_id = $2 AND fencing_token = $1 AND mode = $3
+                AND holder = $5 AND expires_at > now()
+            )"#,
+        )
+        .bind(fencing_token.token)
+        .bind(&fencing_token.lock_id)
+        .bind(LockMode::Exclusive as i32)
+        .bind(&fencing_token.namespace)
+        .bind(subject)
+        .fetch_one(tx)
         .await
         {
             Ok(row) => row,
-            Err(err) => return Err(Status::internal(err.to_string())),
+            Err(err) => return Err(Error::Database(err.to_string())),
         };
+
         Ok(row.0)
     }
 }
+
+async fn info(pool: &sqlx::PgPool, namespace: &str, lock_id: &str) -> Result<LockInfo, Error> {
+    let row: Option<(i64, Option<Uuid>)> = sqlx::query_as(
+        "SELECT fencing_token, resource_id FROM locks WHERE namespace = $1 AND id = $2",
+    )
+    .bind(namespace)
+    .bind(lock_id)
+    .fetch_optional(pool)
+    .await?;
+    let (fencing_token, resource_id) = match row {
+        Some(row) => row,
+        None => return Err(Error::NotFound),
+    };
+
+    let holders: Vec<LeaseRow> = sqlx::query_as(
+        r#"
+        SELECT holder, holder_name, fencing_token, mode, created_at, renewed_at, expires_at
+        FROM lock_leases
+        WHERE namespace = $1 AND lock_id = $2 AND expires_at > now()
+        ORDER BY fencing_token"#,
+    )
+    .bind(namespace)
+    .bind(lock_id)
+    .fetch_all(pool)
+    .await?;
+
+    let waiters: Vec<WaiterRow> = sqlx::query_as(
+        r#"
+        SELECT holder, holder_name, mode, created_at FROM lock_waiters
+        WHERE namespace = $1 AND lock_id = $2 AND expires_at > now()
+        ORDER BY created_at, waiter_id"#,
+    )
+    .bind(namespace)
+    .bind(lock_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(LockInfo {
+        namespace: namespace.to_string(),
+        lock_id: lock_id.to_string(),
+        resource_id: resource_id
+            .map(|id| id.to_hyphenated().to_string())
+            .unwrap_or_default(),
+        fencing_token,
+        holders: holders.into_iter().map(LockHolder::from).collect(),
+        waiters: waiters.into_iter().map(LockWaiter::from).collect(),
+    })
+}
+
+// A lock is identified by its namespace and id. Both are stored verbatim rather
+// than hashed into an advisory lock key, so the identity doesn't depend on the
+// build of the server and distinct names never collide. Fencing tokens are passed
+// as `[<namespace>#]<lock id>#<token>`, hence neither may contain a '#'.
+fn check_key(namespace: &str, lock_id: &str) -> Result<(), Error> {
+    if lock_id.is_empty() {
+        return Err(Error::InvalidArgument("'lock_id' must be specified".into()));
+    }
+    if namespace.contains('#') || lock_id.contains('#') {
+        return Err(Error::InvalidArgument(
+            "'namespace' and 'lock_id' must not contain '#'".into(),
+        ));
+    }
+    if namespace.len() > 255 || lock_id.len() > 255 {
+        return Err(Error::InvalidArgument(
+            "'namespace' and 'lock_id' must not be longer than 255 bytes".into(),
+        ));
+    }
+    Ok(())
+}
+
+// Requests passing a resource must name the one the lock is bound to, requests
+// without a resource take the lock whatever it is bound to.
+fn check_binding(lock_id: &str, bound: Option<Uuid>, requested: Option<Uuid>) -> Result<(), Error> {
+    match (bound, requested) {
+        (_, None) => Ok(()),
+        (Some(bound), Some(requested)) if bound == requested => Ok(()),
+        (Some(bound), Some(_)) => Err(Error::InvalidArgument(format!(
+            "lock {} is bound to resource {}",
+            lock_id, bound
+        ))),
+        (None, Some(_)) => Err(Error::InvalidArgument(format!(
+            "lock {} is not bound to a resource",
+            lock_id
+        ))),
+    }
+}
+
+fn ttl_secs(ttl_ms: u32) -> f64 {
+    match ttl_ms {
+        0 => DEFAULT_TTL_MS as f64 / 1000.0,
+        val => val as f64 / 1000.0,
+    }
+}
+
+// Exclusive leases require a free lock, shared leases only exclude exclusive
+// holders and semaphores admit up to limit holders of the same mode.
+fn admits(opts: &LockOptions<'_>, holders: &[i32]) -> bool {
+    let all = |mode: LockMode| holders.iter().all(|m| *m == mode as i32);
+    match opts.mode {
+        LockMode::Exclusive => holders.is_empty(),
+        LockMode::Shared => all(LockMode::Shared),
+        LockMode::Semaphore => all(LockMode::Semaphore) && holders.len() < opts.limit as usize,
+    }
+}
+
+fn mode_of(mode: i32) -> LockMode {
+    LockMode::from_i32(mode).unwrap_or(LockMode::Exclusive)
+}
+
+fn lease(
+    namespace: &str,
+    lock_id: &str,
+    fencing_token: i64,
+    mode: LockMode,
+    expires_at: chrono::DateTime<chrono::Utc>,
+) -> Lease {
+    Lease {
+        namespace: namespace.to_string(),
+        lock_id: lock_id.to_string(),
+        fencing_token,
+        mode: mode as i32,
+        expires_at: Some(timestamp(&expires_at)),
+    }
+}
+
+fn timestamp(ts: &chrono::DateTime<chrono::Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: ts.timestamp(),
+        nanos: ts.timestamp_subsec_nanos() as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_key_accepts_valid_keys() {
+        assert!(check_key("", "lock").is_ok());
+        assert!(check_key("tenant-1", "lock/with:separators").is_ok());
+        assert!(check_key(&"n".repeat(255), &"l".repeat(255)).is_ok());
+    }
+
+    #[test]
+    fn check_key_rejects_invalid_keys() {
+        for (namespace, lock_id) in [
+            ("", ""),
+            ("a#b", "lock"),
+            ("", "lock#1"),
+            (&"n".repeat(256) as &str, "lock"),
+            ("", &"l".repeat(256) as &str),
+        ] {
+            assert!(
+                matches!(
+                    check_key(namespace, lock_id),
+                    Err(Error::InvalidArgument(_))
+                ),
+                "{:?} {:?}",
+                namespace,
+                lock_id
+            );
+        }
+    }
+
+    #[test]
+    fn check_binding_requires_the_bound_resource() {
+        let a = Uuid::from_u128(1);
+        let b = Uuid::from_u128(2);
+        assert!(check_binding("lock", None, None).is_ok());
+        assert!(check_binding("lock", Some(a), None).is_ok());
+        assert!(check_binding("lock", Some(a), Some(a)).is_ok());
+        assert!(check_binding("lock", Some(a), Some(b)).is_err());
+        assert!(check_binding("lock", None, Some(a)).is_err());
+    }
+
+    #[test]
+    fn admits_holders_according_to_the_mode() {
+        let claims = Claims::admin();
+        let opts = |mode: LockMode, limit: u32| LockOptions {
+            claims: &claims,
+            holder_name: "",
+            namespace: "",
+            lock_id: "lock",
+            resource_id: None,
+            mode,
+            limit,
+            acquire_timeout_ms: 0,
+        };
+        let exclusive = LockMode::Exclusive as i32;
+        let shared = LockMode::Shared as i32;
+        let semaphore = LockMode::Semaphore as i32;
+
+        assert!(admits(&opts(LockMode::Exclusive, 0), &[]));
+        assert!(!admits(&opts(LockMode::Exclusive, 0), &[exclusive]));
+        assert!(!admits(&opts(LockMode::Exclusive, 0), &[shared]));
+
+        assert!(admits(&opts(LockMode::Shared, 0), &[]));
+        assert!(admits(&opts(LockMode::Shared, 0), &[shared, shared]));
+        assert!(!admits(&opts(LockMode::Shared, 0), &[exclusive]));
+        assert!(!admits(&opts(LockMode::Shared, 0), &[semaphore]));
+
+        assert!(admits(&opts(LockMode::Semaphore, 2), &[]));
+        assert!(admits(&opts(LockMode::Semaphore, 2), &[semaphore]));
+        assert!(!admits(
+            &opts(LockMode::Semaphore, 2),
+            &[semaphore, semaphore]
+        ));
+        assert!(!admits(&opts(LockMode::Semaphore, 2), &[shared]));
+        assert!(!admits(&opts(LockMode::Semaphore, 0), &[]));
+    }
+}
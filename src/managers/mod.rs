@@ -1,9 +1,14 @@
+pub mod blobs;
+pub mod elections;
 pub mod errors;
 pub mod events;
 pub mod groups;
+pub mod hooks;
 pub mod locks;
 pub mod permissions;
 pub mod resources;
+pub mod schedules;
 pub mod schemas;
 pub mod service_accounts;
+pub mod tasks;
 pub mod users;
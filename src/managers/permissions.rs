@@ -1,5 +1,6 @@
 use super::errors::Error;
 use crate::api;
+use crate::managers::resources::PermissionsTable;
 use crate::token::Claims;
 
 use futures::{Stream, TryStreamExt};
@@ -7,6 +8,7 @@ use std::pin::Pin;
 
 use api::catalog::PermissionInfo;
 use log::info;
+use sea_query::{Expr, Query, SimpleExpr};
 use sqlx::types::Uuid;
 use std::ops::Deref;
 use std::sync::Arc;
@@ -213,6 +215,61 @@ impl Manager {
                 FROM resources
                 LEFT JOIN permissions ON (resources.permission_parent_id = permissions.resource_id) 
                 WHERE resources.resource_id = $1 
+                AND resources.deleted_at IS NULL
+                AND principal_id = ANY($2)
+                AND action = $3"#,
+        )
+        .bind(resource_id)
+        .bind(&claims.principals()?)
+        .bind(action)
+        .fetch_one(self.pool.deref())
+        .await?;
+
+        match row.0 {
+            0 => Err(Error::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the condition of check as expression on the permission parent of
+    /// a resource, so queries can select the permitted resources instead of
+    /// checking them one by one. Whether the resource is deleted is up to them.
+    pub fn condition(
+        &self,
+        permission_parent_id: Expr,
+        action: &str,
+        claims: &Claims,
+    ) -> Result<SimpleExpr, Error> {
+        if claims.adm {
+            return Ok(Expr::value(true));
+        }
+        Ok(permission_parent_id.in_subquery(
+            Query::select()
+                .column(PermissionsTable::ResourceID)
+                .from(PermissionsTable::Table)
+                .and_where(Expr::col(PermissionsTable::PrincipalID).is_in(claims.principals()?))
+                .and_where(Expr::col(PermissionsTable::Action).eq(action))
+                .take(),
+        ))
+    }
+
+    /// Like check, but for resources which are deleted and not yet purged.
+    #[tracing::instrument(name = "mgr::permissions::check_deleted", skip(self))]
+    pub async fn check_deleted(
+        &self,
+        resource_id: &Uuid,
+        action: &str,
+        claims: &Claims,
+    ) -> Result<(), Error> {
+        if claims.adm {
+            return Ok(());
+        }
+        let row: (i64,) = sqlx::query_as(
+            r#"SELECT count(*)
+                FROM resources
+                LEFT JOIN permissions ON (resources.permission_parent_id = permissions.resource_id)
+                WHERE resources.resource_id = $1
+                AND resources.deleted_at IS NOT NULL
                 AND principal_id = ANY($2)
                 AND action = $3"#,
         )
@@ -244,6 +301,7 @@ impl Manager {
                 FROM resources
                 LEFT JOIN permissions ON (resources.permission_parent_id = permissions.resource_id)
                 WHERE resource_id = $1 
+                AND resources.deleted_at IS NULL
                 AND principal_id IN $2
                 AND action = $3"#,
         )
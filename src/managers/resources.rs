@@ -2,23 +2,31 @@ use super::errors::Error;
 
 use futures::{Stream, TryStreamExt};
 use sqlx::types::Uuid;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ops::Deref;
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 use tracing::Instrument;
 
-use sea_query::{Expr, Iden, PostgresQueryBuilder, Query};
+use sea_query::{Expr, Iden, PostgresQueryBuilder, Query, SelectStatement};
 sea_query::sea_query_driver_postgres!();
-use sea_query_driver_postgres::bind_query_as;
+use sea_query_driver_postgres::{bind_query, bind_query_as};
+use sqlx::postgres::PgRow;
+use sqlx::{FromRow, Row};
 
 use crate::api;
 use crate::managers;
+use crate::managers::schemas::{OnDelete, Reference};
 use crate::token::Claims;
 use crate::token::Context;
 
-use api::catalog::{Resource, ShareRequest};
+use api::catalog::{
+    comparison, resource_filter, AggregateFunction, AggregateResourcesResponse, Aggregation,
+    AggregationField, AggregationGroup, Comparison, ComparisonOperator, Resource,
+    ResourceAttribute, ResourceFilter, ResourceFilterList, ResourceTree, ShareRequest, WatchEvent,
+    WatchEventType,
+};
 
 #[derive(sqlx::FromRow)]
 struct ResourceRow {
@@ -31,6 +39,11 @@ struct ResourceRow {
     updated_at: chrono::DateTime<chrono::Utc>,
     data: serde_json::Value,
     labels: serde_json::Value,
+    deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    status: Option<serde_json::Value>,
+    generation: i64,
+    observed_generation: i64,
 }
 
 impl From<ResourceRow> for Resource {
@@ -55,17 +68,37 @@ impl From<ResourceRow> for Resource {
                 nanos: 0,
             }),
             data: serde_json::to_string(&r.data).unwrap_or_default(),
+            deleted_at: r.deleted_at.map(|deleted_at| prost_types::Timestamp {
+                seconds: deleted_at.timestamp(),
+                nanos: 0,
+            }),
+            highlight: String::new(),
+            expanded: HashMap::new(),
+            expires_at: r.expires_at.map(|expires_at| prost_types::Timestamp {
+                seconds: expires_at.timestamp(),
+                nanos: 0,
+            }),
+            status: serde_json::to_string(&r.status).unwrap_or_default(),
+            generation: r.generation,
+            observed_generation: r.observed_generation,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(sqlx::FromRow)]
+struct AggregationRow {
+    group_keys: serde_json::Value,
+    results: serde_json::Value,
+}
+
+#[derive(Debug, Clone)]
 pub struct Manager {
     pool: Arc<sqlx::PgPool>,
     permissions: Arc<managers::permissions::Manager>,
     schemas: Arc<managers::schemas::Manager>,
     events: Arc<managers::events::Manager>,
     locks: Arc<managers::locks::Manager>,
+    hooks: Arc<managers::hooks::Manager>,
 }
 
 #[derive(Debug)]
@@ -77,6 +110,100 @@ pub struct CreateOptions<'a> {
     pub data: &'a serde_json::Value,
     pub labels: &'a HashMap<String, String>,
     pub shares: &'a Vec<ShareRequest>,
+    /// defaults to now plus the `x-ttl` of the schema of the kind
+    pub expires_at: Option<&'a chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug)]
+pub struct ListOptions<'a> {
+    pub claims: &'a Claims,
+    pub labels: &'a HashMap<String, String>,
+    pub filter: &'a str,
+    pub kind: &'a str,
+    pub search_term: &'a str,
+    /// return ts_headline snippets of the text matching search_term
+    pub highlight: bool,
+    pub parent_id: Option<&'a Uuid>,
+    pub descendants_of: Option<&'a Uuid>,
+    pub max_depth: u32,
+    pub include_deleted: bool,
+    pub fields: &'a [String],
+    pub where_filter: Option<&'a ResourceFilter>,
+    /// parent_id, permission_parent_id or reference properties whose resources
+    /// are embedded into the listed resources
+    pub expand: &'a [String],
+}
+
+/// The data of a resource which passed the admission hooks, see admit.
+#[derive(Debug)]
+pub struct Admitted {
+    resource_id: Uuid,
+    data: serde_json::Value,
+}
+
+/// Describes how the data of a resource is changed by an update.
+#[derive(Debug)]
+pub enum Patch {
+    /// JSON merge patch (RFC 7396)
+    Merge(serde_json::Value),
+    /// JSON patch (RFC 6902)
+    Json(json_patch::Patch),
+    /// replaces the whole document
+    Replace(serde_json::Value),
+}
+
+impl Patch {
+    fn apply(&self, doc: &mut serde_json::Value) -> Result<(), Error> {
+        match self {
+            Patch::Merge(patch) => json_patch::merge(doc, patch),
+            Patch::Json(patch) => json_patch::patch(doc, patch)?,
+            Patch::Replace(data) => *doc = data.clone(),
+        };
+        Ok(())
+    }
+}
+
+// Number of listed resources whose references are expanded together.
+const EXPAND_BATCH_SIZE: usize = 100;
+
+// Number of times an update is reviewed by the admission hooks before it fails
+// because the resource keeps being modified concurrently.
+const UPDATE_ATTEMPTS: usize = 3;
+
+// The resources affected by deleting a resource: all deleted ones, the ones
+// among them deleted by cascading references and the referrers whose properties
+// are set to null.
+#[derive(Default, PartialEq)]
+struct DeletePlan {
+    deleted: BTreeSet<Uuid>,
+    cascaded: BTreeSet<Uuid>,
+    set_null: BTreeMap<Uuid, BTreeSet<String>>,
+}
+
+// Interval in which expired resources are deleted.
+const EXPIRE_INTERVAL_SECS: u64 = 10;
+
+// Interval in which resources whose retention window has passed are purged.
+const PURGE_INTERVAL_SECS: u64 = 60;
+
+// Selects the ids of all resources below the resource bound to `id`, limited to
+// the depth bound to `max_depth` (0 = unlimited). Root resources are their own
+// parent, which terminates the recursion.
+fn descendants_query(id: &str, max_depth: &str) -> String {
+    format!(
+        r#"WITH RECURSIVE descendants(resource_id, depth) AS (
+        SELECT resource_id, 1 FROM resources WHERE parent_id = {id}::UUID AND resource_id <> parent_id
+        UNION
+        SELECT resources.resource_id, descendants.depth + 1
+            FROM resources
+            JOIN descendants ON (resources.parent_id = descendants.resource_id)
+            WHERE resources.resource_id <> resources.parent_id
+            AND ({max_depth} = 0 OR descendants.depth < {max_depth})
+    )
+    SELECT resource_id FROM descendants"#,
+        id = id,
+        max_depth = max_depth
+    )
 }
 
 impl Manager {
@@ -86,6 +213,7 @@ impl Manager {
         schemas: Arc<managers::schemas::Manager>,
         events: Arc<managers::events::Manager>,
         locks: Arc<managers::locks::Manager>,
+        hooks: Arc<managers::hooks::Manager>,
     ) -> Result<Manager, Error> {
         let res = Manager {
             pool,
@@ -93,8 +221,42 @@ impl Manager {
             schemas,
             events,
             locks,
+            hooks,
         };
         res.init_tables().await?;
+
+        let pool = res.pool.clone();
+        tokio::spawn(
+            async move {
+                use tokio::time::{sleep, Duration};
+                loop {
+                    sleep(Duration::from_secs(PURGE_INTERVAL_SECS)).await;
+                    match purge_expired(&pool).await {
+                        Ok(0) => (),
+                        Ok(count) => log::info!("purged {} deleted resources", count),
+                        Err(err) => log::error!("failed to purge deleted resources: {}", err),
+                    }
+                }
+            }
+            .instrument(tracing::debug_span!("purge_deleted_resources")),
+        );
+
+        let mgr = res.clone();
+        tokio::spawn(
+            async move {
+                use tokio::time::{sleep, Duration};
+                loop {
+                    sleep(Duration::from_secs(EXPIRE_INTERVAL_SECS)).await;
+                    match mgr.delete_expired().await {
+                        Ok(0) => (),
+                        Ok(count) => log::info!("deleted {} expired resources", count),
+                        Err(err) => log::error!("failed to delete expired resources: {}", err),
+                    }
+                }
+            }
+            .instrument(tracing::debug_span!("delete_expired_resources")),
+        );
+
         Ok(res)
     }
 
@@ -111,25 +273,55 @@ impl Manager {
                 created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
                 updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
                 data JSONB,
-                labels JSONB,
-                data_vec TSVECTOR GENERATED ALWAYS AS (jsonb_to_tsvector('english', data, '["string"]')) STORED
+                labels JSONB
             );"#,
         )
         .execute(self.pool.deref())
         .await?;
 
+        sqlx::query("ALTER TABLE resources ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;")
+            .execute(self.pool.deref())
+            .await?;
+
+        // the resources deleted together are restored together
+        sqlx::query("ALTER TABLE resources ADD COLUMN IF NOT EXISTS deletion_id UUID;")
+            .execute(self.pool.deref())
+            .await?;
+
+        sqlx::query("ALTER TABLE resources ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;")
+            .execute(self.pool.deref())
+            .await?;
+
+        sqlx::query("ALTER TABLE resources ADD COLUMN IF NOT EXISTS status JSONB;")
+            .execute(self.pool.deref())
+            .await?;
+
+        sqlx::query(
+            "ALTER TABLE resources ADD COLUMN IF NOT EXISTS generation INT8 NOT NULL DEFAULT 1;",
+        )
+        .execute(self.pool.deref())
+        .await?;
+
         sqlx::query(
-            "CREATE INDEX IF NOT EXISTS resources_data_gin_idx ON resources USING GIN (data);",
+            "ALTER TABLE resources ADD COLUMN IF NOT EXISTS observed_generation INT8 NOT NULL DEFAULT 0;",
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS resources_expires_at_idx ON resources(expires_at) WHERE expires_at IS NOT NULL;",
         )
         .execute(self.pool.deref())
         .await?;
 
         sqlx::query(
-            "CREATE INDEX IF NOT EXISTS resources_data_vec_gin_idx ON resources USING GIN (data_vec);",
+            "CREATE INDEX IF NOT EXISTS resources_data_gin_idx ON resources USING GIN (data);",
         )
         .execute(self.pool.deref())
         .await?;
 
+        self.init_search().await?;
+
         sqlx::query(
             "CREATE INDEX IF NOT EXISTS resources_labels_gin_idx ON resources USING GIN (labels);",
         )
@@ -142,19 +334,147 @@ impl Manager {
         .execute(self.pool.deref())
         .await?;
 
+        // descendants are looked up recursively by their parent
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS resources_parent_id_idx ON resources(parent_id);",
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        Ok(())
+    }
+
+    // Sets up the full text search. The search vector of a resource is computed by
+    // a trigger using the `x-search` configuration in the schema of its kind.
+    #[tracing::instrument(name = "mgr::resources::init_search", skip(self))]
+    async fn init_search(&self) -> Result<(), Error> {
+        // replaced by search_vec, which is not hard-wired to the english config
+        sqlx::query("ALTER TABLE resources DROP COLUMN IF EXISTS data_vec;")
+            .execute(self.pool.deref())
+            .await?;
+
+        sqlx::query("ALTER TABLE resources ADD COLUMN IF NOT EXISTS search_vec TSVECTOR;")
+            .execute(self.pool.deref())
+            .await?;
+
+        sqlx::query(
+            r#"CREATE OR REPLACE FUNCTION resource_search_config(resource_kind TEXT) RETURNS REGCONFIG AS $$
+            DECLARE
+                config REGCONFIG;
+            BEGIN
+                SELECT (data->'x-search'->>'language')::REGCONFIG INTO config FROM schemas WHERE kind = resource_kind;
+                RETURN COALESCE(config, 'english'::REGCONFIG);
+            END;
+            $$ LANGUAGE plpgsql STABLE;"#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        sqlx::query(
+            r#"CREATE OR REPLACE FUNCTION resource_search_vector(resource_kind TEXT, resource_data JSONB) RETURNS TSVECTOR AS $$
+            DECLARE
+                config REGCONFIG := resource_search_config(resource_kind);
+                search JSONB;
+                types JSONB;
+                field JSONB;
+                result TSVECTOR := ''::TSVECTOR;
+            BEGIN
+                SELECT data->'x-search' INTO search FROM schemas WHERE kind = resource_kind;
+                types := COALESCE(search->'types', '["string"]'::JSONB);
+                IF jsonb_typeof(search->'fields') IS DISTINCT FROM 'array' THEN
+                    RETURN jsonb_to_tsvector(config, COALESCE(resource_data, 'null'::JSONB), types);
+                END IF;
+                FOR field IN SELECT * FROM jsonb_array_elements(search->'fields') LOOP
+                    result := result || setweight(
+                        jsonb_to_tsvector(config, COALESCE(resource_data #> string_to_array(field->>'path', '.'), 'null'::JSONB), types),
+                        COALESCE(field->>'weight', 'D')::"char"
+                    );
+                END LOOP;
+                RETURN result;
+            END;
+            $$ LANGUAGE plpgsql STABLE;"#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        // the searched strings of a resource, used to generate highlights
+        sqlx::query(
+            r#"CREATE OR REPLACE FUNCTION resource_search_text(resource_kind TEXT, resource_data JSONB) RETURNS TEXT AS $$
+            DECLARE
+                search JSONB;
+                paths TEXT[];
+            BEGIN
+                SELECT data->'x-search' INTO search FROM schemas WHERE kind = resource_kind;
+                IF jsonb_typeof(search->'fields') = 'array' THEN
+                    SELECT array_agg(field->>'path') INTO paths FROM jsonb_array_elements(search->'fields') AS field;
+                ELSE
+                    paths := ARRAY[''];
+                END IF;
+                RETURN (
+                    SELECT string_agg(value #>> '{}', ' ... ')
+                    FROM unnest(paths) AS path,
+                        jsonb_path_query(
+                            COALESCE(resource_data #> string_to_array(NULLIF(path, ''), '.'), resource_data),
+                            'strict $.** ? (@.type() == "string")'
+                        ) AS value
+                );
+            END;
+            $$ LANGUAGE plpgsql STABLE;"#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        sqlx::query(
+            r#"CREATE OR REPLACE FUNCTION resources_update_search_vec() RETURNS TRIGGER AS $$
+            BEGIN
+                NEW.search_vec := resource_search_vector(NEW.kind, NEW.data);
+                RETURN NEW;
+            END;
+            $$ LANGUAGE plpgsql;"#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        sqlx::query("DROP TRIGGER IF EXISTS resources_search_vec_trigger ON resources;")
+            .execute(self.pool.deref())
+            .await?;
+
+        sqlx::query(
+            r#"CREATE TRIGGER resources_search_vec_trigger
+            BEFORE INSERT OR UPDATE OF kind, data ON resources
+            FOR EACH ROW EXECUTE FUNCTION resources_update_search_vec();"#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        // resources created before the search_vec column existed
+        sqlx::query(
+            "UPDATE resources SET search_vec = resource_search_vector(kind, data) WHERE search_vec IS NULL;",
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS resources_search_vec_gin_idx ON resources USING GIN (search_vec);",
+        )
+        .execute(self.pool.deref())
+        .await?;
+
         Ok(())
     }
 
     #[tracing::instrument(name = "mgr::resources::create", skip(self))]
     pub async fn create(&self, opts: CreateOptions<'_>) -> Result<Resource, Error> {
-        let mut tx = self.pool.begin().await?;
+        let admitted = self.admit(&opts).await?;
 
-        let data = opts.data.to_owned();
+        let mut tx = self.pool.begin().await?;
 
-        let res = self.create_with_tx(opts, &mut tx).await?;
+        let res = self.create_with_tx(opts, admitted, &mut tx).await?;
 
         tx.commit().await?;
 
+        let data = serde_json::from_str(&res.data)?;
+
         self.events
             .publish(
                 &Claims::admin(),
@@ -169,21 +489,61 @@ impl Manager {
         Ok(res)
     }
 
+    /// Runs the data of a resource to be created through the admission hooks
+    /// and the schema of its kind. Hooks are called remotely, so this happens
+    /// before the transaction of create_with_tx is started.
+    #[tracing::instrument(name = "mgr::resources::admit", skip(self))]
+    pub async fn admit(&self, opts: &CreateOptions<'_>) -> Result<Admitted, Error> {
+        let resource_id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+
+        let review = managers::hooks::Review {
+            operation: api::catalog::EventType::Create,
+            resource_id: &resource_id,
+            kind: opts.kind,
+            old_data: None,
+            labels: opts.labels,
+            claims: &opts.context.claims,
+        };
+        let data = self.hooks.mutate(&review, opts.data).await?;
+        self.schemas.validate(opts.kind, &data).await?;
+        self.hooks.validate(&review, &data).await?;
+
+        Ok(Admitted { resource_id, data })
+    }
+
+    /// Creates the resource with the data returned by admit for the same
+    /// options.
     #[tracing::instrument(name = "mgr::resources::create_with_tx", skip(self))]
     pub async fn create_with_tx(
         &self,
         opts: CreateOptions<'_>,
+        admitted: Admitted,
         tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     ) -> Result<Resource, Error> {
-        self.schemas.validate(opts.kind, opts.data).await?;
+        let Admitted { resource_id, data } = admitted;
 
-        let resource_id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+        self.check_references(&opts.context.claims, opts.kind, &data, tx)
+            .await?;
 
         let now = chrono::Utc::now();
 
-        let data_str = serde_json::to_string(opts.data)?;
+        let data_str = serde_json::to_string(&data)?;
 
-        let res = Resource {
+        if let Some(parent_id) = opts.parent_id {
+            let row: (i64,) = sqlx::query_as(
+                "SELECT count(*) FROM resources WHERE resource_id = $1 AND deleted_at IS NULL",
+            )
+            .bind(parent_id)
+            .fetch_one(&mut *tx)
+            .await?;
+            if row.0 == 0 {
+                return Err(Error::InvalidArgument(
+                    "parent resource does not exist".to_string(),
+                ));
+            }
+        }
+
+        let mut res = Resource {
             id: resource_id.to_hyphenated().to_string(),
             parent_id: opts
                 .parent_id
@@ -207,22 +567,34 @@ impl Manager {
                 seconds: now.timestamp(),
                 nanos: 0,
             }),
+            deleted_at: None,
+            highlight: String::new(),
+            expanded: HashMap::new(),
+            expires_at: None,
+            status: serde_json::Value::Null.to_string(),
+            generation: 1,
+            observed_generation: 0,
         };
 
         let label_value = serde_json::to_value(opts.labels)?;
 
         // check fencing token
-        if let Some((lock_id, fencing_token)) = &opts.context.fencing_token {
+        if let Some(fencing_token) = &opts.context.fencing_token {
             let is_ok = self
                 .locks
-                .check_fencing_token(tx, lock_id, *fencing_token)
+                .check_fencing_token(tx, fencing_token, &opts.context.claims.sub)
                 .await?;
             if !is_ok {
                 return Err(Error::InvalidFencingToken);
             }
         }
 
-        sqlx::query("INSERT INTO resources(resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)").
+        let (expires_at,): (Option<chrono::DateTime<chrono::Utc>>,) = sqlx::query_as(
+            r#"INSERT INTO resources(resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, expires_at)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, COALESCE($10, (
+                SELECT $6 + make_interval(secs => (data->>'x-ttl')::FLOAT8) FROM schemas WHERE kind = $2 AND jsonb_typeof(data->'x-ttl') = 'number'
+            )))
+            RETURNING expires_at"#).
             bind(&resource_id).
             bind(&res.kind).
             bind(opts.parent_id.unwrap_or(&resource_id)).
@@ -230,9 +602,14 @@ impl Manager {
             bind(Uuid::parse_str(&res.creator_id)?).
             bind(now).
             bind(now).
-            bind(opts.data).
+            bind(&data).
             bind(&label_value).
-            execute(&mut *tx).await?;
+            bind(opts.expires_at).
+            fetch_one(&mut *tx).await?;
+        res.expires_at = expires_at.map(|expires_at| prost_types::Timestamp {
+            seconds: expires_at.timestamp(),
+            nanos: 0,
+        });
 
         match opts.permission_parent_id {
             Some(_) => {}
@@ -271,107 +648,862 @@ impl Manager {
 
     #[tracing::instrument(name = "mgr::resources::get", skip(self))]
     pub async fn get(&self, claims: &Claims, id: &Uuid) -> Result<Resource, Error> {
+        self.get_with_fields(claims, id, &[]).await
+    }
+
+    /// Like get, but only returns the given dot separated paths of the data.
+    #[tracing::instrument(name = "mgr::resources::get_with_fields", skip(self))]
+    pub async fn get_with_fields(
+        &self,
+        claims: &Claims,
+        id: &Uuid,
+        fields: &[String],
+    ) -> Result<Resource, Error> {
         self.permissions.check(id, "read", claims).await?;
 
-        let row: ResourceRow = sqlx::query_as(
-            "SELECT resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels FROM resources WHERE resource_id = $1",
-        )
-        .bind(id)
-        .fetch_one(self.pool.deref())
-        .await?;
+        // the sea-query statement is not Send, so it is dropped before awaiting
+        let (sql, values) = {
+            let mut query = Query::select()
+                .columns(vec![
+                    ResourcesTable::ResourceID,
+                    ResourcesTable::Kind,
+                    ResourcesTable::ParentID,
+                    ResourcesTable::PermissionParentID,
+                    ResourcesTable::CreatorID,
+                    ResourcesTable::CreatedAt,
+                    ResourcesTable::UpdatedAt,
+                    ResourcesTable::Labels,
+                    ResourcesTable::DeletedAt,
+                    ResourcesTable::ExpiresAt,
+                    ResourcesTable::Status,
+                    ResourcesTable::Generation,
+                    ResourcesTable::ObservedGeneration,
+                ])
+                .from(ResourcesTable::Table)
+                .and_where(Expr::cust_with_values(
+                    "resource_id = ?::UUID",
+                    vec![id.to_hyphenated().to_string()],
+                ))
+                .and_where(Expr::col(ResourcesTable::DeletedAt).is_null())
+                .to_owned();
+            match fields.is_empty() {
+                true => query.column(ResourcesTable::Data),
+                false => {
+                    let (sql, keys) = Projection::new(fields)?.to_sql("data");
+                    query.expr(Expr::cust_with_values(&format!("{} AS data", sql), keys))
+                }
+            };
+            query.build(PostgresQueryBuilder)
+        };
+        let row = bind_query_as(sqlx::query_as::<_, ResourceRow>(&sql), &values)
+            .fetch_one(self.pool.deref())
+            .await?;
 
         log::info!("got row");
 
         Ok(row.into())
     }
 
+    /// Embeds the resources referenced by the names in expand (parent_id,
+    /// permission_parent_id or properties declared with `x-reference`) into the
+    /// given resources. Referenced resources the caller may not read are left out.
+    #[tracing::instrument(name = "mgr::resources::expand", skip(self, resources))]
+    pub async fn expand(
+        &self,
+        claims: &Claims,
+        resources: &mut [Resource],
+        expand: &[String],
+    ) -> Result<(), Error> {
+        if expand.is_empty() {
+            return Ok(());
+        }
+        let references = self.schemas.references().await?;
+        validate_expand(&references, expand)?;
+        expand_resources(&self.pool, claims, &references, resources, expand).await
+    }
+
+    /// Returns a resource together with its descendants up to max_depth levels
+    /// below it (0 = unlimited). Descendants which the caller may not read are
+    /// omitted together with their subtrees.
+    #[tracing::instrument(name = "mgr::resources::tree", skip(self))]
+    pub async fn tree(
+        &self,
+        claims: &Claims,
+        id: &Uuid,
+        max_depth: u32,
+    ) -> Result<ResourceTree, Error> {
+        let root = self.get(claims, id).await?;
+
+        let principals = match claims.adm {
+            true => vec![],
+            false => claims.principals()?,
+        };
+
+        let sql = format!(
+            r#"SELECT DISTINCT ON (resources.resource_id) resources.resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation
+                FROM resources
+                LEFT JOIN permissions ON (resources.permission_parent_id = permissions.resource_id)
+                WHERE resources.resource_id IN ({})
+                AND resources.deleted_at IS NULL
+                AND ($3 OR (permissions.principal_id = ANY($4) AND permissions.action = 'read'))"#,
+            descendants_query("$1", "$2")
+        );
+        let rows: Vec<ResourceRow> = sqlx::query_as(&sql)
+            .bind(id)
+            .bind(max_depth as i64)
+            .bind(claims.adm)
+            .bind(&principals)
+            .fetch_all(self.pool.deref())
+            .await?;
+
+        let mut children: HashMap<String, Vec<Resource>> = HashMap::new();
+        for row in rows {
+            let res: Resource = row.into();
+            children.entry(res.parent_id.clone()).or_default().push(res);
+        }
+
+        Ok(build_tree(root, &mut children))
+    }
+
     #[tracing::instrument(name = "mgr::resources::delete", skip(self))]
     pub async fn delete(&self, context: &Context, id: &Uuid) -> Result<Resource, Error> {
-        let mut tx = self.pool.begin().await?;
-
         self.permissions.check(id, "write", &context.claims).await?;
 
         let old = self.get(&context.claims, id).await?;
 
-        // check fencing token
-        if let Some((lock_id, fencing_token)) = &context.fencing_token {
-            let is_ok = self
-                .locks
-                .check_fencing_token(&mut tx, lock_id, *fencing_token)
+        let plan = {
+            let mut conn = self.pool.acquire().await?;
+            self.plan_delete(&mut conn, id).await?
+        };
+
+        // the referrers are deleted or updated on behalf of the caller
+        for referrer in plan.cascaded.iter().chain(plan.set_null.keys()) {
+            self.permissions
+                .check(referrer, "write", &context.claims)
                 .await?;
-            if !is_ok {
-                return Err(Error::InvalidFencingToken);
-            }
         }
 
-        sqlx::query("DELETE FROM resources WHERE resource_id = $1")
-            .bind(&id)
-            .execute(&mut tx)
+        // clearing a reference is an update of the referrer, which is reviewed
+        // before the transaction since hooks are called remotely
+        let mut reviews = vec![];
+        for (referrer, properties) in plan.set_null.iter() {
+            let row: ResourceRow = sqlx::query_as(
+                "SELECT resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation FROM resources WHERE resource_id = $1 AND deleted_at IS NULL",
+            )
+            .bind(referrer)
+            .fetch_one(self.pool.deref())
             .await?;
+            let mut data = row.data.clone();
+            if let serde_json::Value::Object(object) = &mut data {
+                for property in properties {
+                    object.insert(property.clone(), serde_json::Value::Null);
+                }
+            }
+            let review = self
+                .review_update(context, &row, &Patch::Replace(data), &[], &HashMap::new())
+                .await?;
+            reviews.push((row, review));
+        }
 
-        tx.commit().await?;
+        let mut tx = self.begin_write(context).await?;
 
-        self.events
-            .publish(
-                &Claims::admin(),
-                id,
-                &old.kind,
-                &old.labels,
-                api::catalog::EventType::Delete,
-                &serde_json::from_str(&old.data)?,
+        if self.plan_delete(&mut tx, id).await? != plan {
+            return Err(Error::FailedPrecondition(
+                "resources were modified concurrently".to_string(),
+            ));
+        }
+
+        let now = chrono::Utc::now();
+        let mut updated = vec![];
+        for (row, (old_data, data, _)) in reviews {
+            let current = lock_row(&mut tx, &row.resource_id).await?;
+            if (current.generation, current.updated_at) != (row.generation, row.updated_at) {
+                return Err(Error::FailedPrecondition(
+                    "resource was modified concurrently".to_string(),
+                ));
+            }
+            self.check_references(&context.claims, &row.kind, &data, &mut tx)
+                .await?;
+            let row: ResourceRow = sqlx::query_as(
+                "UPDATE resources SET data = $2, updated_at = $3, generation = generation + $4 WHERE resource_id = $1 RETURNING resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation",
             )
+            .bind(row.resource_id)
+            .bind(&data)
+            .bind(now)
+            .bind(match data != old_data {
+                true => 1_i64,
+                false => 0,
+            })
+            .fetch_one(&mut tx)
             .await?;
+            updated.push(row);
+        }
+
+        // restoring the resource brings back exactly this batch
+        let deletion_id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+        let deleted: Vec<ResourceRow> = sqlx::query_as(
+            "UPDATE resources SET deleted_at = $2, deletion_id = $3 WHERE resource_id = ANY($1) AND deleted_at IS NULL RETURNING resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation",
+        )
+        .bind(plan.deleted.iter().cloned().collect::<Vec<_>>())
+        .bind(now)
+        .bind(deletion_id)
+        .fetch_all(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        // every deleted resource gets an event, so subscribers and watches
+        // don't have to look up the descendants of a root
+        for (rows, event_type) in [
+            (updated, api::catalog::EventType::Update),
+            (deleted, api::catalog::EventType::Delete),
+        ] {
+            for row in rows {
+                let res: Resource = row.into();
+                self.events
+                    .publish(
+                        &Claims::admin(),
+                        &Uuid::parse_str(&res.id)?,
+                        &res.kind,
+                        &res.labels,
+                        event_type,
+                        &serde_json::from_str(&res.data)?,
+                    )
+                    .await?;
+            }
+        }
 
         Ok(old)
     }
 
-    #[tracing::instrument(name = "mgr::resources::update", skip(self))]
-    pub async fn update(
+    // Collects the resources deleted together with a resource, i.e. its
+    // descendants and the resources cascading from them, and the references to
+    // them which are set to null. Fails if a reference restricts the deletion.
+    async fn plan_delete(
         &self,
-        context: &Context,
+        conn: &mut sqlx::PgConnection,
         id: &Uuid,
-        doc: &serde_json::Value,
-        labels: &HashMap<String, String>,
-    ) -> Result<Resource, Error> {
-        let mut tx = self.pool.begin().await?;
+    ) -> Result<DeletePlan, Error> {
+        let references = self.schemas.references().await?;
+        let mut plan = DeletePlan::default();
+        let mut pending = vec![*id];
+        while let Some(root) = pending.pop() {
+            let rows: Vec<(Uuid, String)> = sqlx::query_as(&format!(
+                "SELECT resource_id, kind FROM resources WHERE (resource_id = $1 OR resource_id IN ({})) AND deleted_at IS NULL",
+                descendants_query("$1", "0")
+            ))
+            .bind(root)
+            .fetch_all(&mut *conn)
+            .await?;
+            let rows: Vec<(Uuid, String)> = rows
+                .into_iter()
+                .filter(|(id, _)| plan.deleted.insert(*id))
+                .collect();
 
-        self.permissions.check(id, "write", &context.claims).await?;
+            for reference in references.iter() {
+                let targets: Vec<String> = rows
+                    .iter()
+                    .filter(|(_, kind)| match &reference.target_kind {
+                        Some(target_kind) => target_kind == kind,
+                        None => true,
+                    })
+                    .map(|(id, _)| id.to_hyphenated().to_string())
+                    .collect();
+                if targets.is_empty() {
+                    continue;
+                }
 
-        // check fencing token
-        if let Some((lock_id, fencing_token)) = &context.fencing_token {
-            let is_ok = self
-                .locks
-                .check_fencing_token(&mut tx, lock_id, *fencing_token)
+                let referrers: Vec<(Uuid,)> = sqlx::query_as(
+                    "SELECT resource_id FROM resources WHERE kind = $1 AND data->>$2 = ANY($3) AND deleted_at IS NULL",
+                )
+                .bind(&reference.kind)
+                .bind(&reference.property)
+                .bind(&targets)
+                .fetch_all(&mut *conn)
                 .await?;
-            if !is_ok {
-                return Err(Error::InvalidFencingToken);
+                let mut referrers = referrers
+                    .into_iter()
+                    .map(|(id,)| id)
+                    .filter(|id| !plan.deleted.contains(id));
+
+                match reference.on_delete {
+                    OnDelete::Restrict => {
+                        if let Some(referrer) = referrers.next() {
+                            return Err(Error::FailedPrecondition(format!(
+                                "resource is referenced by {} via {}",
+                                referrer.to_hyphenated(),
+                                reference.property
+                            )));
+                        }
+                    }
+                    OnDelete::Cascade => {
+                        for referrer in referrers {
+                            plan.cascaded.insert(referrer);
+                            pending.push(referrer);
+                        }
+                    }
+                    OnDelete::SetNull => {
+                        for referrer in referrers {
+                            plan.set_null
+                                .entry(referrer)
+                                .or_default()
+                                .insert(reference.property.clone());
+                        }
+                    }
+                }
             }
         }
 
-        let mut resource = self.get(&context.claims, id).await?;
+        // referrers which are deleted anyway are left as they are
+        let deleted = &plan.deleted;
+        plan.set_null.retain(|id, _| !deleted.contains(id));
+        Ok(plan)
+    }
 
-        let mut data = serde_json::from_str(&resource.data)?;
+    /// Deletes the resources whose expiry has passed like a delete by an admin,
+    /// so descendants and references are handled and events are published.
+    #[tracing::instrument(name = "mgr::resources::delete_expired", skip(self))]
+    pub async fn delete_expired(&self) -> Result<usize, Error> {
+        let ids: Vec<(Uuid,)> = sqlx::query_as(
+            "SELECT resource_id FROM resources WHERE expires_at <= now() AND deleted_at IS NULL ORDER BY expires_at",
+        )
+        .fetch_all(self.pool.deref())
+        .await?;
 
-        let now = chrono::Utc::now();
+        let context = Context {
+            claims: Claims::admin(),
+            fencing_token: None,
+        };
+        let mut count = 0;
+        for (id,) in ids {
+            match self.delete(&context, &id).await {
+                Ok(_) => count += 1,
+                // deleted together with an expired ancestor or referenced resource
+                Err(Error::NotFound) => (),
+                Err(err) => log::warn!(
+                    "failed to delete expired resource {}: {}",
+                    id.to_hyphenated(),
+                    err
+                ),
+            }
+        }
+        Ok(count)
+    }
 
-        json_patch::merge(&mut data, doc);
+    /// Streams the resources matching the filters of opts like list and then the
+    /// changes of the result set. Every event is checked against the filters and
+    /// the read permission of the caller.
+    #[tracing::instrument(name = "mgr::resources::watch", skip(self))]
+    pub async fn watch(
+        &self,
+        opts: ListOptions<'_>,
+    ) -> Result<
+        Pin<Box<impl Stream<Item = Result<WatchEvent, tonic::Status>> + Send + Sync + 'static>>,
+        tonic::Status,
+    > {
+        if opts.include_deleted {
+            return Err(tonic::Status::invalid_argument(
+                "deleted resources can't be watched",
+            ));
+        }
 
-        self.schemas.validate(&resource.kind, &data).await?;
+        let references = match opts.expand.is_empty() {
+            true => vec![],
+            false => self.schemas.references().await?,
+        };
+        validate_expand(&references, opts.expand)?;
 
-        let mut final_labels = resource.labels.clone();
-        if !labels.is_empty() {
-            // empty string marks label for removal
-            for (k, v) in labels.iter() {
-                if v.is_empty() {
-                    final_labels.remove(k);
-                } else {
-                    final_labels.insert(k.to_string(), v.to_string());
-                }
-            }
-            sqlx::query("UPDATE resources SET data = $1, updated_at = $2, labels = $3 WHERE resource_id = $4")
-                .bind(&data)
-                .bind(&now)
-                .bind(serde_json::to_value(&final_labels)?)
+        let mut watched = WatchedList::new(&opts);
+
+        // subscribe before the initial list, so no change is missed
+        let mut events = self.events.receiver();
+        let resources = watched.fetch(&self.pool, None).await?;
+
+        let (tx, rx) = mpsc::channel(4);
+        let pool = self.pool.clone();
+        tokio::spawn(
+            async move {
+                let mut changes = watched.apply(resources, None);
+                loop {
+                    // the expanded resources are resolved when a change is sent
+                    let mut resources: Vec<Resource> = changes
+                        .iter_mut()
+                        .filter_map(|change| change.resource.take())
+                        .collect();
+                    if let Err(err) = expand_resources(
+                        &pool,
+                        &watched.claims,
+                        &references,
+                        &mut resources,
+                        &watched.expand,
+                    )
+                    .await
+                    {
+                        log::error!("failed to expand resources: {}", err);
+                        let _ = tx.send(Err(err.into())).await;
+                        return;
+                    }
+                    let mut resources = resources.into_iter();
+                    for mut change in changes.drain(..) {
+                        if change.event_type != WatchEventType::Synced as i32 {
+                            change.resource = resources.next();
+                        }
+                        if tx.send(Ok(change)).await.is_err() {
+                            return;
+                        }
+                    }
+
+                    let event = tokio::select! {
+                        _ = tx.closed() => return,
+                        event = events.recv() => event,
+                    };
+                    let (resources, scope) = match event {
+                        Ok(event) => match watched.fetch_changed(&pool, &event).await {
+                            Ok(Some((resources, scope))) => (resources, Some(scope)),
+                            Ok(None) => continue,
+                            Err(err) => {
+                                log::error!("failed to fetch changed resources: {}", err);
+                                let _ = tx.send(Err(err.into())).await;
+                                return;
+                            }
+                        },
+                        Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                            log::info!("watch skipped {} events, resync", skipped);
+                            match watched.fetch(&pool, None).await {
+                                Ok(resources) => (resources, None),
+                                Err(err) => {
+                                    log::error!("failed to resync watch: {}", err);
+                                    let _ = tx.send(Err(err.into())).await;
+                                    return;
+                                }
+                            }
+                        }
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                    };
+                    changes = watched.apply(resources, scope.as_ref());
+                }
+            }
+            .instrument(tracing::info_span!("watch_resources")),
+        );
+
+        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+
+    /// Lists the readable resources which reference the given resource in a
+    /// property declared with `x-reference`.
+    #[tracing::instrument(name = "mgr::resources::list_referrers", skip(self))]
+    pub async fn list_referrers(
+        &self,
+        claims: &Claims,
+        id: &Uuid,
+    ) -> Result<
+        Pin<Box<impl Stream<Item = Result<Resource, tonic::Status>> + Send + Sync + 'static>>,
+        tonic::Status,
+    > {
+        let target = self.get(claims, id).await?;
+        let id = id.to_hyphenated().to_string();
+
+        let referrers = self
+            .schemas
+            .references()
+            .await?
+            .into_iter()
+            .filter(|reference| match &reference.target_kind {
+                Some(target_kind) => *target_kind == target.kind,
+                None => true,
+            })
+            .map(|reference| {
+                let comparisons = vec![
+                    Comparison {
+                        field: Some(comparison::Field::Attribute(ResourceAttribute::Kind as i32)),
+                        operator: ComparisonOperator::Eq as i32,
+                        value: serde_json::Value::from(reference.kind).to_string(),
+                    },
+                    Comparison {
+                        field: Some(comparison::Field::Path(reference.property)),
+                        operator: ComparisonOperator::Eq as i32,
+                        value: serde_json::Value::from(id.clone()).to_string(),
+                    },
+                ];
+                ResourceFilter {
+                    filter: Some(resource_filter::Filter::And(ResourceFilterList {
+                        filters: comparisons
+                            .into_iter()
+                            .map(|comparison| ResourceFilter {
+                                filter: Some(resource_filter::Filter::Comparison(comparison)),
+                            })
+                            .collect(),
+                    })),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // an empty IN list matches nothing
+        let where_filter = match referrers.is_empty() {
+            true => ResourceFilter {
+                filter: Some(resource_filter::Filter::Comparison(Comparison {
+                    field: Some(comparison::Field::Attribute(ResourceAttribute::Kind as i32)),
+                    operator: ComparisonOperator::In as i32,
+                    value: "[]".to_string(),
+                })),
+            },
+            false => ResourceFilter {
+                filter: Some(resource_filter::Filter::Or(ResourceFilterList {
+                    filters: referrers,
+                })),
+            },
+        };
+
+        self.list(ListOptions {
+            claims,
+            labels: &HashMap::new(),
+            filter: "",
+            kind: "",
+            search_term: "",
+            highlight: false,
+            parent_id: None,
+            descendants_of: None,
+            max_depth: 0,
+            include_deleted: false,
+            fields: &[],
+            where_filter: Some(&where_filter),
+            expand: &[],
+        })
+        .await
+    }
+
+    // Checks that the references declared in the schema of kind point to
+    // existing resources of the declared kind which the caller may read.
+    #[tracing::instrument(name = "mgr::resources::check_references", skip(self, tx))]
+    async fn check_references(
+        &self,
+        claims: &Claims,
+        kind: &str,
+        data: &serde_json::Value,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), Error> {
+        for reference in self.schemas.references().await? {
+            if reference.kind != kind {
+                continue;
+            }
+            let value = match data.get(&reference.property) {
+                None | Some(serde_json::Value::Null) => continue,
+                Some(value) => value,
+            };
+            let target = match value.as_str().map(Uuid::parse_str) {
+                Some(Ok(target)) => target,
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "{} must be a resource id",
+                        reference.property
+                    )))
+                }
+            };
+
+            let missing = Error::InvalidArgument(format!(
+                "{} references a resource which does not exist",
+                reference.property
+            ));
+            if self
+                .permissions
+                .check(&target, "read", claims)
+                .await
+                .is_err()
+            {
+                return Err(missing);
+            }
+            let row: Option<(String,)> = sqlx::query_as(
+                "SELECT kind FROM resources WHERE resource_id = $1 AND deleted_at IS NULL",
+            )
+            .bind(target)
+            .fetch_optional(&mut *tx)
+            .await?;
+            match (row, &reference.target_kind) {
+                (None, _) => return Err(missing),
+                (Some((target_kind,)), Some(expected)) if target_kind != *expected => {
+                    return Err(Error::InvalidArgument(format!(
+                        "{} must reference a resource of kind {}",
+                        reference.property, expected
+                    )))
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Groups the readable resources matching the filters of opts and runs the
+    /// aggregations on every group.
+    #[tracing::instrument(name = "mgr::resources::aggregate", skip(self))]
+    pub async fn aggregate(
+        &self,
+        opts: ListOptions<'_>,
+        group_by: &[AggregationField],
+        aggregations: &[Aggregation],
+    ) -> Result<AggregateResourcesResponse, Error> {
+        if aggregations.is_empty() {
+            return Err(Error::InvalidArgument(
+                "at least one aggregation is required".to_string(),
+            ));
+        }
+
+        // the sea-query statements are not Send, so they are dropped before awaiting
+        let (sql, values) = {
+            // the readable resources matching the filters
+            let mut resources = Query::select()
+                .expr(Expr::cust(
+                    "DISTINCT ON(resources.resource_id) resources.resource_id",
+                ))
+                .columns(vec![
+                    (ResourcesTable::Table, ResourcesTable::Data),
+                    (ResourcesTable::Table, ResourcesTable::Labels),
+                ])
+                .to_owned();
+            apply_filters(&mut resources, &opts)?;
+
+            // the group keys are computed in a subquery so they can be grouped by name
+            let mut groups = Query::select()
+                .columns(vec![ResourcesTable::Data, ResourcesTable::Labels])
+                .from_subquery(resources, ResourcesTable::SubQuery)
+                .to_owned();
+            let mut group_names = vec![];
+            for (i, field) in group_by.iter().enumerate() {
+                let (sql, values) = match field_sql(field)? {
+                    Some(field) => field,
+                    None => {
+                        return Err(Error::InvalidArgument(
+                            "group by fields need a path or label".to_string(),
+                        ))
+                    }
+                };
+                groups.expr(Expr::cust_with_values(
+                    &format!("{} AS g{}", sql, i),
+                    values,
+                ));
+                group_names.push(format!("g{}", i));
+            }
+
+            // the aggregated values are selected in the same subquery
+            let mut results = vec![];
+            for (i, aggregation) in aggregations.iter().enumerate() {
+                let name = format!("a{}", i);
+                let (sql, field) = aggregation_sql(aggregation, &name)?;
+                if let Some((field, values)) = field {
+                    groups.expr(Expr::cust_with_values(
+                        &format!("{} AS {}", field, name),
+                        values,
+                    ));
+                }
+                results.push(sql);
+            }
+
+            let group_keys = match group_names.is_empty() {
+                true => "'[]'::JSONB".to_string(),
+                false => format!("jsonb_build_array({})", group_names.join(", ")),
+            };
+            let mut query = Query::select()
+                .expr(Expr::cust(&format!("{} AS group_keys", group_keys)))
+                .expr(Expr::cust(&format!(
+                    "jsonb_build_array({}) AS results",
+                    results.join(", ")
+                )))
+                .from_subquery(groups, ResourcesTable::SubQuery)
+                .to_owned();
+            if !group_names.is_empty() {
+                query
+                    .add_group_by(group_names.iter().map(|name| Expr::cust(name)))
+                    .order_by_expr(Expr::cust("group_keys"), sea_query::Order::Asc);
+            }
+            query.build(PostgresQueryBuilder)
+        };
+        let rows = bind_query_as(sqlx::query_as::<_, AggregationRow>(&sql), &values)
+            .fetch_all(self.pool.deref())
+            .await?;
+
+        let to_strings = |value: serde_json::Value| match value {
+            serde_json::Value::Array(items) => items.iter().map(|item| item.to_string()).collect(),
+            _ => vec![],
+        };
+        Ok(AggregateResourcesResponse {
+            groups: rows
+                .into_iter()
+                .map(|row| AggregationGroup {
+                    keys: to_strings(row.group_keys),
+                    values: to_strings(row.results),
+                })
+                .collect(),
+        })
+    }
+
+    /// Restores a deleted resource together with the resources which were
+    /// deleted along with it, i.e. its descendants and the resources deleted by
+    /// cascading references. A create event is published for every restored
+    /// resource.
+    #[tracing::instrument(name = "mgr::resources::restore", skip(self))]
+    pub async fn restore(&self, context: &Context, id: &Uuid) -> Result<Resource, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        self.permissions
+            .check_deleted(id, "write", &context.claims)
+            .await?;
+
+        // check fencing token
+        if let Some(fencing_token) = &context.fencing_token {
+            let is_ok = self
+                .locks
+                .check_fencing_token(&mut tx, fencing_token, &context.claims.sub)
+                .await?;
+            if !is_ok {
+                return Err(Error::InvalidFencingToken);
+            }
+        }
+
+        let (deletion_id,): (Option<Uuid>,) = sqlx::query_as(
+            "SELECT deletion_id FROM resources WHERE resource_id = $1 AND deleted_at IS NOT NULL",
+        )
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await?;
+
+        // restored resources whose expiry has passed are kept, not deleted again
+        let rows: Vec<ResourceRow> = sqlx::query_as(
+            r#"UPDATE resources SET deleted_at = NULL, deletion_id = NULL, expires_at = CASE WHEN expires_at <= now() THEN NULL ELSE expires_at END
+            WHERE (resource_id = $1 OR deletion_id = $2) AND deleted_at IS NOT NULL
+            RETURNING resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation"#,
+        )
+        .bind(id)
+        .bind(deletion_id)
+        .fetch_all(&mut tx)
+        .await?;
+
+        // a resource of the batch may have been the child of a resource which
+        // was deleted on its own later on
+        let (orphaned,): (bool,) = sqlx::query_as(
+            r#"SELECT EXISTS (
+                SELECT 1 FROM resources r JOIN resources p ON (p.resource_id = r.parent_id)
+                WHERE r.resource_id = ANY($1) AND p.deleted_at IS NOT NULL
+            )"#,
+        )
+        .bind(rows.iter().map(|row| row.resource_id).collect::<Vec<_>>())
+        .fetch_one(&mut tx)
+        .await?;
+        if orphaned {
+            return Err(Error::InvalidArgument(
+                "the parent resource is deleted, restore it first".to_string(),
+            ));
+        }
+
+        tx.commit().await?;
+
+        let mut restored = None;
+        for row in rows {
+            let res: Resource = row.into();
+            self.events
+                .publish(
+                    &Claims::admin(),
+                    &Uuid::parse_str(&res.id)?,
+                    &res.kind,
+                    &res.labels,
+                    api::catalog::EventType::Create,
+                    &serde_json::from_str(&res.data)?,
+                )
+                .await?;
+            if res.id == id.to_hyphenated().to_string() {
+                restored = Some(res);
+            }
+        }
+
+        restored.ok_or(Error::NotFound)
+    }
+
+    /// Permanently removes a deleted resource and its descendants.
+    #[tracing::instrument(name = "mgr::resources::purge", skip(self))]
+    pub async fn purge(&self, context: &Context, id: &Uuid) -> Result<Resource, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        self.permissions
+            .check_deleted(id, "write", &context.claims)
+            .await?;
+
+        // check fencing token
+        if let Some(fencing_token) = &context.fencing_token {
+            let is_ok = self
+                .locks
+                .check_fencing_token(&mut tx, fencing_token, &context.claims.sub)
+                .await?;
+            if !is_ok {
+                return Err(Error::InvalidFencingToken);
+            }
+        }
+
+        let row: ResourceRow = sqlx::query_as(
+            "DELETE FROM resources WHERE resource_id = $1 AND deleted_at IS NOT NULL RETURNING resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation",
+        )
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await
+        .map_err(purge_error)?;
+
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+
+    #[tracing::instrument(name = "mgr::resources::update", skip(self))]
+    pub async fn update(
+        &self,
+        context: &Context,
+        id: &Uuid,
+        patch: &Patch,
+        update_mask: &[String],
+        labels: &HashMap<String, String>,
+        expires_at: Option<Option<chrono::DateTime<chrono::Utc>>>,
+    ) -> Result<Resource, Error> {
+        self.permissions.check(id, "write", &context.claims).await?;
+
+        // lock the row so concurrent patches are applied one after another
+        let mut tx = self.begin_write(context).await?;
+        let mut row = lock_row(&mut tx, id).await?;
+
+        // hooks are called remotely, so the lock is not held meanwhile and the
+        // update is reviewed again if the resource changed before it is retaken
+        let remote = self.hooks.exists(&row.kind).await?;
+        let mut attempts = 1;
+        let (old_data, data, final_labels) = loop {
+            if !remote {
+                break self
+                    .review_update(context, &row, patch, update_mask, labels)
+                    .await?;
+            }
+            tx.rollback().await?;
+            let review = self
+                .review_update(context, &row, patch, update_mask, labels)
+                .await?;
+            tx = self.begin_write(context).await?;
+            let current = lock_row(&mut tx, id).await?;
+            if (current.generation, current.updated_at) == (row.generation, row.updated_at) {
+                break review;
+            }
+            if attempts == UPDATE_ATTEMPTS {
+                return Err(Error::FailedPrecondition(
+                    "resource was modified concurrently".to_string(),
+                ));
+            }
+            attempts += 1;
+            row = current;
+        };
+        let mut resource: Resource = row.into();
+
+        self.check_references(&context.claims, &resource.kind, &data, &mut tx)
+            .await?;
+
+        let now = chrono::Utc::now();
+
+        if !labels.is_empty() {
+            sqlx::query("UPDATE resources SET data = $1, updated_at = $2, labels = $3 WHERE resource_id = $4")
+                .bind(&data)
+                .bind(&now)
+                .bind(serde_json::to_value(&final_labels)?)
                 .bind(id)
                 .execute(&mut tx)
                 .await?;
@@ -384,6 +1516,29 @@ impl Manager {
                 .await?;
         }
 
+        // None keeps the expiry, Some(None) removes it
+        if let Some(expires_at) = expires_at {
+            sqlx::query("UPDATE resources SET expires_at = $1 WHERE resource_id = $2")
+                .bind(expires_at)
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+            resource.expires_at = expires_at.map(|expires_at| prost_types::Timestamp {
+                seconds: expires_at.timestamp(),
+                nanos: 0,
+            });
+        }
+
+        // the generation tells controllers whether their status is up to date,
+        // so only changes of the data count
+        if data != old_data {
+            sqlx::query("UPDATE resources SET generation = generation + 1 WHERE resource_id = $1")
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+            resource.generation += 1;
+        }
+
         resource.data = serde_json::to_string(&data)?;
         resource.labels = final_labels;
         resource.updated_at = Some(prost_types::Timestamp {
@@ -407,169 +1562,291 @@ impl Manager {
         Ok(resource)
     }
 
-    #[tracing::instrument(name = "mgr::resources::list", skip(self))]
-    pub async fn list(
+    // Computes the data and labels of the resource after the update and runs
+    // them through the admission hooks and the schema of its kind.
+    async fn review_update(
         &self,
-        claims: &Claims,
+        context: &Context,
+        row: &ResourceRow,
+        patch: &Patch,
+        update_mask: &[String],
         labels: &HashMap<String, String>,
-        filter: &str,
-        kind: &str,
-        search_term: &str,
-    ) -> Result<
-        Pin<Box<impl Stream<Item = Result<Resource, tonic::Status>> + Send + Sync + 'static>>,
-        tonic::Status,
-    > {
-        let (tx, rx) = mpsc::channel(4);
-        let mut transaction = match self.pool.begin().await {
-            Ok(val) => val,
-            Err(err) => {
-                return Err(tonic::Status::internal(format!(
-                    "failed to start transaction: {}",
-                    err
-                )));
+    ) -> Result<(serde_json::Value, serde_json::Value, HashMap<String, String>), Error> {
+        let old_data = row.data.clone();
+        let mut data = old_data.clone();
+
+        patch.apply(&mut data)?;
+
+        if !update_mask.is_empty() {
+            data = apply_update_mask(&old_data, &data, update_mask)?;
+        }
+
+        let mut final_labels: HashMap<String, String> =
+            serde_json::from_value(row.labels.clone()).unwrap_or_default();
+        // empty string marks label for removal
+        for (k, v) in labels.iter() {
+            if v.is_empty() {
+                final_labels.remove(k);
+            } else {
+                final_labels.insert(k.to_string(), v.to_string());
             }
+        }
+
+        let review = managers::hooks::Review {
+            operation: api::catalog::EventType::Update,
+            resource_id: &row.resource_id,
+            kind: &row.kind,
+            old_data: Some(&old_data),
+            labels: &final_labels,
+            claims: &context.claims,
         };
+        let data = self.hooks.mutate(&review, &data).await?;
+        self.schemas.validate(&row.kind, &data).await?;
+        self.hooks.validate(&review, &data).await?;
 
-        let mut query = Query::select();
+        Ok((old_data, data, final_labels))
+    }
 
-        let span = tracing::debug_span!("prepare");
+    // Starts the transaction of a write, checking the fencing token passed
+    // along with it.
+    async fn begin_write(
+        &self,
+        context: &Context,
+    ) -> Result<sqlx::Transaction<'static, sqlx::Postgres>, Error> {
+        let mut tx = self.pool.begin().await?;
 
-        span.in_scope(|| {
-            let principals = claims.principals()?;
+        // check fencing token
+        if let Some(fencing_token) = &context.fencing_token {
+            let is_ok = self
+                .locks
+                .check_fencing_token(&mut tx, fencing_token, &context.claims.sub)
+                .await?;
+            if !is_ok {
+                return Err(Error::InvalidFencingToken);
+            }
+        }
+        Ok(tx)
+    }
 
-            query = query
-                .expr(sea_query::Expr::cust(
-                    "DISTINCT ON(resources.resource_id) resources.resource_id",
-                ))
-                .columns(vec![
-                    (ResourcesTable::Table, ResourcesTable::Kind),
-                    (ResourcesTable::Table, ResourcesTable::ParentID),
-                    (ResourcesTable::Table, ResourcesTable::PermissionParentID),
-                    (ResourcesTable::Table, ResourcesTable::CreatorID),
-                    (ResourcesTable::Table, ResourcesTable::CreatedAt),
-                    (ResourcesTable::Table, ResourcesTable::UpdatedAt),
-                    (ResourcesTable::Table, ResourcesTable::Data),
-                    (ResourcesTable::Table, ResourcesTable::Labels),
-                ])
-                .from(ResourcesTable::Table)
-                .join(
-                    sea_query::JoinType::LeftJoin,
-                    PermissionsTable::Table,
-                    Expr::tbl(ResourcesTable::Table, ResourcesTable::PermissionParentID)
-                        .equals(PermissionsTable::Table, PermissionsTable::ResourceID),
-                )
-                .order_by(
-                    (ResourcesTable::Table, ResourcesTable::ResourceID),
-                    sea_query::Order::Desc,
-                )
-                .order_by(
-                    (ResourcesTable::Table, ResourcesTable::CreatedAt),
-                    sea_query::Order::Desc,
-                )
-                .to_owned();
+    /// Replaces the status of the resource within the transaction, the change is
+    /// published once it commits. It is meant for subsystems which keep their
+    /// state in the status of resources, so it doesn't check permissions.
+    pub async fn set_status_with_tx(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        id: &Uuid,
+        status: &serde_json::Value,
+    ) -> Result<(), Error> {
+        let row: Option<(String, serde_json::Value)> = sqlx::query_as(
+            "UPDATE resources SET status = $1 WHERE resource_id = $2 RETURNING kind, labels",
+        )
+        .bind(status)
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let (kind, labels) = row.ok_or(Error::NotFound)?;
+        let labels: HashMap<String, String> = serde_json::from_value(labels)?;
 
-            if !claims.adm {
-                query = query
-                    .and_where(
-                        Expr::tbl(PermissionsTable::Table, PermissionsTable::PrincipalID)
-                            .is_in(principals),
-                    )
-                    .and_where(
-                        Expr::tbl(PermissionsTable::Table, PermissionsTable::Action).eq("read"),
-                    )
-                    .to_owned();
-            }
+        self.events
+            .publish_with_tx(
+                tx,
+                &Claims::admin(),
+                id,
+                &kind,
+                &labels,
+                api::catalog::EventType::StatusUpdate,
+                status,
+            )
+            .await?;
+        Ok(())
+    }
 
-            if !labels.is_empty() {
-                let labels_value = match serde_json::to_value(&labels) {
-                    Ok(val) => val,
-                    Err(err) => {
-                        return Err(tonic::Status::internal(format!(
-                            "failed to encode resource labels: {}",
-                            err
-                        )));
-                    }
-                };
-                query = query
-                    .and_where(sea_query::Expr::cust_with_values(
-                        "labels @> ?",
-                        vec![labels_value],
-                    ))
-                    .to_owned();
-            }
+    /// Applies the patch to the status of the resource, which requires the
+    /// "status" permission. The status bypasses hooks and schema validation, it
+    /// is reported by controllers instead of being written by users.
+    #[tracing::instrument(name = "mgr::resources::update_status", skip(self))]
+    pub async fn update_status(
+        &self,
+        context: &Context,
+        id: &Uuid,
+        patch: &Patch,
+        observed_generation: i64,
+    ) -> Result<Resource, Error> {
+        let mut tx = self.pool.begin().await?;
 
-            if !filter.is_empty() {
-                query = query
-                    .and_where(Expr::cust_with_values(
-                        "data @@ (?::JSONPATH)",
-                        vec![filter],
-                    ))
-                    .to_owned();
-            }
+        self.permissions
+            .check(id, "status", &context.claims)
+            .await?;
 
-            if !kind.is_empty() {
-                query = query
-                    .and_where(Expr::tbl(ResourcesTable::Table, ResourcesTable::Kind).eq(kind))
-                    .to_owned();
+        // check fencing token
+        if let Some(fencing_token) = &context.fencing_token {
+            let is_ok = self
+                .locks
+                .check_fencing_token(&mut tx, fencing_token, &context.claims.sub)
+                .await?;
+            if !is_ok {
+                return Err(Error::InvalidFencingToken);
             }
+        }
+
+        let row: ResourceRow = sqlx::query_as(
+            "SELECT resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation FROM resources WHERE resource_id = $1 AND deleted_at IS NULL FOR UPDATE",
+        )
+        .bind(id)
+        .fetch_one(&mut tx)
+        .await?;
+        let mut resource: Resource = row.into();
+
+        if observed_generation < 0 || observed_generation > resource.generation {
+            return Err(Error::InvalidArgument(format!(
+                "observed_generation {} is not a generation of the resource, it is at {}",
+                observed_generation, resource.generation
+            )));
+        }
+        // reports of controllers which lag behind must not override newer ones
+        if observed_generation > 0 && observed_generation < resource.observed_generation {
+            return Err(Error::FailedPrecondition(format!(
+                "observed_generation {} is older than the observed generation {} of the resource",
+                observed_generation, resource.observed_generation
+            )));
+        }
+        if observed_generation > 0 {
+            resource.observed_generation = observed_generation;
+        }
+
+        let mut status: serde_json::Value = serde_json::from_str(&resource.status)?;
+        patch.apply(&mut status)?;
+
+        // updated_at stays, together with the generation it guards updates of
+        // the data against concurrent changes which status reports are not
+        sqlx::query("UPDATE resources SET status = $1, observed_generation = $2 WHERE resource_id = $3")
+            .bind(&status)
+            .bind(resource.observed_generation)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+
+        resource.status = serde_json::to_string(&status)?;
+
+        self.events
+            .publish_with_tx(
+                &mut tx,
+                &Claims::admin(),
+                id,
+                &resource.kind,
+                &resource.labels,
+                api::catalog::EventType::StatusUpdate,
+                &status,
+            )
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(resource)
+    }
+
+    #[tracing::instrument(name = "mgr::resources::list", skip(self))]
+    pub async fn list(
+        &self,
+        opts: ListOptions<'_>,
+    ) -> Result<
+        Pin<Box<impl Stream<Item = Result<Resource, tonic::Status>> + Send + Sync + 'static>>,
+        tonic::Status,
+    > {
+        if opts.include_deleted && !opts.claims.adm {
+            return Err(tonic::Status::permission_denied(
+                "only admins are allowed to list deleted resources",
+            ));
+        }
 
-            if !search_term.is_empty() {
-                query = query
-                    .and_where(Expr::cust_with_values(
-                        "data_vec @@ (websearch_to_tsquery(?))",
-                        vec![search_term],
-                    ))
-                    .to_owned();
+        let (tx, rx) = mpsc::channel(4);
+        let mut transaction = match self.pool.begin().await {
+            Ok(val) => val,
+            Err(err) => {
+                return Err(tonic::Status::internal(format!(
+                    "failed to start transaction: {}",
+                    err
+                )));
             }
+        };
 
-            query = Query::select()
-                .expr(sea_query::Expr::cust("*"))
-                .from_subquery(query.clone(), ResourcesTable::SubQuery)
-                .order_by(
-                    (ResourcesTable::SubQuery, ResourcesTable::CreatedAt),
-                    sea_query::Order::Desc,
-                )
-                .to_owned();
+        let span = tracing::debug_span!("prepare");
+        let (sql, values) = span
+            .in_scope(|| list_query(&opts))?
+            .build(PostgresQueryBuilder);
 
-            Ok(())
-        })?;
+        let references = match opts.expand.is_empty() {
+            true => vec![],
+            false => self.schemas.references().await?,
+        };
+        validate_expand(&references, opts.expand)?;
+        // resources are expanded in batches to resolve their references together
+        let batch_size = match opts.expand.is_empty() {
+            true => 1,
+            false => EXPAND_BATCH_SIZE,
+        };
+        let expand = opts.expand.to_vec();
+        let claims = opts.claims.clone();
+        let pool = self.pool.clone();
 
         let span = tracing::info_span!("fetch_rows");
-        let (sql, values) = query.build(PostgresQueryBuilder);
+        let highlight = opts.highlight && !opts.search_term.is_empty();
         tokio::spawn(
             async move {
-                let mut rows = bind_query_as(sqlx::query_as::<_, ResourceRow>(&sql), &values)
-                    .fetch(&mut transaction);
+                let mut rows = bind_query(sqlx::query(&sql), &values).fetch(&mut transaction);
+                let mut batch = Vec::with_capacity(batch_size);
                 loop {
-                    let row: ResourceRow = match rows
+                    let row: Option<PgRow> = match rows
                         .try_next()
                         .instrument(tracing::debug_span!("fetch_row"))
                         .await
                     {
-                        Ok(row) => match row {
-                            Some(row) => row,
-                            None => {
-                                break;
-                            }
-                        },
+                        Ok(row) => row,
                         Err(err) => {
                             log::error!("failed to parse row: {:?}", err);
                             return Err(());
                         }
                     };
 
-                    let res: Resource = row.into();
+                    if let Some(row) = &row {
+                        match resource_from_row(row, highlight) {
+                            Ok(res) => batch.push(res),
+                            Err(err) => {
+                                log::error!("failed to parse row: {:?}", err);
+                                return Err(());
+                            }
+                        };
+                        if batch.len() < batch_size {
+                            continue;
+                        }
+                    }
 
-                    match tx
-                        .send(Ok(res))
-                        .instrument(tracing::debug_span!("send_resource"))
-                        .await
+                    if let Err(err) =
+                        expand_resources(&pool, &claims, &references, &mut batch, &expand)
+                            .instrument(tracing::debug_span!("expand_resources"))
+                            .await
                     {
-                        Ok(_) => (),
-                        Err(_) => {
-                            return Err(());
-                        }
-                    };
+                        log::error!("failed to expand resources: {}", err);
+                        let _ = tx.send(Err(err.into())).await;
+                        return Err(());
+                    }
+
+                    for res in batch.drain(..) {
+                        match tx
+                            .send(Ok(res))
+                            .instrument(tracing::debug_span!("send_resource"))
+                            .await
+                        {
+                            Ok(_) => (),
+                            Err(_) => {
+                                return Err(());
+                            }
+                        };
+                    }
+
+                    if row.is_none() {
+                        break;
+                    }
                 }
                 drop(rows);
                 match transaction
@@ -588,6 +1865,1095 @@ impl Manager {
     }
 }
 
+// Locks the row of the resource for the rest of the transaction.
+async fn lock_row(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    id: &Uuid,
+) -> Result<ResourceRow, Error> {
+    let row = sqlx::query_as(
+        "SELECT resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation FROM resources WHERE resource_id = $1 AND deleted_at IS NULL FOR UPDATE",
+    )
+    .bind(id)
+    .fetch_one(tx)
+    .await?;
+    Ok(row)
+}
+
+// Purges deleted resources of all kinds whose schema declares a retention window
+// (`x-retention`, in seconds) which has passed. Resources are purged one by one,
+// so one which can't be purged yet doesn't hold back the others.
+async fn purge_expired(pool: &sqlx::PgPool) -> Result<u64, Error> {
+    let ids: Vec<(Uuid,)> = sqlx::query_as(
+        r#"SELECT resources.resource_id FROM resources JOIN schemas ON (resources.kind = schemas.kind)
+            WHERE resources.deleted_at IS NOT NULL
+            AND jsonb_typeof(schemas.data->'x-retention') = 'number'
+            AND resources.deleted_at < now() - make_interval(secs => (schemas.data->>'x-retention')::FLOAT8)
+            ORDER BY resources.deleted_at"#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut count = 0;
+    for (id,) in ids {
+        // descendants are purged along with their parent
+        match sqlx::query("DELETE FROM resources WHERE resource_id = $1 AND deleted_at IS NOT NULL")
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(purge_error)
+        {
+            Ok(res) => count += res.rows_affected(),
+            Err(err) => log::warn!(
+                "failed to purge deleted resource {}: {}",
+                id.to_hyphenated(),
+                err
+            ),
+        }
+    }
+    Ok(count)
+}
+
+// Maps the foreign key violation raised when a purged resource is still the
+// permission parent of another resource.
+fn purge_error(err: sqlx::Error) -> Error {
+    match err.as_database_error().and_then(|err| err.code()) {
+        Some(code) if code == "23503" => Error::FailedPrecondition(
+            "resource is the permission parent of other resources".to_string(),
+        ),
+        _ => err.into(),
+    }
+}
+
+// Checks that every name in expand is parent_id, permission_parent_id or a
+// property declared with x-reference.
+fn validate_expand(references: &[Reference], expand: &[String]) -> Result<(), Error> {
+    for name in expand {
+        if name == "parent_id" || name == "permission_parent_id" {
+            continue;
+        }
+        if !references
+            .iter()
+            .any(|reference| reference.property == *name)
+        {
+            return Err(Error::InvalidArgument(format!(
+                "can't expand {}: must be parent_id, permission_parent_id or a property declared with x-reference",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Fills the expanded map of the resources with the readable resources referenced
+// by the names in expand. A reference property is only expanded for the kinds
+// declaring it, projected out properties are not expanded.
+async fn expand_resources(
+    pool: &sqlx::PgPool,
+    claims: &Claims,
+    references: &[Reference],
+    resources: &mut [Resource],
+    expand: &[String],
+) -> Result<(), Error> {
+    if expand.is_empty() || resources.is_empty() {
+        return Ok(());
+    }
+
+    let nil = Uuid::nil().to_hyphenated().to_string();
+    let mut targets: Vec<Vec<(String, Uuid)>> = vec![];
+    for res in resources.iter() {
+        let data: serde_json::Value = serde_json::from_str(&res.data).unwrap_or_default();
+        let mut res_targets = vec![];
+        for name in expand {
+            let id = match name.as_str() {
+                "parent_id" => Some(res.parent_id.as_str()),
+                "permission_parent_id" => Some(res.permission_parent_id.as_str()),
+                property => match references
+                    .iter()
+                    .any(|reference| reference.kind == res.kind && reference.property == property)
+                {
+                    true => data.get(property).and_then(|id| id.as_str()),
+                    false => None,
+                },
+            };
+            // root resources are their own parent
+            let id = match id {
+                Some(id) if id != res.id && id != nil => id,
+                _ => continue,
+            };
+            if let Ok(id) = Uuid::parse_str(id) {
+                res_targets.push((name.clone(), id));
+            }
+        }
+        targets.push(res_targets);
+    }
+
+    let ids: Vec<Uuid> = targets
+        .iter()
+        .flatten()
+        .map(|(_, id)| *id)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let principals = match claims.adm {
+        true => vec![],
+        false => claims.principals()?,
+    };
+    let rows: Vec<ResourceRow> = sqlx::query_as(
+        r#"SELECT DISTINCT ON (resources.resource_id) resources.resource_id, kind, parent_id, permission_parent_id, creator_id, created_at, updated_at, data, labels, deleted_at, expires_at, status, generation, observed_generation
+            FROM resources
+            LEFT JOIN permissions ON (resources.permission_parent_id = permissions.resource_id)
+            WHERE resources.resource_id = ANY($1)
+            AND resources.deleted_at IS NULL
+            AND ($2 OR (permissions.principal_id = ANY($3) AND permissions.action = 'read'))"#,
+    )
+    .bind(&ids)
+    .bind(claims.adm)
+    .bind(&principals)
+    .fetch_all(pool)
+    .await?;
+    let found: HashMap<Uuid, Resource> = rows
+        .into_iter()
+        .map(|row| (row.resource_id, row.into()))
+        .collect();
+
+    for (res, res_targets) in resources.iter_mut().zip(targets) {
+        for (name, id) in res_targets {
+            if let Some(target) = found.get(&id) {
+                res.expanded.insert(name, target.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+// The filters of a watch together with the last sent state of the resources in
+// its result set.
+struct WatchedList {
+    claims: Claims,
+    labels: HashMap<String, String>,
+    filter: String,
+    kind: String,
+    search_term: String,
+    highlight: bool,
+    parent_id: Option<Uuid>,
+    descendants_of: Option<Uuid>,
+    max_depth: u32,
+    fields: Vec<String>,
+    where_filter: Option<ResourceFilter>,
+    expand: Vec<String>,
+    resources: HashMap<String, Resource>,
+}
+
+impl WatchedList {
+    fn new(opts: &ListOptions) -> WatchedList {
+        WatchedList {
+            claims: opts.claims.clone(),
+            labels: opts.labels.clone(),
+            filter: opts.filter.to_string(),
+            kind: opts.kind.to_string(),
+            search_term: opts.search_term.to_string(),
+            highlight: opts.highlight,
+            parent_id: opts.parent_id.cloned(),
+            descendants_of: opts.descendants_of.cloned(),
+            max_depth: opts.max_depth,
+            fields: opts.fields.to_vec(),
+            where_filter: opts.where_filter.cloned(),
+            expand: opts.expand.to_vec(),
+            resources: HashMap::new(),
+        }
+    }
+
+    fn options(&self) -> ListOptions<'_> {
+        ListOptions {
+            claims: &self.claims,
+            labels: &self.labels,
+            filter: &self.filter,
+            kind: &self.kind,
+            search_term: &self.search_term,
+            highlight: self.highlight,
+            parent_id: self.parent_id.as_ref(),
+            descendants_of: self.descendants_of.as_ref(),
+            max_depth: self.max_depth,
+            include_deleted: false,
+            fields: &self.fields,
+            where_filter: self.where_filter.as_ref(),
+            expand: &self.expand,
+        }
+    }
+
+    // Fetches the matching resources, restricted to the resources in scope if
+    // one is given (the sql condition and its values).
+    async fn fetch(
+        &self,
+        pool: &sqlx::PgPool,
+        scope: Option<SqlFragment>,
+    ) -> Result<Vec<Resource>, Error> {
+        let (sql, values) = {
+            let mut query = list_query(&self.options())?;
+            if let Some((sql, values)) = scope {
+                query.and_where(Expr::cust_with_values(&sql, values));
+            }
+            query.build(PostgresQueryBuilder)
+        };
+        let highlight = self.highlight && !self.search_term.is_empty();
+        let rows = bind_query(sqlx::query(&sql), &values)
+            .fetch_all(pool)
+            .await?;
+        let mut res = vec![];
+        for row in rows {
+            res.push(resource_from_row(&row, highlight)?);
+        }
+        Ok(res)
+    }
+
+    // Fetches the resource of an event if it matches together with its id as
+    // the scope. Every resource restored or deleted with another one gets its
+    // own event, so no other resource is affected. Returns None if the event
+    // can't change the result set.
+    async fn fetch_changed(
+        &self,
+        pool: &sqlx::PgPool,
+        event: &api::catalog::Event,
+    ) -> Result<Option<(Vec<Resource>, HashSet<String>)>, Error> {
+        let id = match Uuid::parse_str(&event.resource_id) {
+            Ok(id) => id,
+            Err(_) => return Ok(None),
+        };
+        let id_str = id.to_hyphenated().to_string();
+
+        // the kind of a resource never changes
+        if !self.kind.is_empty() && event.resource_kind != self.kind {
+            return Ok(None);
+        }
+        // the event carries the current labels, a resource which doesn't match
+        // them only matters if it was sent before
+        let labels_match = self
+            .labels
+            .iter()
+            .all(|(key, value)| event.resource_labels.get(key) == Some(value));
+        if !labels_match && !self.resources.contains_key(&id_str) {
+            return Ok(None);
+        }
+
+        let resources = self
+            .fetch(
+                pool,
+                Some((
+                    "resource_id = ?::UUID".to_string(),
+                    vec![id_str.clone().into()],
+                )),
+            )
+            .await?;
+        Ok(Some((resources, vec![id_str].into_iter().collect())))
+    }
+
+    // Compares fetched resources with the last sent ones and returns the changes.
+    // Sent resources in scope which were not fetched anymore are removed, without
+    // a scope the fetched resources are the whole result set.
+    fn apply(
+        &mut self,
+        fetched: Vec<Resource>,
+        scope: Option<&HashSet<String>>,
+    ) -> Vec<WatchEvent> {
+        let mut changes = vec![];
+
+        let fetched_ids: HashSet<String> = fetched.iter().map(|res| res.id.clone()).collect();
+        let removed: Vec<String> = self
+            .resources
+            .keys()
+            .filter(|id| scope.map(|scope| scope.contains(*id)).unwrap_or(true))
+            .filter(|id| !fetched_ids.contains(*id))
+            .cloned()
+            .collect();
+        for id in removed {
+            if let Some(res) = self.resources.remove(&id) {
+                changes.push(WatchEvent {
+                    event_type: WatchEventType::Removed as i32,
+                    resource: Some(res),
+                });
+            }
+        }
+
+        for res in fetched {
+            let event_type = match self.resources.get(&res.id) {
+                None => WatchEventType::Added,
+                Some(old) if *old != res => WatchEventType::Modified,
+                Some(_) => continue,
+            };
+            self.resources.insert(res.id.clone(), res.clone());
+            changes.push(WatchEvent {
+                event_type: event_type as i32,
+                resource: Some(res),
+            });
+        }
+
+        if scope.is_none() {
+            changes.push(WatchEvent {
+                event_type: WatchEventType::Synced as i32,
+                resource: None,
+            });
+        }
+
+        changes
+    }
+}
+
+// Decodes a row returned by the query of list.
+fn resource_from_row(row: &PgRow, highlight: bool) -> Result<Resource, sqlx::Error> {
+    let mut res: Resource = ResourceRow::from_row(row)?.into();
+    if highlight {
+        res.highlight = row
+            .try_get::<Option<String>, _>("highlight")?
+            .unwrap_or_default();
+    }
+    Ok(res)
+}
+
+// Builds the query of list. The readable resources matching the filters are
+// selected in a subquery, so conditions on the result can be added.
+fn list_query(opts: &ListOptions) -> Result<SelectStatement, Error> {
+    let mut query = Query::select()
+        .expr(sea_query::Expr::cust(
+            "DISTINCT ON(resources.resource_id) resources.resource_id",
+        ))
+        .columns(vec![
+            (ResourcesTable::Table, ResourcesTable::Kind),
+            (ResourcesTable::Table, ResourcesTable::ParentID),
+            (ResourcesTable::Table, ResourcesTable::PermissionParentID),
+            (ResourcesTable::Table, ResourcesTable::CreatorID),
+            (ResourcesTable::Table, ResourcesTable::CreatedAt),
+            (ResourcesTable::Table, ResourcesTable::UpdatedAt),
+            (ResourcesTable::Table, ResourcesTable::Labels),
+            (ResourcesTable::Table, ResourcesTable::DeletedAt),
+            (ResourcesTable::Table, ResourcesTable::ExpiresAt),
+            (ResourcesTable::Table, ResourcesTable::Status),
+            (ResourcesTable::Table, ResourcesTable::Generation),
+            (ResourcesTable::Table, ResourcesTable::ObservedGeneration),
+        ])
+        .order_by(
+            (ResourcesTable::Table, ResourcesTable::ResourceID),
+            sea_query::Order::Desc,
+        )
+        .order_by(
+            (ResourcesTable::Table, ResourcesTable::CreatedAt),
+            sea_query::Order::Desc,
+        )
+        .to_owned();
+
+    query = match opts.fields.is_empty() {
+        true => query
+            .column((ResourcesTable::Table, ResourcesTable::Data))
+            .to_owned(),
+        false => {
+            let (sql, keys) = Projection::new(opts.fields)?.to_sql("resources.data");
+            query
+                .expr(Expr::cust_with_values(&format!("{} AS data", sql), keys))
+                .to_owned()
+        }
+    };
+
+    if !opts.search_term.is_empty() {
+        let (sql, values) = search_query_sql(opts.kind, opts.search_term);
+        query.expr(Expr::cust_with_values(
+            &format!("ts_rank(resources.search_vec, {}) AS rank", sql),
+            values,
+        ));
+        if opts.highlight {
+            let (config, mut values) = search_config_sql(opts.kind);
+            let (sql, query_values) = search_query_sql(opts.kind, opts.search_term);
+            values.extend(query_values);
+            query.expr(Expr::cust_with_values(
+                &format!(
+                    "ts_headline({}, resource_search_text(resources.kind, resources.data), {}) AS highlight",
+                    config, sql
+                ),
+                values,
+            ));
+        }
+    }
+
+    apply_filters(&mut query, opts)?;
+
+    // search results are ordered by their rank
+    let mut outer = Query::select()
+        .expr(sea_query::Expr::cust("*"))
+        .from_subquery(query.clone(), ResourcesTable::SubQuery)
+        .to_owned();
+    if !opts.search_term.is_empty() {
+        outer.order_by_expr(Expr::cust("rank"), sea_query::Order::Desc);
+    }
+    outer.order_by(
+        (ResourcesTable::SubQuery, ResourcesTable::CreatedAt),
+        sea_query::Order::Desc,
+    );
+
+    Ok(outer)
+}
+
+// Adds the conditions shared by list and aggregate to a select over the
+// resources table. The permissions table is joined to check read access.
+fn apply_filters(query: &mut SelectStatement, opts: &ListOptions) -> Result<(), Error> {
+    let ListOptions {
+        claims,
+        labels,
+        filter,
+        kind,
+        search_term,
+        highlight: _,
+        parent_id,
+        descendants_of,
+        max_depth,
+        include_deleted,
+        fields: _,
+        where_filter,
+        expand: _,
+    } = *opts;
+    let principals = claims.principals()?;
+
+    query.from(ResourcesTable::Table).join(
+        sea_query::JoinType::LeftJoin,
+        PermissionsTable::Table,
+        Expr::tbl(ResourcesTable::Table, ResourcesTable::PermissionParentID)
+            .equals(PermissionsTable::Table, PermissionsTable::ResourceID),
+    );
+
+    if !include_deleted {
+        query.and_where(Expr::tbl(ResourcesTable::Table, ResourcesTable::DeletedAt).is_null());
+    }
+
+    if !claims.adm {
+        query
+            .and_where(
+                Expr::tbl(PermissionsTable::Table, PermissionsTable::PrincipalID).is_in(principals),
+            )
+            .and_where(Expr::tbl(PermissionsTable::Table, PermissionsTable::Action).eq("read"));
+    }
+
+    if !labels.is_empty() {
+        let labels_value = match serde_json::to_value(&labels) {
+            Ok(val) => val,
+            Err(err) => {
+                return Err(Error::InvalidArgument(format!(
+                    "failed to encode resource labels: {}",
+                    err
+                )));
+            }
+        };
+        query.and_where(sea_query::Expr::cust_with_values(
+            "labels @> ?",
+            vec![labels_value],
+        ));
+    }
+
+    if !filter.is_empty() {
+        query.and_where(Expr::cust_with_values(
+            "data @@ (?::JSONPATH)",
+            vec![filter],
+        ));
+    }
+
+    if !kind.is_empty() {
+        query.and_where(Expr::tbl(ResourcesTable::Table, ResourcesTable::Kind).eq(kind));
+    }
+
+    if !search_term.is_empty() {
+        let (sql, values) = search_query_sql(kind, search_term);
+        query.and_where(Expr::cust_with_values(
+            &format!("resources.search_vec @@ {}", sql),
+            values,
+        ));
+    }
+
+    if let Some(parent_id) = parent_id {
+        let parent_id = parent_id.to_hyphenated().to_string();
+        query
+            .and_where(Expr::cust_with_values(
+                "resources.parent_id = ?::UUID",
+                vec![parent_id.clone()],
+            ))
+            .and_where(Expr::cust_with_values(
+                "resources.resource_id <> ?::UUID",
+                vec![parent_id],
+            ));
+    }
+
+    if let Some(descendants_of) = descendants_of {
+        let max_depth = max_depth as i64;
+        query.and_where(Expr::cust_with_values(
+            &format!("resources.resource_id IN ({})", descendants_query("?", "?")),
+            vec![
+                sea_query::Value::from(descendants_of.to_hyphenated().to_string()),
+                sea_query::Value::from(max_depth),
+                sea_query::Value::from(max_depth),
+            ],
+        ));
+    }
+
+    if let Some(where_filter) = where_filter {
+        let (sql, values) = filter_sql(where_filter)?;
+        query.and_where(Expr::cust_with_values(&sql, values));
+    }
+
+    Ok(())
+}
+
+// Tree of the dot separated paths selected from the data of a resource. A
+// missing subtree selects the whole value.
+#[derive(Default)]
+struct Projection(std::collections::BTreeMap<String, Option<Projection>>);
+
+impl Projection {
+    fn new(fields: &[String]) -> Result<Projection, Error> {
+        let mut root = Projection::default();
+        for field in fields {
+            let segments: Vec<&str> = field.split('.').collect();
+            if segments.iter().any(|segment| segment.is_empty()) {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid path '{}' in fields",
+                    field
+                )));
+            }
+
+            let mut node = &mut root;
+            for (i, segment) in segments.iter().enumerate() {
+                let entry = node
+                    .0
+                    .entry(segment.to_string())
+                    .or_insert_with(|| Some(Projection::default()));
+                if i == segments.len() - 1 {
+                    *entry = None;
+                    break;
+                }
+                match entry {
+                    Some(child) => node = child,
+                    // an enclosing object is already selected as a whole
+                    None => break,
+                }
+            }
+        }
+        Ok(root)
+    }
+
+    // Renders the projection of the jsonb expression `source` as a sql expression
+    // which builds the reduced document. Keys are bound as `?` placeholders, the
+    // returned values are in placeholder order. Missing keys are left out.
+    fn to_sql(&self, source: &str) -> (String, Vec<String>) {
+        self.render(source, &[])
+    }
+
+    fn render(&self, source: &str, source_keys: &[String]) -> (String, Vec<String>) {
+        let mut sql = "'{}'::JSONB".to_string();
+        let mut keys = vec![];
+        for (key, child) in &self.0 {
+            let value = format!("({} -> ?::TEXT)", source);
+            let mut value_keys = source_keys.to_vec();
+            value_keys.push(key.to_string());
+
+            match child {
+                None => {
+                    sql.push_str(&format!(
+                        " || (CASE WHEN {value} IS NULL THEN '{{}}'::JSONB ELSE jsonb_build_object(?::TEXT, {value}) END)",
+                        value = value
+                    ));
+                    keys.extend(value_keys.iter().cloned());
+                    keys.push(key.to_string());
+                    keys.extend(value_keys);
+                }
+                Some(child) => {
+                    let (child_sql, child_keys) = child.render(&value, &value_keys);
+                    sql.push_str(&format!(
+                        " || (CASE WHEN jsonb_typeof({}) = 'object' THEN jsonb_build_object(?::TEXT, {}) ELSE '{{}}'::JSONB END)",
+                        value, child_sql
+                    ));
+                    keys.extend(value_keys);
+                    keys.push(key.to_string());
+                    keys.extend(child_keys);
+                }
+            }
+        }
+        (format!("({})", sql), keys)
+    }
+}
+
+// Takes the given paths from the patched document and everything else from the
+// old one. Paths which do not exist in the patched document are removed.
+fn apply_update_mask(
+    old: &serde_json::Value,
+    patched: &serde_json::Value,
+    update_mask: &[String],
+) -> Result<serde_json::Value, Error> {
+    let mut res = old.clone();
+    for path in update_mask {
+        let segments: Vec<&str> = path.split('.').collect();
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return Err(Error::InvalidArgument(format!(
+                "invalid path '{}' in update mask",
+                path
+            )));
+        }
+
+        let (last, parents) = segments.split_last().unwrap();
+        let value = segments
+            .iter()
+            .try_fold(patched, |value, segment| value.get(segment));
+
+        let mut target = &mut res;
+        for segment in parents {
+            if !target.is_object() {
+                return Err(Error::InvalidArgument(format!(
+                    "path '{}' in update mask does not point into an object",
+                    path
+                )));
+            }
+            target = target
+                .as_object_mut()
+                .unwrap()
+                .entry(segment.to_string())
+                .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        }
+        match (target.as_object_mut(), value) {
+            (Some(obj), Some(value)) => {
+                obj.insert(last.to_string(), value.clone());
+            }
+            (Some(obj), None) => {
+                obj.remove(*last);
+            }
+            (None, _) => {
+                return Err(Error::InvalidArgument(format!(
+                    "path '{}' in update mask does not point into an object",
+                    path
+                )));
+            }
+        }
+    }
+    Ok(res)
+}
+
+fn build_tree(resource: Resource, children: &mut HashMap<String, Vec<Resource>>) -> ResourceTree {
+    let nodes = children.remove(&resource.id).unwrap_or_default();
+    ResourceTree {
+        children: nodes
+            .into_iter()
+            .map(|child| build_tree(child, children))
+            .collect(),
+        resource: Some(resource),
+    }
+}
+
+// A sql expression together with the values bound to its placeholders.
+type SqlFragment = (String, Vec<sea_query::Value>);
+
+// Returns the text search config of the resources. If the kind is known it is
+// passed as constant, so the search index can be used.
+fn search_config_sql(kind: &str) -> SqlFragment {
+    match kind.is_empty() {
+        true => ("resource_search_config(resources.kind)".to_string(), vec![]),
+        false => (
+            "resource_search_config(?)".to_string(),
+            vec![kind.to_string().into()],
+        ),
+    }
+}
+
+fn search_query_sql(kind: &str, search_term: &str) -> SqlFragment {
+    let (config, mut values) = search_config_sql(kind);
+    values.push(search_term.to_string().into());
+    (format!("websearch_to_tsquery({}, ?)", config), values)
+}
+
+fn validate_path(path: &str) -> Result<(), Error> {
+    match path.split('.').any(|segment| segment.is_empty()) {
+        true => Err(Error::InvalidArgument(format!("invalid path '{}'", path))),
+        false => Ok(()),
+    }
+}
+
+// Returns the sql expression selecting the value of a field as jsonb together
+// with its bound values, None if neither path nor label is set.
+fn field_sql(field: &AggregationField) -> Result<Option<SqlFragment>, Error> {
+    match (field.path.is_empty(), field.label.is_empty()) {
+        (true, true) => Ok(None),
+        (false, true) => {
+            validate_path(&field.path)?;
+            Ok(Some((
+                "(data #> string_to_array(?, '.'))".to_string(),
+                vec![field.path.clone().into()],
+            )))
+        }
+        (true, false) => Ok(Some((
+            "(labels -> ?)".to_string(),
+            vec![field.label.clone().into()],
+        ))),
+        (false, false) => Err(Error::InvalidArgument(
+            "either path or label may be set".to_string(),
+        )),
+    }
+}
+
+// Renders an aggregation as sql expression returning jsonb. The aggregated
+// value is expected in the column `name`, the returned field expression has to
+// be selected under that name. Non numeric values are ignored by all functions
+// but COUNT.
+fn aggregation_sql(
+    aggregation: &Aggregation,
+    name: &str,
+) -> Result<(String, Option<SqlFragment>), Error> {
+    let function = match AggregateFunction::from_i32(aggregation.function) {
+        Some(function) => function,
+        None => {
+            return Err(Error::InvalidArgument(
+                "unknown aggregate function".to_string(),
+            ))
+        }
+    };
+    let field = match &aggregation.field {
+        Some(field) => field_sql(field)?,
+        None => None,
+    };
+
+    let function = match (function, field.is_some()) {
+        (AggregateFunction::Count, false) => return Ok(("to_jsonb(count(*))".to_string(), None)),
+        (_, false) => {
+            return Err(Error::InvalidArgument(
+                "aggregations other than COUNT need a path or label".to_string(),
+            ))
+        }
+        (AggregateFunction::Count, true) => {
+            return Ok((format!("to_jsonb(count({}))", name), field))
+        }
+        (AggregateFunction::Sum, true) => "sum",
+        (AggregateFunction::Avg, true) => "avg",
+        (AggregateFunction::Min, true) => "min",
+        (AggregateFunction::Max, true) => "max",
+    };
+
+    let number = format!(
+        r#"CASE WHEN jsonb_typeof({name}) = 'number' THEN ({name} #>> '{{}}')::NUMERIC
+            WHEN jsonb_typeof({name}) = 'string' AND ({name} #>> '{{}}') ~ '^-?[0-9]+(\.[0-9]+)?$' THEN ({name} #>> '{{}}')::NUMERIC
+            END"#,
+        name = name
+    );
+    Ok((format!("to_jsonb({}({}))", function, number), field))
+}
+
+/// Reads a typed filter from its JSON form as used by REST and GraphQL:
+/// `{"and": [..]}`, `{"or": [..]}`, `{"not": {..}}` or a comparison like
+/// `{"path": "meta.owner", "operator": "EQ", "value": "alice"}`. A comparison
+/// names exactly one of `path`, `label` or `attribute` (`KIND`, `CREATOR_ID`,
+/// `CREATED_AT` or `UPDATED_AT`), the operator defaults to `EQ` and the value
+/// may be any JSON value.
+pub fn filter_from_json(value: &serde_json::Value) -> Result<ResourceFilter, Error> {
+    let object = match value {
+        serde_json::Value::Object(object) => object,
+        _ => {
+            return Err(Error::InvalidArgument(
+                "filter must be a JSON object".to_string(),
+            ))
+        }
+    };
+    let list = |value: &serde_json::Value, operator: &str| match value {
+        serde_json::Value::Array(items) => Ok(ResourceFilterList {
+            filters: items
+                .iter()
+                .map(filter_from_json)
+                .collect::<Result<_, _>>()?,
+        }),
+        _ => Err(Error::InvalidArgument(format!(
+            "{} must be a JSON array of filters",
+            operator
+        ))),
+    };
+
+    let filter = match (object.len(), object.iter().next()) {
+        (1, Some((key, value))) if key == "and" => resource_filter::Filter::And(list(value, key)?),
+        (1, Some((key, value))) if key == "or" => resource_filter::Filter::Or(list(value, key)?),
+        (1, Some((key, value))) if key == "not" => {
+            resource_filter::Filter::Not(Box::new(filter_from_json(value)?))
+        }
+        _ => resource_filter::Filter::Comparison(comparison_from_json(object)?),
+    };
+    Ok(ResourceFilter {
+        filter: Some(filter),
+    })
+}
+
+fn comparison_from_json(
+    object: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Comparison, Error> {
+    let mut field = None;
+    let mut operator = ComparisonOperator::Eq;
+    let mut value = None;
+    for (key, item) in object {
+        let string = || {
+            item.as_str()
+                .ok_or_else(|| Error::InvalidArgument(format!("{} must be a string", key)))
+        };
+        match key.as_str() {
+            "path" | "label" | "attribute" if field.is_some() => {
+                return Err(Error::InvalidArgument(
+                    "comparison needs exactly one of path, label or attribute".to_string(),
+                ))
+            }
+            "path" => field = Some(comparison::Field::Path(string()?.to_string())),
+            "label" => field = Some(comparison::Field::Label(string()?.to_string())),
+            "attribute" => {
+                let attribute = match string()? {
+                    "KIND" => ResourceAttribute::Kind,
+                    "CREATOR_ID" => ResourceAttribute::CreatorId,
+                    "CREATED_AT" => ResourceAttribute::CreatedAt,
+                    "UPDATED_AT" => ResourceAttribute::UpdatedAt,
+                    other => {
+                        return Err(Error::InvalidArgument(format!(
+                            "unknown attribute '{}'",
+                            other
+                        )))
+                    }
+                };
+                field = Some(comparison::Field::Attribute(attribute as i32));
+            }
+            "operator" => {
+                operator = match string()? {
+                    "EQ" => ComparisonOperator::Eq,
+                    "NE" => ComparisonOperator::Ne,
+                    "LT" => ComparisonOperator::Lt,
+                    "LE" => ComparisonOperator::Le,
+                    "GT" => ComparisonOperator::Gt,
+                    "GE" => ComparisonOperator::Ge,
+                    "IN" => ComparisonOperator::In,
+                    other => {
+                        return Err(Error::InvalidArgument(format!(
+                            "unknown comparison operator '{}'",
+                            other
+                        )))
+                    }
+                }
+            }
+            "value" => value = Some(item.to_string()),
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown filter key '{}'",
+                    key
+                )))
+            }
+        }
+    }
+
+    match (field, value) {
+        (Some(field), Some(value)) => Ok(Comparison {
+            field: Some(field),
+            operator: operator as i32,
+            value,
+        }),
+        (None, _) => Err(Error::InvalidArgument(
+            "comparison needs one of path, label or attribute".to_string(),
+        )),
+        (_, None) => Err(Error::InvalidArgument(
+            "comparison needs a value".to_string(),
+        )),
+    }
+}
+
+// Translates a typed filter to a sql condition over the resources table. The
+// condition never evaluates to NULL, so NOT includes resources which lack the
+// compared value.
+fn filter_sql(filter: &ResourceFilter) -> Result<SqlFragment, Error> {
+    let list_sql = |list: &ResourceFilterList, operator: &str| {
+        if list.filters.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "{} needs at least one filter",
+                operator
+            )));
+        }
+        let mut conditions = vec![];
+        let mut values = vec![];
+        for filter in &list.filters {
+            let (sql, filter_values) = filter_sql(filter)?;
+            conditions.push(format!("({})", sql));
+            values.extend(filter_values);
+        }
+        Ok((conditions.join(&format!(" {} ", operator)), values))
+    };
+
+    match &filter.filter {
+        Some(resource_filter::Filter::And(list)) => list_sql(list, "AND"),
+        Some(resource_filter::Filter::Or(list)) => list_sql(list, "OR"),
+        Some(resource_filter::Filter::Not(filter)) => {
+            let (sql, values) = filter_sql(filter)?;
+            Ok((format!("NOT ({})", sql), values))
+        }
+        Some(resource_filter::Filter::Comparison(comparison)) => comparison_sql(comparison),
+        None => Err(Error::InvalidArgument("empty filter".to_string())),
+    }
+}
+
+fn comparison_sql(comparison: &Comparison) -> Result<SqlFragment, Error> {
+    let operator = match ComparisonOperator::from_i32(comparison.operator) {
+        Some(operator) => operator,
+        None => {
+            return Err(Error::InvalidArgument(
+                "unknown comparison operator".to_string(),
+            ))
+        }
+    };
+    let value: serde_json::Value = match serde_json::from_str(&comparison.value) {
+        Ok(value) => value,
+        Err(err) => {
+            return Err(Error::InvalidArgument(format!(
+                "comparison value is no valid JSON: {}",
+                err
+            )))
+        }
+    };
+    let operands = match (operator, value) {
+        (ComparisonOperator::In, serde_json::Value::Array(items)) => items,
+        (ComparisonOperator::In, _) => {
+            return Err(Error::InvalidArgument(
+                "IN needs a JSON array of values".to_string(),
+            ))
+        }
+        (_, value) => vec![value],
+    };
+    let ordering = matches!(
+        operator,
+        ComparisonOperator::Lt
+            | ComparisonOperator::Le
+            | ComparisonOperator::Gt
+            | ComparisonOperator::Ge
+    );
+
+    // converts the operands to strings, checking them with parse
+    let strings = |parse: &dyn Fn(&str) -> Result<(), String>| {
+        operands
+            .iter()
+            .map(|value| match value {
+                serde_json::Value::String(value) => match parse(value) {
+                    Ok(_) => Ok(sea_query::Value::from(value.clone())),
+                    Err(err) => Err(Error::InvalidArgument(err)),
+                },
+                _ => Err(Error::InvalidArgument(format!(
+                    "expected a string but got {}",
+                    value
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    // the compared column and the placeholder of the operands
+    let (column, mut values, placeholder, operand_values) = match &comparison.field {
+        Some(comparison::Field::Path(path)) => {
+            validate_path(path)?;
+            let column = "(resources.data #> string_to_array(?, '.'))";
+            if !ordering {
+                let operand_values = operands.into_iter().map(sea_query::Value::from).collect();
+                (
+                    column.to_string(),
+                    vec![path.clone().into()],
+                    "?::JSONB",
+                    operand_values,
+                )
+            } else {
+                // jsonb values of different types are not compared by value
+                let json_type = match operands.first() {
+                    Some(serde_json::Value::Number(_)) => "number",
+                    Some(serde_json::Value::String(_)) => "string",
+                    _ => {
+                        return Err(Error::InvalidArgument(
+                            "LT, LE, GT and GE need a number or a string".to_string(),
+                        ))
+                    }
+                };
+                let operand_values = operands.into_iter().map(sea_query::Value::from).collect();
+                (
+                    format!("jsonb_typeof({}) = '{}' AND {}", column, json_type, column),
+                    vec![path.clone().into(), path.clone().into()],
+                    "?::JSONB",
+                    operand_values,
+                )
+            }
+        }
+        Some(comparison::Field::Label(label)) => (
+            "(resources.labels ->> ?)".to_string(),
+            vec![label.clone().into()],
+            "?",
+            strings(&|_| Ok(()))?,
+        ),
+        Some(comparison::Field::Attribute(attribute)) => {
+            match ResourceAttribute::from_i32(*attribute) {
+                Some(ResourceAttribute::Kind) => (
+                    "resources.kind".to_string(),
+                    vec![],
+                    "?",
+                    strings(&|_| Ok(()))?,
+                ),
+                Some(ResourceAttribute::CreatorId) => {
+                    if ordering {
+                        return Err(Error::InvalidArgument(
+                            "LT, LE, GT and GE are not supported for creator_id".to_string(),
+                        ));
+                    }
+                    let parse = |value: &str| match Uuid::parse_str(value) {
+                        Ok(_) => Ok(()),
+                        Err(_) => Err(format!("invalid creator_id '{}'", value)),
+                    };
+                    (
+                        "resources.creator_id".to_string(),
+                        vec![],
+                        "?::UUID",
+                        strings(&parse)?,
+                    )
+                }
+                Some(attribute) => {
+                    let parse = |value: &str| match chrono::DateTime::parse_from_rfc3339(value) {
+                        Ok(_) => Ok(()),
+                        Err(err) => Err(format!("invalid timestamp '{}': {}", value, err)),
+                    };
+                    let column = match attribute {
+                        ResourceAttribute::CreatedAt => "resources.created_at",
+                        _ => "resources.updated_at",
+                    };
+                    (
+                        column.to_string(),
+                        vec![],
+                        "?::TIMESTAMPTZ",
+                        strings(&parse)?,
+                    )
+                }
+                None => {
+                    return Err(Error::InvalidArgument(
+                        "unknown resource attribute".to_string(),
+                    ))
+                }
+            }
+        }
+        None => {
+            return Err(Error::InvalidArgument(
+                "comparison needs a path, label or attribute".to_string(),
+            ))
+        }
+    };
+
+    let sql = match operator {
+        ComparisonOperator::In if operand_values.is_empty() => "FALSE".to_string(),
+        ComparisonOperator::In => format!(
+            "{} IN ({})",
+            column,
+            vec![placeholder; operand_values.len()].join(", ")
+        ),
+        ComparisonOperator::Eq => format!("{} = {}", column, placeholder),
+        ComparisonOperator::Ne => format!("{} IS DISTINCT FROM {}", column, placeholder),
+        ComparisonOperator::Lt => format!("{} < {}", column, placeholder),
+        ComparisonOperator::Le => format!("{} <= {}", column, placeholder),
+        ComparisonOperator::Gt => format!("{} > {}", column, placeholder),
+        ComparisonOperator::Ge => format!("{} >= {}", column, placeholder),
+    };
+    values.extend::<Vec<sea_query::Value>>(operand_values);
+    Ok((format!("COALESCE({}, FALSE)", sql), values))
+}
+
 pub enum ResourcesTable {
     Table,
     ResourceID,
@@ -599,6 +2965,11 @@ pub enum ResourcesTable {
     UpdatedAt,
     Data,
     Labels,
+    DeletedAt,
+    ExpiresAt,
+    Status,
+    Generation,
+    ObservedGeneration,
 
     SubQuery,
 }
@@ -620,6 +2991,11 @@ impl Iden for ResourcesTable {
                 Self::Data => "data",
                 Self::Labels => "labels",
                 Self::CreatorID => "creator_id",
+                Self::DeletedAt => "deleted_at",
+                Self::ExpiresAt => "expires_at",
+                Self::Status => "status",
+                Self::Generation => "generation",
+                Self::ObservedGeneration => "observed_generation",
                 Self::SubQuery => "sub_query",
             }
         )
@@ -650,3 +3026,48 @@ impl Iden for PermissionsTable {
         .unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mask(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn apply_update_mask_takes_only_the_masked_paths() {
+        let old = json!({"a": 1, "b": {"c": 2, "d": 3}, "e": 4});
+        let patched = json!({"a": 10, "b": {"c": 20, "d": 30}, "f": 50});
+
+        let res = apply_update_mask(&old, &patched, &mask(&["a", "b.c"])).unwrap();
+        assert_eq!(res, json!({"a": 10, "b": {"c": 20, "d": 3}, "e": 4}));
+
+        // paths missing in the patched document are removed, new ones are added
+        let res = apply_update_mask(&old, &patched, &mask(&["e", "f"])).unwrap();
+        assert_eq!(res, json!({"a": 1, "b": {"c": 2, "d": 3}, "f": 50}));
+
+        // missing parents are created
+        let res = apply_update_mask(&json!({}), &patched, &mask(&["b.d"])).unwrap();
+        assert_eq!(res, json!({"b": {"d": 30}}));
+
+        assert_eq!(apply_update_mask(&old, &patched, &[]).unwrap(), old);
+    }
+
+    #[test]
+    fn apply_update_mask_rejects_invalid_paths() {
+        let old = json!({"a": 1});
+        let patched = json!({"a": {"b": 2}});
+        for path in ["", "a.", ".a", "a..b", "a.b"] {
+            assert!(
+                matches!(
+                    apply_update_mask(&old, &patched, &mask(&[path])),
+                    Err(Error::InvalidArgument(_))
+                ),
+                "{:?}",
+                path
+            );
+        }
+    }
+}
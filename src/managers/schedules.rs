@@ -0,0 +1,546 @@
+use super::errors::Error;
+
+use futures::Stream;
+use managers::locks::LockOptions;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tonic::Status;
+use tracing::Instrument;
+
+use crate::api;
+use crate::managers;
+use crate::token::Claims;
+
+use api::catalog::{EventType, LockMode, MissedRunPolicy, Schedule};
+
+/// Namespace of the locks which make sure a run is published by one server.
+pub const NAMESPACE: &str = "schedules";
+
+// Interval in which due schedules are fired.
+const FIRE_INTERVAL_MS: u64 = 1_000;
+
+// Number of due schedules fired per interval.
+const FIRE_BATCH_SIZE: i64 = 100;
+
+// Ttl of the lease held while firing a schedule, runs of a server which dies in
+// the meantime are fired by another one after it expired.
+const FIRE_LEASE_TTL_MS: u32 = 30_000;
+
+// Runs which were due longer than this are missed and handled according to the
+// MissedRunPolicy of the schedule.
+const MISSED_RUN_GRACE_MS: i64 = 60_000;
+
+// Number of missed runs published per interval with FIRE_ALL, the rest follows
+// in the next intervals.
+const MAX_CATCH_UP_RUNS: usize = 100;
+
+const SCHEDULE_COLUMNS: &str = "schedule_id, cron, run_at, resource_id, kind, payload, missed_run_policy, next_run_at, last_run_at, creator_id, created_at";
+
+#[derive(sqlx::FromRow)]
+struct ScheduleRow {
+    schedule_id: Uuid,
+    cron: String,
+    run_at: Option<chrono::DateTime<chrono::Utc>>,
+    resource_id: Option<Uuid>,
+    kind: String,
+    payload: serde_json::Value,
+    missed_run_policy: i32,
+    next_run_at: Option<chrono::DateTime<chrono::Utc>>,
+    last_run_at: Option<chrono::DateTime<chrono::Utc>>,
+    creator_id: String,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<ScheduleRow> for Schedule {
+    fn from(r: ScheduleRow) -> Schedule {
+        Schedule {
+            id: r.schedule_id.to_hyphenated().to_string(),
+            cron: r.cron,
+            run_at: r.run_at.as_ref().map(timestamp),
+            resource_id: r
+                .resource_id
+                .map(|id| id.to_hyphenated().to_string())
+                .unwrap_or_default(),
+            kind: r.kind,
+            payload: r.payload.to_string(),
+            missed_run_policy: r.missed_run_policy,
+            next_run_at: r.next_run_at.as_ref().map(timestamp),
+            last_run_at: r.last_run_at.as_ref().map(timestamp),
+            creator_id: r.creator_id,
+            created_at: Some(timestamp(&r.created_at)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScheduleOptions<'a> {
+    /// cron expression with seconds, mutually exclusive with run_at
+    pub cron: &'a str,
+    pub run_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub resource_id: Option<Uuid>,
+    /// defaults to the kind of the resource
+    pub kind: &'a str,
+    pub payload: &'a serde_json::Value,
+    pub missed_run_policy: MissedRunPolicy,
+}
+
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pool: Arc<sqlx::PgPool>,
+    permissions: Arc<managers::permissions::Manager>,
+    resources: Arc<managers::resources::Manager>,
+    events: Arc<managers::events::Manager>,
+    locks: Arc<managers::locks::Manager>,
+}
+
+impl Manager {
+    pub async fn new(
+        pool: Arc<sqlx::PgPool>,
+        permissions: Arc<managers::permissions::Manager>,
+        resources: Arc<managers::resources::Manager>,
+        events: Arc<managers::events::Manager>,
+        locks: Arc<managers::locks::Manager>,
+    ) -> Result<Manager, Error> {
+        let res = Manager {
+            pool,
+            permissions,
+            resources,
+            events,
+            locks,
+        };
+        res.init_tables().await?;
+
+        let mgr = res.clone();
+        tokio::spawn(
+            async move {
+                use tokio::time::{sleep, Duration};
+                loop {
+                    sleep(Duration::from_millis(FIRE_INTERVAL_MS)).await;
+                    match mgr.fire_due().await {
+                        Ok(0) => (),
+                        Ok(count) => log::info!("published {} scheduled events", count),
+                        Err(err) => log::error!("failed to fire schedules: {}", err),
+                    }
+                }
+            }
+            .instrument(tracing::debug_span!("fire_due_schedules")),
+        );
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(name = "mgr::schedules::init_tables", skip(self))]
+    async fn init_tables(&self) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS schedules (
+                schedule_id UUID PRIMARY KEY,
+                cron VARCHAR(255) NOT NULL DEFAULT '',
+                run_at TIMESTAMPTZ,
+                resource_id UUID REFERENCES resources(resource_id) ON DELETE CASCADE,
+                kind VARCHAR(255) NOT NULL,
+                payload JSONB NOT NULL,
+                missed_run_policy INT4 NOT NULL,
+                next_run_at TIMESTAMPTZ,
+                last_run_at TIMESTAMPTZ,
+                creator_id VARCHAR(255) NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+        "#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS schedules_next_run_at_idx ON schedules (next_run_at) WHERE next_run_at IS NOT NULL;",
+        )
+        .execute(self.pool.deref())
+        .await?;
+        Ok(())
+    }
+
+    /// Creates a schedule, which requires write permission on its resource or
+    /// admin claims if it has none.
+    #[tracing::instrument(name = "mgr::schedules::create", skip(self))]
+    pub async fn create(
+        &self,
+        claims: &Claims,
+        opts: &ScheduleOptions<'_>,
+    ) -> Result<Schedule, Error> {
+        let next_run_at = match (opts.cron, opts.run_at) {
+            ("", Some(run_at)) => run_at,
+            ("", None) => {
+                return Err(Error::InvalidArgument(
+                    "either 'cron' or 'run_at' must be specified".into(),
+                ))
+            }
+            (expr, None) => match parse_cron(expr)?.upcoming(chrono::Utc).next() {
+                Some(next_run_at) => next_run_at,
+                None => {
+                    return Err(Error::InvalidArgument(format!(
+                        "cron expression {} never fires",
+                        expr
+                    )))
+                }
+            },
+            (_, Some(_)) => {
+                return Err(Error::InvalidArgument(
+                    "'cron' and 'run_at' are mutually exclusive".into(),
+                ))
+            }
+        };
+
+        let kind = match opts.resource_id {
+            Some(resource_id) => {
+                self.permissions
+                    .check(&resource_id, "write", claims)
+                    .await?;
+                let resource = self.resources.get(claims, &resource_id).await?;
+                if !opts.kind.is_empty() && opts.kind != resource.kind {
+                    return Err(Error::InvalidArgument(format!(
+                        "resource {} is of kind {}",
+                        resource_id.to_hyphenated(),
+                        resource.kind
+                    )));
+                }
+                resource.kind
+            }
+            None => {
+                if !claims.adm {
+                    return Err(Error::Forbidden);
+                }
+                if opts.kind.is_empty() {
+                    return Err(Error::InvalidArgument(
+                        "'kind' must be specified for schedules without resource".into(),
+                    ));
+                }
+                opts.kind.to_string()
+            }
+        };
+        if kind.len() > 255 {
+            return Err(Error::InvalidArgument(
+                "'kind' must not be longer than 255 bytes".into(),
+            ));
+        }
+
+        let schedule_id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+        let row: ScheduleRow = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO schedules (schedule_id, cron, run_at, resource_id, kind, payload, missed_run_policy, next_run_at, creator_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING {}"#,
+            SCHEDULE_COLUMNS
+        ))
+        .bind(schedule_id)
+        .bind(opts.cron)
+        .bind(opts.run_at)
+        .bind(opts.resource_id)
+        .bind(&kind)
+        .bind(opts.payload)
+        .bind(opts.missed_run_policy as i32)
+        .bind(next_run_at)
+        .bind(&claims.sub)
+        .fetch_one(self.pool.deref())
+        .await?;
+
+        Ok(row.into())
+    }
+
+    /// Returns the schedule, requires read permission on its resource.
+    #[tracing::instrument(name = "mgr::schedules::get", skip(self))]
+    pub async fn get(&self, claims: &Claims, id: &Uuid) -> Result<Schedule, Error> {
+        let row = self.fetch(id).await?;
+        self.check(claims, &row.resource_id, "read").await?;
+        Ok(row.into())
+    }
+
+    /// Streams the schedules of the resource, or all schedules for admins.
+    #[tracing::instrument(name = "mgr::schedules::list", skip(self))]
+    pub async fn list(
+        &self,
+        claims: &Claims,
+        resource_id: Option<Uuid>,
+    ) -> Result<
+        Pin<Box<impl Stream<Item = Result<Schedule, Status>> + Send + Sync + 'static>>,
+        Status,
+    > {
+        self.check(claims, &resource_id, "read").await?;
+
+        let rows: Vec<ScheduleRow> = sqlx::query_as(&format!(
+            r#"
+            SELECT {} FROM schedules
+            WHERE $1::UUID IS NULL OR resource_id = $1
+            ORDER BY created_at, schedule_id"#,
+            SCHEDULE_COLUMNS
+        ))
+        .bind(resource_id)
+        .fetch_all(self.pool.deref())
+        .await
+        .map_err(Error::from)?;
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            for row in rows {
+                if let Err(err) = tx.send(Ok(row.into())).await {
+                    log::error!("failed to send schedule: {}", err);
+                    break;
+                }
+            }
+        });
+
+        Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
+    }
+
+    /// Deletes the schedule, requires write permission on its resource.
+    #[tracing::instrument(name = "mgr::schedules::delete", skip(self))]
+    pub async fn delete(&self, claims: &Claims, id: &Uuid) -> Result<Schedule, Error> {
+        let row = self.fetch(id).await?;
+        self.check(claims, &row.resource_id, "write").await?;
+
+        let row: Option<ScheduleRow> = sqlx::query_as(&format!(
+            "DELETE FROM schedules WHERE schedule_id = $1 RETURNING {}",
+            SCHEDULE_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(self.pool.deref())
+        .await?;
+
+        match row {
+            Some(row) => Ok(row.into()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    /// Fires the due schedules which are not being fired by another server and
+    /// returns the number of published events.
+    #[tracing::instrument(name = "mgr::schedules::fire_due", skip(self))]
+    pub async fn fire_due(&self) -> Result<usize, Error> {
+        let ids: Vec<(Uuid,)> = sqlx::query_as(
+            r#"
+            SELECT s.schedule_id FROM schedules s
+            LEFT JOIN resources r ON r.resource_id = s.resource_id
+            WHERE s.next_run_at <= now()
+            AND r.deleted_at IS NULL
+            ORDER BY s.next_run_at
+            LIMIT $1"#,
+        )
+        .bind(FIRE_BATCH_SIZE)
+        .fetch_all(self.pool.deref())
+        .await?;
+
+        let claims = Claims::admin();
+        let mut count = 0;
+        for (id,) in ids {
+            let lock_id = id.to_hyphenated().to_string();
+            let opts = LockOptions {
+                claims: &claims,
+                holder_name: "",
+                namespace: NAMESPACE,
+                lock_id: &lock_id,
+                resource_id: None,
+                mode: LockMode::Exclusive,
+                limit: 0,
+                acquire_timeout_ms: 0,
+            };
+            let lease = match self.locks.acquire(&opts, FIRE_LEASE_TTL_MS).await {
+                Ok(lease) => lease,
+                // fired by another server
+                Err(Error::ResourceExhausted(_)) => continue,
+                Err(err) => return Err(err),
+            };
+
+            // a schedule which fails, e.g. because of an invalid cron expression,
+            // doesn't hold back the others
+            match self.fire(&id).await {
+                Ok(fired) => count += fired,
+                Err(err) => log::warn!("failed to fire schedule {}: {}", lock_id, err),
+            }
+            if let Err(err) = self
+                .locks
+                .release(&claims, NAMESPACE, &lock_id, lease.fencing_token)
+                .await
+            {
+                log::warn!("failed to release lock of schedule {}: {}", lock_id, err);
+            }
+        }
+        Ok(count)
+    }
+
+    // Publishes the due runs of the schedule according to its MissedRunPolicy and
+    // advances next_run_at. It is called with the lock of the schedule held, every
+    // run is published in the transaction which advances next_run_at past it, so
+    // runs are neither repeated nor lost if a server dies in between.
+    async fn fire(&self, id: &Uuid) -> Result<usize, Error> {
+        let row: Option<ScheduleRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM schedules WHERE schedule_id = $1 AND next_run_at <= now()",
+            SCHEDULE_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(self.pool.deref())
+        .await?;
+        // fired by another server in the meantime or deleted
+        let row = match row {
+            Some(row) => row,
+            None => return Ok(0),
+        };
+        let scheduled = match row.next_run_at {
+            Some(scheduled) => scheduled,
+            None => return Ok(0),
+        };
+        let mut due = scheduled;
+
+        let cron = match row.cron.as_str() {
+            "" => None,
+            expr => Some(parse_cron(expr)?),
+        };
+        let now = chrono::Utc::now();
+        let next_after = |ts: &chrono::DateTime<chrono::Utc>| match &cron {
+            Some(cron) => cron.after(ts).next(),
+            None => None,
+        };
+
+        let policy =
+            MissedRunPolicy::from_i32(row.missed_run_policy).unwrap_or(MissedRunPolicy::FireOnce);
+        if policy != MissedRunPolicy::FireAll {
+            // only the latest due run may be published
+            if let Some(cron) = &cron {
+                if let Some(latest) = cron.after(&due).take_while(|ts| *ts <= now).last() {
+                    due = latest;
+                }
+            }
+            let missed = now - due > chrono::Duration::milliseconds(MISSED_RUN_GRACE_MS);
+            let publish = policy == MissedRunPolicy::FireOnce || !missed;
+            let mut tx = self.pool.begin().await?;
+            if !self
+                .advance(&mut tx, &row, &scheduled, &due, next_after(&due), publish)
+                .await?
+            {
+                return Ok(0);
+            }
+            if publish {
+                self.publish(&mut tx, &row, &due).await?;
+            }
+            tx.commit().await?;
+            return Ok(publish as usize);
+        }
+
+        let mut count = 0;
+        loop {
+            let next = next_after(&due);
+            let mut tx = self.pool.begin().await?;
+            if !self.advance(&mut tx, &row, &due, &due, next, true).await? {
+                break;
+            }
+            self.publish(&mut tx, &row, &due).await?;
+            tx.commit().await?;
+            count += 1;
+            match next {
+                Some(next) if next <= now && count < MAX_CATCH_UP_RUNS => due = next,
+                _ => break,
+            }
+        }
+        Ok(count)
+    }
+
+    // Moves next_run_at of the schedule from the current to the next run, returns
+    // false if the schedule was deleted or advanced by someone else in the meantime.
+    async fn advance(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        row: &ScheduleRow,
+        current: &chrono::DateTime<chrono::Utc>,
+        due: &chrono::DateTime<chrono::Utc>,
+        next: Option<chrono::DateTime<chrono::Utc>>,
+        published: bool,
+    ) -> Result<bool, Error> {
+        let res = sqlx::query(
+            r#"
+            UPDATE schedules SET
+                next_run_at = $2,
+                last_run_at = CASE WHEN $3 THEN $4 ELSE last_run_at END
+            WHERE schedule_id = $1 AND next_run_at = $5"#,
+        )
+        .bind(row.schedule_id)
+        .bind(next)
+        .bind(published)
+        .bind(due)
+        .bind(current)
+        .execute(tx)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn publish(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        row: &ScheduleRow,
+        scheduled_at: &chrono::DateTime<chrono::Utc>,
+    ) -> Result<(), Error> {
+        let schedule_id = row.schedule_id.to_hyphenated().to_string();
+        let data = serde_json::json!({
+            "schedule_id": schedule_id,
+            "scheduled_at": scheduled_at.to_rfc3339(),
+            "payload": row.payload,
+        });
+        let mut labels = HashMap::new();
+        labels.insert("schedule".to_string(), schedule_id);
+
+        self.events
+            .publish_with_tx(
+                tx,
+                &Claims::admin(),
+                &row.resource_id.unwrap_or(row.schedule_id),
+                &row.kind,
+                &labels,
+                EventType::Scheduled,
+                &data,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn fetch(&self, id: &Uuid) -> Result<ScheduleRow, Error> {
+        let row: Option<ScheduleRow> = sqlx::query_as(&format!(
+            "SELECT {} FROM schedules WHERE schedule_id = $1",
+            SCHEDULE_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(self.pool.deref())
+        .await?;
+        match row {
+            Some(row) => Ok(row),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    // Schedules without resource are only accessible for admins.
+    async fn check(
+        &self,
+        claims: &Claims,
+        resource_id: &Option<Uuid>,
+        action: &str,
+    ) -> Result<(), Error> {
+        match resource_id {
+            Some(resource_id) => self.permissions.check(resource_id, action, claims).await,
+            None if claims.adm => Ok(()),
+            None => Err(Error::Forbidden),
+        }
+    }
+}
+
+fn parse_cron(expr: &str) -> Result<cron::Schedule, Error> {
+    cron::Schedule::from_str(expr)
+        .map_err(|err| Error::InvalidArgument(format!("invalid cron expression {}: {}", expr, err)))
+}
+
+fn timestamp(ts: &chrono::DateTime<chrono::Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: ts.timestamp(),
+        nanos: ts.timestamp_subsec_nanos() as i32,
+    }
+}
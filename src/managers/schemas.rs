@@ -21,6 +21,76 @@ struct SchemaRow {
     data: serde_json::Value,
 }
 
+/// What happens to the referencing resources when a referenced resource is deleted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnDelete {
+    /// the delete is rejected
+    Restrict,
+    /// the referencing resources are deleted as well
+    Cascade,
+    /// the reference is set to null
+    SetNull,
+}
+
+/// A reference to another resource, declared with `x-reference` on a top level
+/// property of a schema, e.g. `"x-reference": {"kind": "customer", "on_delete": "cascade"}`.
+#[derive(Debug, Clone)]
+pub struct Reference {
+    /// kind of the referencing resources
+    pub kind: String,
+    pub property: String,
+    /// kind of the referenced resources, None allows any kind
+    pub target_kind: Option<String>,
+    pub on_delete: OnDelete,
+}
+
+impl Reference {
+    fn parse(kind: &str, property: &str, spec: &serde_json::Value) -> Result<Reference, Error> {
+        let spec = match spec {
+            serde_json::Value::Object(spec) => spec,
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "x-reference of {} must be an object",
+                    property
+                )))
+            }
+        };
+        let mut res = Reference {
+            kind: kind.to_string(),
+            property: property.to_string(),
+            target_kind: None,
+            on_delete: OnDelete::Restrict,
+        };
+        for (key, value) in spec {
+            match (key.as_str(), value) {
+                ("kind", serde_json::Value::String(target_kind)) => {
+                    res.target_kind = Some(target_kind.clone())
+                }
+                ("on_delete", serde_json::Value::String(on_delete)) => {
+                    res.on_delete = match on_delete.as_str() {
+                        "restrict" => OnDelete::Restrict,
+                        "cascade" => OnDelete::Cascade,
+                        "set_null" => OnDelete::SetNull,
+                        _ => {
+                            return Err(Error::InvalidArgument(format!(
+                                "on_delete of {} must be restrict, cascade or set_null",
+                                property
+                            )))
+                        }
+                    }
+                }
+                _ => {
+                    return Err(Error::InvalidArgument(format!(
+                        "invalid x-reference option '{}' of {}",
+                        key, property
+                    )))
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
 #[derive(Debug)]
 pub struct Manager {
     pool: Arc<sqlx::PgPool>,
@@ -81,6 +151,9 @@ impl Manager {
 
     #[tracing::instrument(name = "mgr::schemas::create", skip(self))]
     pub async fn create(&self, kind: &str, data: &serde_json::Value) -> Result<Schema, Error> {
+        self.validate_search_config(data).await?;
+        validate_references(kind, data)?;
+
         let id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
 
         let now = chrono::Utc::now();
@@ -124,6 +197,10 @@ impl Manager {
 
         self.create_unique_indexes(kind, data).await?;
 
+        if data.get("x-search").is_some() {
+            self.reindex_search(kind).await?;
+        }
+
         Ok(res)
     }
 
@@ -226,6 +303,11 @@ impl Manager {
             }
         };
 
+        let data: serde_json::Value = serde_json::from_str(&res.data).unwrap_or_default();
+        if data.get("x-search").is_some() {
+            self.reindex_search(&res.kind).await?;
+        }
+
         Ok(res)
     }
 
@@ -233,7 +315,7 @@ impl Manager {
     pub async fn update(&self, id: &Uuid, doc: &serde_json::Value) -> Result<Schema, Error> {
         let mut schema = self.get(id).await?;
 
-        let mut data = match serde_json::from_str(&schema.data) {
+        let mut data: serde_json::Value = match serde_json::from_str(&schema.data) {
             Ok(obj) => obj,
             Err(err) => {
                 return Err(Error::InvalidArgument(format!(
@@ -245,7 +327,10 @@ impl Manager {
 
         let now = chrono::Utc::now();
 
+        let old_search = data.get("x-search").cloned();
         json_patch::merge(&mut data, doc);
+        self.validate_search_config(&data).await?;
+        validate_references(&schema.kind, &data)?;
 
         match sqlx::query("UPDATE schemas SET data = $1, updated_at = $2 WHERE id = $3")
             .bind(&data)
@@ -260,6 +345,10 @@ impl Manager {
             }
         };
 
+        if data.get("x-search").cloned() != old_search {
+            self.reindex_search(&schema.kind).await?;
+        }
+
         schema.data = match serde_json::to_string(&data) {
             Ok(data) => data,
             Err(err) => {
@@ -336,6 +425,18 @@ impl Manager {
         Ok(Box::pin(tokio_stream::wrappers::ReceiverStream::new(rx)))
     }
 
+    /// Returns a fingerprint of all stored schemas which changes whenever a
+    /// schema is created, updated or deleted. Used to invalidate derived state.
+    #[tracing::instrument(name = "mgr::schemas::revision", skip(self))]
+    pub async fn revision(&self) -> Result<String, Error> {
+        let row: (Option<String>,) = sqlx::query_as(
+            "SELECT md5(string_agg(id::text || updated_at::text, ',' ORDER BY id)) FROM schemas",
+        )
+        .fetch_one(self.pool.deref())
+        .await?;
+        Ok(row.0.unwrap_or_default())
+    }
+
     #[tracing::instrument(name = "mgr::schemas::validate", skip(self))]
     pub async fn validate(&self, kind: &str, doc: &serde_json::Value) -> Result<(), Error> {
         let row: SchemaRow = match sqlx::query_as(
@@ -368,6 +469,141 @@ impl Manager {
         Ok(())
     }
 
+    /// Returns the references declared by the top level properties of all stored
+    /// schemas, fails if one of them is malformed.
+    #[tracing::instrument(name = "mgr::schemas::references", skip(self))]
+    pub async fn references(&self) -> Result<Vec<Reference>, Error> {
+        let rows: Vec<(String, String, serde_json::Value)> = sqlx::query_as(
+            r#"SELECT schemas.kind, properties.key, properties.value->'x-reference'
+            FROM schemas, jsonb_each(
+                CASE WHEN jsonb_typeof(schemas.data->'properties') = 'object' THEN schemas.data->'properties' ELSE '{}'::JSONB END
+            ) AS properties
+            WHERE jsonb_typeof(properties.value) = 'object' AND properties.value ? 'x-reference'"#,
+        )
+        .fetch_all(self.pool.deref())
+        .await?;
+
+        rows.iter()
+            .map(|(kind, property, spec)| Reference::parse(kind, property, spec))
+            .collect()
+    }
+
+    // Checks the full text search configuration of a kind, e.g.
+    // `"x-search": {"language": "german", "fields": [{"path": "title", "weight": "A"}]}`.
+    #[tracing::instrument(name = "mgr::schemas::validate_search_config", skip(self))]
+    async fn validate_search_config(&self, doc: &serde_json::Value) -> Result<(), Error> {
+        let search = match doc.get("x-search") {
+            Some(serde_json::Value::Object(search)) => search,
+            Some(_) => {
+                return Err(Error::InvalidArgument(
+                    "x-search must be an object".to_owned(),
+                ))
+            }
+            None => return Ok(()),
+        };
+
+        for key in search.keys() {
+            if !["language", "fields", "types"].contains(&key.as_str()) {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown x-search option '{}'",
+                    key
+                )));
+            }
+        }
+
+        match search.get("language") {
+            Some(serde_json::Value::String(language)) => {
+                let exists: (bool,) =
+                    sqlx::query_as("SELECT EXISTS(SELECT 1 FROM pg_ts_config WHERE cfgname = $1)")
+                        .bind(language)
+                        .fetch_one(self.pool.deref())
+                        .await?;
+                if !exists.0 {
+                    return Err(Error::InvalidArgument(format!(
+                        "unknown x-search language '{}'",
+                        language
+                    )));
+                }
+            }
+            Some(_) => {
+                return Err(Error::InvalidArgument(
+                    "x-search language must be a string".to_owned(),
+                ))
+            }
+            None => (),
+        }
+
+        match search.get("fields") {
+            Some(serde_json::Value::Array(fields)) => {
+                for field in fields {
+                    let path = field.get("path").and_then(|path| path.as_str());
+                    match path {
+                        Some(path) if !path.split('.').any(|segment| segment.is_empty()) => (),
+                        _ => {
+                            return Err(Error::InvalidArgument(
+                                "x-search fields need a dot separated path".to_owned(),
+                            ))
+                        }
+                    }
+                    match field.get("weight").map(|weight| weight.as_str()) {
+                        None | Some(Some("A")) | Some(Some("B")) | Some(Some("C"))
+                        | Some(Some("D")) => (),
+                        _ => {
+                            return Err(Error::InvalidArgument(
+                                "x-search weights must be one of A, B, C or D".to_owned(),
+                            ))
+                        }
+                    }
+                }
+            }
+            Some(_) => {
+                return Err(Error::InvalidArgument(
+                    "x-search fields must be an array".to_owned(),
+                ))
+            }
+            None => (),
+        }
+
+        match search.get("types") {
+            Some(serde_json::Value::Array(types)) => {
+                for t in types {
+                    match t.as_str() {
+                        Some("string") | Some("numeric") | Some("boolean") | Some("key")
+                        | Some("all") => (),
+                        _ => {
+                            return Err(Error::InvalidArgument(
+                                "x-search types must be string, numeric, boolean, key or all"
+                                    .to_owned(),
+                            ))
+                        }
+                    }
+                }
+            }
+            Some(_) => {
+                return Err(Error::InvalidArgument(
+                    "x-search types must be an array".to_owned(),
+                ))
+            }
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    // Recomputes the search vectors of all resources of a kind after its search
+    // configuration changed.
+    #[tracing::instrument(name = "mgr::schemas::reindex_search", skip(self))]
+    async fn reindex_search(&self, kind: &str) -> Result<(), Error> {
+        info!("reindex search vectors");
+        sqlx::query(
+            "UPDATE resources SET search_vec = resource_search_vector(kind, data) WHERE kind = $1",
+        )
+        .bind(kind)
+        .execute(self.pool.deref())
+        .await?;
+        Ok(())
+    }
+
     #[tracing::instrument(name = "mgr::schemas::create_unique_indexes", skip(self))]
     async fn create_unique_indexes(
         &self,
@@ -417,3 +653,15 @@ impl Manager {
         Ok(())
     }
 }
+
+// Checks the x-reference declarations of the top level properties of a schema.
+fn validate_references(kind: &str, doc: &serde_json::Value) -> Result<(), Error> {
+    if let Some(serde_json::Value::Object(properties)) = doc.get("properties") {
+        for (property, spec) in properties {
+            if let Some(reference) = spec.get("x-reference") {
+                Reference::parse(kind, property, reference)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,530 @@
+use super::errors::Error;
+
+use sea_query::{Alias, Expr, Order, PostgresQueryBuilder, Query};
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+use tracing::Instrument;
+sea_query::sea_query_driver_postgres!();
+use sea_query_driver_postgres::bind_query_as;
+
+use crate::api;
+use crate::managers;
+use crate::managers::resources::{CreateOptions, ResourcesTable};
+use crate::token::Claims;
+use crate::token::Context;
+
+use api::catalog::{Task, TaskState};
+
+/// Kind of the resources which mirror tasks.
+pub const KIND: &str = "task";
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_VISIBILITY_TIMEOUT_MS: u32 = 30_000;
+
+// A failed task is retried after BACKOFF_BASE_MS * 2^(attempts - 1), but not
+// later than BACKOFF_MAX_MS.
+const BACKOFF_BASE_MS: u32 = 1_000;
+const BACKOFF_MAX_MS: u32 = 600_000;
+
+// Interval in which claimed tasks that ran out of attempts and timed out are
+// moved to the dead letter state.
+const DEAD_LETTER_INTERVAL_SECS: u64 = 10;
+
+const TASK_COLUMNS: &str = "task_id, resource_id, queue, payload, state, attempts, max_attempts, visible_at, claim_id, worker_id, last_error, result";
+
+#[derive(sqlx::FromRow)]
+struct TaskRow {
+    task_id: Uuid,
+    resource_id: Uuid,
+    queue: String,
+    payload: serde_json::Value,
+    state: i32,
+    attempts: i32,
+    max_attempts: i32,
+    visible_at: chrono::DateTime<chrono::Utc>,
+    claim_id: Option<Uuid>,
+    worker_id: String,
+    last_error: String,
+    result: Option<serde_json::Value>,
+}
+
+impl TaskRow {
+    // The part of the task mirrored into the status of its resource.
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "state": state_name(self.state),
+            "attempts": self.attempts,
+            "max_attempts": self.max_attempts,
+            "worker_id": self.worker_id,
+            "last_error": self.last_error,
+            "result": self.result,
+        })
+    }
+}
+
+impl From<TaskRow> for Task {
+    fn from(r: TaskRow) -> Task {
+        Task {
+            id: r.task_id.to_hyphenated().to_string(),
+            resource_id: r.resource_id.to_hyphenated().to_string(),
+            queue: r.queue,
+            payload: r.payload.to_string(),
+            state: r.state,
+            attempts: r.attempts as u32,
+            max_attempts: r.max_attempts as u32,
+            visible_at: Some(prost_types::Timestamp {
+                seconds: r.visible_at.timestamp(),
+                nanos: r.visible_at.timestamp_subsec_nanos() as i32,
+            }),
+            claim_id: r
+                .claim_id
+                .map(|id| id.to_hyphenated().to_string())
+                .unwrap_or_default(),
+            worker_id: r.worker_id,
+            last_error: r.last_error,
+            result: r
+                .result
+                .map(|result| result.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Manager {
+    pool: Arc<sqlx::PgPool>,
+    permissions: Arc<managers::permissions::Manager>,
+    schemas: Arc<managers::schemas::Manager>,
+    events: Arc<managers::events::Manager>,
+    resources: Arc<managers::resources::Manager>,
+}
+
+impl Manager {
+    pub async fn new(
+        pool: Arc<sqlx::PgPool>,
+        permissions: Arc<managers::permissions::Manager>,
+        schemas: Arc<managers::schemas::Manager>,
+        events: Arc<managers::events::Manager>,
+        resources: Arc<managers::resources::Manager>,
+    ) -> Result<Manager, Error> {
+        let res = Manager {
+            pool,
+            permissions,
+            schemas,
+            events,
+            resources,
+        };
+        res.init_tables().await?;
+        res.init_schema().await?;
+
+        let mgr = res.clone();
+        tokio::spawn(
+            async move {
+                use tokio::time::{sleep, Duration};
+                loop {
+                    sleep(Duration::from_secs(DEAD_LETTER_INTERVAL_SECS)).await;
+                    match mgr.dead_letter_expired().await {
+                        Ok(0) => (),
+                        Ok(count) => log::info!("moved {} timed out tasks to dead letter", count),
+                        Err(err) => log::error!("failed to dead letter timed out tasks: {}", err),
+                    }
+                }
+            }
+            .instrument(tracing::debug_span!("dead_letter_expired_tasks")),
+        );
+
+        Ok(res)
+    }
+
+    #[tracing::instrument(name = "mgr::tasks::init_tables", skip(self))]
+    async fn init_tables(&self) -> Result<(), Error> {
+        // the queue, the resources of the tasks only mirror its state
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS tasks (
+                task_id UUID PRIMARY KEY REFERENCES resources(resource_id) ON DELETE CASCADE,
+                resource_id UUID NOT NULL,
+                queue VARCHAR(255) NOT NULL,
+                payload JSONB NOT NULL,
+                state INT4 NOT NULL,
+                attempts INT4 NOT NULL DEFAULT 0,
+                max_attempts INT4 NOT NULL,
+                visible_at TIMESTAMPTZ NOT NULL,
+                claim_id UUID,
+                worker_id VARCHAR(255) NOT NULL DEFAULT '',
+                last_error TEXT NOT NULL DEFAULT '',
+                result JSONB,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+            );
+        "#,
+        )
+        .execute(self.pool.deref())
+        .await?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS tasks_queue_idx ON tasks (queue, visible_at) WHERE state IN (0, 1);",
+        )
+        .execute(self.pool.deref())
+        .await?;
+        Ok(())
+    }
+
+    // Creates the schema of the task resources unless it exists. An existing
+    // schema of the kind which differs from it wasn't created for tasks, the
+    // server doesn't start rather than mixing tasks with foreign resources.
+    async fn init_schema(&self) -> Result<(), Error> {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "queue": {"type": "string"},
+                "payload": {},
+                "max_attempts": {"type": "integer"}
+            },
+            "required": ["queue"]
+        });
+
+        match self.schemas.get_by_kind(KIND).await {
+            Ok(existing) => {
+                let data: serde_json::Value = serde_json::from_str(&existing.data)?;
+                return match data == schema {
+                    true => Ok(()),
+                    false => Err(Error::FailedPrecondition(format!(
+                        "schema {} exists but is not the schema of tasks",
+                        KIND
+                    ))),
+                };
+            }
+            Err(Error::NotFound) => (),
+            Err(err) => return Err(err),
+        };
+
+        match self.schemas.create(KIND, &schema).await {
+            Ok(_) => log::info!("created schema {}", KIND),
+            // created by another instance in the meantime
+            Err(err) => log::warn!("failed to create schema {}: {}", KIND, err),
+        };
+        Ok(())
+    }
+
+    /// Adds a task to the queue as child of the resource, requires write
+    /// permission on the resource.
+    #[tracing::instrument(name = "mgr::tasks::enqueue", skip(self))]
+    pub async fn enqueue(
+        &self,
+        claims: &Claims,
+        resource_id: &Uuid,
+        queue: &str,
+        payload: &serde_json::Value,
+        max_attempts: u32,
+        delay_ms: u32,
+    ) -> Result<Task, Error> {
+        if queue.is_empty() {
+            return Err(Error::InvalidArgument("'queue' must be specified".into()));
+        }
+        if queue.len() > 255 {
+            return Err(Error::InvalidArgument(
+                "'queue' must not be longer than 255 bytes".into(),
+            ));
+        }
+        let max_attempts = match max_attempts {
+            0 => DEFAULT_MAX_ATTEMPTS,
+            val => val,
+        };
+
+        self.permissions.check(resource_id, "write", claims).await?;
+        let resource = self.resources.get(claims, resource_id).await?;
+        let permission_parent_id = Uuid::parse_str(&resource.permission_parent_id)?;
+
+        let data = serde_json::json!({
+            "queue": queue,
+            "payload": payload,
+            "max_attempts": max_attempts,
+        });
+        let mut labels = HashMap::new();
+        labels.insert("queue".to_string(), queue.to_string());
+
+        let context = Context {
+            claims: claims.clone(),
+            fencing_token: None,
+        };
+        let kind = KIND.to_string();
+        let opts = CreateOptions {
+            context: &context,
+            kind: &kind,
+            parent_id: Some(resource_id),
+            permission_parent_id: Some(&permission_parent_id),
+            data: &data,
+            labels: &labels,
+            shares: &vec![],
+            expires_at: None,
+        };
+        let admitted = self.resources.admit(&opts).await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        let task = self
+            .resources
+            .create_with_tx(opts, admitted, &mut tx)
+            .await?;
+        let task_id = Uuid::parse_str(&task.id)?;
+
+        let row: TaskRow = sqlx::query_as(&format!(
+            r#"
+            INSERT INTO tasks (task_id, resource_id, queue, payload, state, max_attempts, visible_at)
+            VALUES ($1, $2, $3, $4, $5, $6, now() + make_interval(secs => $7))
+            RETURNING {}"#,
+            TASK_COLUMNS
+        ))
+        .bind(task_id)
+        .bind(resource_id)
+        .bind(queue)
+        .bind(payload)
+        .bind(TaskState::Pending as i32)
+        .bind(max_attempts as i32)
+        .bind(delay_ms as f64 / 1000.0)
+        .fetch_one(&mut tx)
+        .await?;
+
+        // the creation happened in our transaction, so is its event
+        self.events
+            .publish_with_tx(
+                &mut tx,
+                &Claims::admin(),
+                &task_id,
+                KIND,
+                &task.labels,
+                api::catalog::EventType::Create,
+                &data,
+            )
+            .await?;
+        self.resources
+            .set_status_with_tx(&mut tx, &task_id, &row.status())
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+
+    /// Claims the next visible task of the queue the claims have write
+    /// permission on. Tasks are claimed in the order they become visible.
+    #[tracing::instrument(name = "mgr::tasks::claim", skip(self))]
+    pub async fn claim(
+        &self,
+        claims: &Claims,
+        queue: &str,
+        visibility_timeout_ms: u32,
+    ) -> Result<Option<Task>, Error> {
+        let visibility_timeout_ms = match visibility_timeout_ms {
+            0 => DEFAULT_VISIBILITY_TIMEOUT_MS,
+            val => val,
+        };
+        let claim_id = Uuid::from_bytes(uuid::Uuid::new_v4().into_bytes());
+
+        // the first visible task of the queue the claims may write, a running
+        // task whose visibility timeout passed is claimed again
+        let t = Alias::new("t");
+        let (candidate, values) = Query::select()
+            .expr_as(
+                Expr::tbl(t.clone(), Alias::new("task_id")),
+                Alias::new("candidate_id"),
+            )
+            .from_as(Alias::new("tasks"), t.clone())
+            .inner_join(
+                ResourcesTable::Table,
+                Expr::tbl(ResourcesTable::Table, ResourcesTable::ResourceID)
+                    .equals(t.clone(), Alias::new("task_id")),
+            )
+            .and_where(Expr::tbl(t.clone(), Alias::new("queue")).eq(queue))
+            .and_where(
+                Expr::tbl(t.clone(), Alias::new("state"))
+                    .is_in(vec![TaskState::Pending as i32, TaskState::Running as i32]),
+            )
+            .and_where(Expr::cust("t.visible_at <= now()"))
+            .and_where(
+                Expr::tbl(t.clone(), Alias::new("attempts"))
+                    .less_than(Expr::tbl(t.clone(), Alias::new("max_attempts"))),
+            )
+            .and_where(Expr::tbl(ResourcesTable::Table, ResourcesTable::DeletedAt).is_null())
+            .and_where(self.permissions.condition(
+                Expr::tbl(ResourcesTable::Table, ResourcesTable::PermissionParentID),
+                "write",
+                claims,
+            )?)
+            .order_by((t, Alias::new("visible_at")), Order::Asc)
+            .limit(1)
+            .build(PostgresQueryBuilder);
+
+        // skipping the locked tasks lets concurrent workers claim different ones
+        let n = values.0.len();
+        let sql = format!(
+            r#"
+            UPDATE tasks SET
+                state = ${},
+                attempts = tasks.attempts + 1,
+                visible_at = now() + make_interval(secs => ${}),
+                claim_id = ${},
+                worker_id = ${}
+            FROM ({} FOR UPDATE OF t SKIP LOCKED) AS candidate
+            WHERE tasks.task_id = candidate.candidate_id
+            RETURNING {}"#,
+            n + 1,
+            n + 2,
+            n + 3,
+            n + 4,
+            candidate,
+            TASK_COLUMNS
+        );
+
+        let mut tx = self.pool.begin().await?;
+
+        let row: Option<TaskRow> = bind_query_as(sqlx::query_as(&sql), &values)
+            .bind(TaskState::Running as i32)
+            .bind(visibility_timeout_ms as f64 / 1000.0)
+            .bind(claim_id)
+            .bind(&claims.sub)
+            .fetch_optional(&mut tx)
+            .await?;
+        if let Some(row) = &row {
+            self.resources
+                .set_status_with_tx(&mut tx, &row.task_id, &row.status())
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(row.map(|row| row.into()))
+    }
+
+    /// Marks the claimed task as succeeded.
+    #[tracing::instrument(name = "mgr::tasks::complete", skip(self))]
+    pub async fn complete(
+        &self,
+        claims: &Claims,
+        id: &Uuid,
+        claim_id: &Uuid,
+        result: &serde_json::Value,
+    ) -> Result<Task, Error> {
+        self.permissions.check(id, "write", claims).await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        let row: Option<TaskRow> = sqlx::query_as(&format!(
+            r#"
+            UPDATE tasks SET state = $3, claim_id = NULL, result = $4, visible_at = now()
+            WHERE task_id = $1 AND claim_id = $2 AND state = $5
+            RETURNING {}"#,
+            TASK_COLUMNS
+        ))
+        .bind(id)
+        .bind(claim_id)
+        .bind(TaskState::Succeeded as i32)
+        .bind(result)
+        .bind(TaskState::Running as i32)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        self.finish(tx, id, claim_id, row).await
+    }
+
+    /// Schedules the claimed task for a retry with exponential backoff or moves
+    /// it to the dead letter state if it has no attempts left.
+    #[tracing::instrument(name = "mgr::tasks::fail", skip(self))]
+    pub async fn fail(
+        &self,
+        claims: &Claims,
+        id: &Uuid,
+        claim_id: &Uuid,
+        error: &str,
+    ) -> Result<Task, Error> {
+        self.permissions.check(id, "write", claims).await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        let row: Option<TaskRow> = sqlx::query_as(&format!(
+            r#"
+            UPDATE tasks SET
+                state = CASE WHEN attempts >= max_attempts THEN $3 ELSE $4 END,
+                visible_at = now() + make_interval(secs => LEAST($6, $7 * power(2, attempts - 1))),
+                claim_id = NULL,
+                last_error = $8
+            WHERE task_id = $1 AND claim_id = $2 AND state = $5
+            RETURNING {}"#,
+            TASK_COLUMNS
+        ))
+        .bind(id)
+        .bind(claim_id)
+        .bind(TaskState::DeadLetter as i32)
+        .bind(TaskState::Pending as i32)
+        .bind(TaskState::Running as i32)
+        .bind(BACKOFF_MAX_MS as f64 / 1000.0)
+        .bind(BACKOFF_BASE_MS as f64 / 1000.0)
+        .bind(error)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        self.finish(tx, id, claim_id, row).await
+    }
+
+    async fn finish(
+        &self,
+        mut tx: sqlx::Transaction<'_, sqlx::Postgres>,
+        id: &Uuid,
+        claim_id: &Uuid,
+        row: Option<TaskRow>,
+    ) -> Result<Task, Error> {
+        match row {
+            Some(row) => {
+                self.resources
+                    .set_status_with_tx(&mut tx, &row.task_id, &row.status())
+                    .await?;
+                tx.commit().await?;
+                Ok(row.into())
+            }
+            None => Err(Error::FailedPrecondition(format!(
+                "task {} is not claimed with {}",
+                id.to_hyphenated(),
+                claim_id.to_hyphenated()
+            ))),
+        }
+    }
+
+    /// Moves claimed tasks whose visibility timeout passed and which have no
+    /// attempts left to the dead letter state.
+    #[tracing::instrument(name = "mgr::tasks::dead_letter_expired", skip(self))]
+    pub async fn dead_letter_expired(&self) -> Result<usize, Error> {
+        let mut tx = self.pool.begin().await?;
+
+        let rows: Vec<TaskRow> = sqlx::query_as(&format!(
+            r#"
+            UPDATE tasks SET state = $1, claim_id = NULL, last_error = 'visibility timeout expired'
+            WHERE state = $2 AND visible_at <= now() AND attempts >= max_attempts
+            RETURNING {}"#,
+            TASK_COLUMNS
+        ))
+        .bind(TaskState::DeadLetter as i32)
+        .bind(TaskState::Running as i32)
+        .fetch_all(&mut tx)
+        .await?;
+
+        for row in rows.iter() {
+            self.resources
+                .set_status_with_tx(&mut tx, &row.task_id, &row.status())
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(rows.len())
+    }
+}
+
+fn state_name(state: i32) -> &'static str {
+    match TaskState::from_i32(state) {
+        Some(TaskState::Pending) => "PENDING",
+        Some(TaskState::Running) => "RUNNING",
+        Some(TaskState::Succeeded) => "SUCCEEDED",
+        Some(TaskState::DeadLetter) => "DEAD_LETTER",
+        None => "UNKNOWN",
+    }
+}
@@ -0,0 +1,96 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, patch};
+use axum::{Extension, Json, Router};
+use std::sync::Arc;
+
+use crate::managers;
+use crate::token;
+
+pub mod openapi;
+pub mod resources;
+
+/// Shared state of the REST handlers.
+#[derive(Debug)]
+pub struct State {
+    pub resources: Arc<managers::resources::Manager>,
+    pub schemas: Arc<managers::schemas::Manager>,
+    pub validator: Arc<token::Validator>,
+}
+
+/// Builds the JSON REST facade for the resource catalog:
+///
+/// * `GET /api/openapi.json` returns an OpenAPI document generated from the stored schemas
+/// * `GET|POST /api/v1/{kind}` lists or creates resources of a kind
+/// * `GET|PATCH|DELETE /api/v1/{kind}/{id}` retrieves, merge-patches or deletes a resource
+/// * `PATCH /api/v1/{kind}/{id}/status` merge-patches the status of a resource
+pub fn router(state: Arc<State>) -> Router {
+    Router::new()
+        .route("/api/openapi.json", get(openapi::document))
+        .route(
+            "/api/v1/:kind",
+            get(resources::list).post(resources::create),
+        )
+        .route(
+            "/api/v1/:kind/:id",
+            get(resources::get)
+                .patch(resources::update)
+                .delete(resources::delete),
+        )
+        .route("/api/v1/:kind/:id/status", patch(resources::update_status))
+        .layer(Extension(state))
+}
+
+#[derive(Debug)]
+pub struct Error {
+    status: StatusCode,
+    message: String,
+}
+
+impl Error {
+    pub fn new(status: StatusCode, message: &str) -> Error {
+        Error {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            Json(serde_json::json!({ "error": self.message })),
+        )
+            .into_response()
+    }
+}
+
+impl From<tonic::Status> for Error {
+    fn from(item: tonic::Status) -> Self {
+        use tonic::Code;
+        let status = match item.code() {
+            Code::InvalidArgument => StatusCode::BAD_REQUEST,
+            Code::NotFound => StatusCode::NOT_FOUND,
+            Code::AlreadyExists => StatusCode::CONFLICT,
+            Code::PermissionDenied => StatusCode::FORBIDDEN,
+            Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Code::FailedPrecondition => StatusCode::PRECONDITION_FAILED,
+            Code::ResourceExhausted => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Error::new(status, item.message())
+    }
+}
+
+impl From<managers::errors::Error> for Error {
+    fn from(item: managers::errors::Error) -> Self {
+        tonic::Status::from(item).into()
+    }
+}
+
+impl From<token::error::Error> for Error {
+    fn from(item: token::error::Error) -> Self {
+        tonic::Status::from(item).into()
+    }
+}
@@ -0,0 +1,347 @@
+use axum::extract::Extension;
+use axum::Json;
+use futures::TryStreamExt;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+use crate::api::catalog::Schema;
+
+use super::{Error, State};
+
+/// Serves the OpenAPI document of the REST API. Every stored schema becomes a
+/// component and gets its own set of paths below `/api/v1/{kind}`.
+#[tracing::instrument(name = "rest::openapi::document", skip(state))]
+pub async fn document(Extension(state): Extension<Arc<State>>) -> Result<Json<Value>, Error> {
+    let schemas: Vec<Schema> = state.schemas.list().await?.try_collect().await?;
+    Ok(Json(build(&schemas)?))
+}
+
+pub fn build(schemas: &[Schema]) -> Result<Value, Error> {
+    let mut components = Map::new();
+    let mut paths = Map::new();
+
+    components.insert(
+        "Error".to_string(),
+        json!({
+            "type": "object",
+            "properties": { "error": { "type": "string" } }
+        }),
+    );
+
+    for schema in schemas {
+        let kind = &schema.kind;
+        let data: Value = serde_json::from_str(&schema.data).map_err(|err| {
+            Error::new(
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("failed to decode schema {}: {}", kind, err),
+            )
+        })?;
+
+        components.insert(kind.to_string(), data);
+        components.insert(format!("{}Resource", kind), resource_schema(kind));
+
+        paths.insert(format!("/api/v1/{}", kind), collection_path(kind));
+        paths.insert(format!("/api/v1/{}/{{id}}", kind), item_path(kind));
+        paths.insert(format!("/api/v1/{}/{{id}}/status", kind), status_path(kind));
+    }
+
+    Ok(json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "opencore",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "security": [{ "bearer": [] }],
+        "paths": paths,
+        "components": {
+            "schemas": components,
+            "securitySchemes": {
+                "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" }
+            }
+        }
+    }))
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn resource_schema(kind: &str) -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "string", "format": "uuid" },
+            "parent_id": { "type": "string", "format": "uuid" },
+            "permission_parent_id": { "type": "string", "format": "uuid" },
+            "creator_id": { "type": "string", "format": "uuid" },
+            "kind": { "type": "string", "const": kind },
+            "data": reference(kind),
+            "labels": { "type": "object", "additionalProperties": { "type": "string" } },
+            "created_at": { "type": "string", "format": "date-time" },
+            "updated_at": { "type": "string", "format": "date-time" },
+            "deleted_at": { "type": "string", "format": "date-time" },
+            "expires_at": { "type": "string", "format": "date-time" },
+            "status": { "description": "reported by the controllers of the resource, null until it is set" },
+            "generation": { "type": "integer", "description": "incremented on every change of the data" },
+            "observed_generation": { "type": "integer", "description": "the generation the status was computed from" },
+            "highlight": { "type": "string" },
+            "expanded": {
+                "type": "object",
+                "description": "the resources requested with expand, their data is not typed since references may point to any kind",
+                "additionalProperties": { "type": "object" }
+            }
+        }
+    })
+}
+
+fn json_content(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn error_responses() -> Value {
+    let error = json!({ "description": "error", "content": json_content(reference("Error")) });
+    json!({
+        "400": error,
+        "401": error,
+        "403": error,
+        "404": error,
+    })
+}
+
+fn with_errors(mut responses: Value) -> Value {
+    if let (Value::Object(responses), Value::Object(errors)) = (&mut responses, error_responses()) {
+        responses.extend(errors);
+    }
+    responses
+}
+
+fn collection_path(kind: &str) -> Value {
+    let resource = reference(&format!("{}Resource", kind));
+    json!({
+        "get": {
+            "operationId": format!("list_{}", kind),
+            "tags": [kind],
+            "parameters": [
+                {
+                    "name": "filter",
+                    "in": "query",
+                    "description": "JSONPath predicate evaluated against the resource data",
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "where",
+                    "in": "query",
+                    "description": "typed filter: {\"and\": [..]}, {\"or\": [..]}, {\"not\": {..}} or a comparison {\"path\"|\"label\"|\"attribute\": .., \"operator\": \"EQ\"|\"NE\"|\"LT\"|\"LE\"|\"GT\"|\"GE\"|\"IN\", \"value\": ..}",
+                    "content": json_content(json!({ "type": "object" }))
+                },
+                {
+                    "name": "query",
+                    "in": "query",
+                    "description": "full text search term, results are ordered by rank",
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "highlight",
+                    "in": "query",
+                    "description": "return snippets of the text matching the search term",
+                    "schema": { "type": "boolean" }
+                },
+                {
+                    "name": "parent_id",
+                    "in": "query",
+                    "description": "only return direct children of this resource",
+                    "schema": { "type": "string", "format": "uuid" }
+                },
+                {
+                    "name": "descendants_of",
+                    "in": "query",
+                    "description": "only return descendants of this resource",
+                    "schema": { "type": "string", "format": "uuid" }
+                },
+                {
+                    "name": "max_depth",
+                    "in": "query",
+                    "description": "number of levels below descendants_of to include (0 = unlimited)",
+                    "schema": { "type": "integer", "minimum": 0 }
+                },
+                {
+                    "name": "fields",
+                    "in": "query",
+                    "description": "comma separated list of dot separated paths, only these are returned from the data",
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "expand",
+                    "in": "query",
+                    "description": "comma separated list of parent_id, permission_parent_id and reference properties whose resources are embedded",
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "include_deleted",
+                    "in": "query",
+                    "description": "also return deleted resources, only allowed for admins",
+                    "schema": { "type": "boolean" }
+                },
+                {
+                    "name": "label",
+                    "in": "query",
+                    "description": "label selectors, passed as label.<key>=<value>",
+                    "style": "deepObject",
+                    "schema": { "type": "object", "additionalProperties": { "type": "string" } }
+                }
+            ],
+            "responses": with_errors(json!({
+                "200": {
+                    "description": "the matching resources",
+                    "content": json_content(json!({ "type": "array", "items": resource }))
+                }
+            }))
+        },
+        "post": {
+            "operationId": format!("create_{}", kind),
+            "tags": [kind],
+            "requestBody": {
+                "required": true,
+                "content": json_content(json!({
+                    "type": "object",
+                    "required": ["data"],
+                    "properties": {
+                        "parent_id": { "type": "string", "format": "uuid" },
+                        "permission_parent_id": { "type": "string", "format": "uuid" },
+                        "data": reference(kind),
+                        "labels": { "type": "object", "additionalProperties": { "type": "string" } },
+                        "shares": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "principal_id": { "type": "string" },
+                                    "actions": { "type": "array", "items": { "type": "string" } }
+                                }
+                            }
+                        },
+                        "expires_at": {
+                            "type": "string",
+                            "format": "date-time",
+                            "description": "delete the resource automatically at this time, defaults to now plus the x-ttl of the schema"
+                        }
+                    }
+                }))
+            },
+            "responses": with_errors(json!({
+                "201": { "description": "the created resource", "content": json_content(resource) }
+            }))
+        }
+    })
+}
+
+fn item_path(kind: &str) -> Value {
+    let resource = reference(&format!("{}Resource", kind));
+    json!({
+        "parameters": [
+            { "name": "id", "in": "path", "required": true, "schema": { "type": "string", "format": "uuid" } }
+        ],
+        "get": {
+            "operationId": format!("get_{}", kind),
+            "tags": [kind],
+            "parameters": [
+                {
+                    "name": "fields",
+                    "in": "query",
+                    "description": "comma separated list of dot separated paths, only these are returned from the data",
+                    "schema": { "type": "string" }
+                },
+                {
+                    "name": "expand",
+                    "in": "query",
+                    "description": "comma separated list of parent_id, permission_parent_id and reference properties whose resources are embedded",
+                    "schema": { "type": "string" }
+                }
+            ],
+            "responses": with_errors(json!({
+                "200": { "description": "the resource", "content": json_content(resource.clone()) }
+            }))
+        },
+        "patch": {
+            "operationId": format!("update_{}", kind),
+            "tags": [kind],
+            "requestBody": {
+                "required": true,
+                "content": json_content(json!({
+                    "type": "object",
+                    "properties": {
+                        "data": { "description": "the patch, interpreted according to patch_type" },
+                        "patch_type": {
+                            "type": "string",
+                            "enum": ["merge", "json", "replace"],
+                            "default": "merge",
+                            "description": "JSON merge patch (RFC 7396), JSON patch (RFC 6902) or full replacement"
+                        },
+                        "update_mask": {
+                            "type": "array",
+                            "items": { "type": "string" },
+                            "description": "dot separated paths, only these are taken from the patched document"
+                        },
+                        "labels": {
+                            "type": "object",
+                            "description": "labels to set, an empty value removes the label",
+                            "additionalProperties": { "type": "string" }
+                        },
+                        "expires_at": {
+                            "type": ["string", "null"],
+                            "format": "date-time",
+                            "description": "changes the time the resource is deleted automatically, null removes the expiry"
+                        }
+                    }
+                }))
+            },
+            "responses": with_errors(json!({
+                "200": { "description": "the updated resource", "content": json_content(resource.clone()) }
+            }))
+        },
+        "delete": {
+            "operationId": format!("delete_{}", kind),
+            "tags": [kind],
+            "responses": with_errors(json!({
+                "200": { "description": "the deleted resource", "content": json_content(resource) }
+            }))
+        }
+    })
+}
+
+fn status_path(kind: &str) -> Value {
+    let resource = reference(&format!("{}Resource", kind));
+    json!({
+        "parameters": [
+            { "name": "id", "in": "path", "required": true, "schema": { "type": "string", "format": "uuid" } }
+        ],
+        "patch": {
+            "operationId": format!("update_{}_status", kind),
+            "tags": [kind],
+            "description": "requires the status permission, the data and the generation of the resource stay untouched",
+            "requestBody": {
+                "required": true,
+                "content": json_content(json!({
+                    "type": "object",
+                    "required": ["status"],
+                    "properties": {
+                        "status": { "description": "the patch, interpreted according to patch_type" },
+                        "patch_type": {
+                            "type": "string",
+                            "enum": ["merge", "json", "replace"],
+                            "default": "merge",
+                            "description": "JSON merge patch (RFC 7396), JSON patch (RFC 6902) or full replacement"
+                        },
+                        "observed_generation": {
+                            "type": "integer",
+                            "description": "the generation of the data the status belongs to, 0 keeps the observed generation"
+                        }
+                    }
+                }))
+            },
+            "responses": with_errors(json!({
+                "200": { "description": "the updated resource", "content": json_content(resource) }
+            }))
+        }
+    })
+}
@@ -0,0 +1,449 @@
+use axum::extract::{Extension, Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use futures::TryStreamExt;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use crate::api;
+use crate::managers;
+
+use api::catalog::{Resource, ShareRequest};
+use managers::resources::Patch;
+
+use super::{Error, State};
+
+/// JSON representation of a resource. Unlike the gRPC message the data is
+/// embedded as JSON value instead of an encoded string.
+#[derive(serde::Serialize, Debug)]
+pub struct ResourceDocument {
+    pub id: String,
+    pub parent_id: String,
+    pub permission_parent_id: String,
+    pub creator_id: String,
+    pub kind: String,
+    pub data: serde_json::Value,
+    pub labels: HashMap<String, String>,
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: serde_json::Value,
+    pub generation: i64,
+    pub observed_generation: i64,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub highlight: String,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub expanded: HashMap<String, ResourceDocument>,
+}
+
+impl TryFrom<Resource> for ResourceDocument {
+    type Error = Error;
+
+    fn try_from(r: Resource) -> Result<Self, Self::Error> {
+        let data = match serde_json::from_str(&r.data) {
+            Ok(data) => data,
+            Err(err) => {
+                return Err(Error::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("failed to decode resource data: {}", err),
+                ))
+            }
+        };
+        let status = match serde_json::from_str(&r.status) {
+            Ok(status) => status,
+            Err(err) => {
+                return Err(Error::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("failed to decode resource status: {}", err),
+                ))
+            }
+        };
+        Ok(ResourceDocument {
+            id: r.id,
+            parent_id: r.parent_id,
+            permission_parent_id: r.permission_parent_id,
+            creator_id: r.creator_id,
+            kind: r.kind,
+            data,
+            labels: r.labels,
+            created_at: r.created_at.map(to_datetime),
+            updated_at: r.updated_at.map(to_datetime),
+            deleted_at: r.deleted_at.map(to_datetime),
+            expires_at: r.expires_at.map(to_datetime),
+            status,
+            generation: r.generation,
+            observed_generation: r.observed_generation,
+            highlight: r.highlight,
+            expanded: r
+                .expanded
+                .into_iter()
+                .map(|(name, res)| Ok((name, ResourceDocument::try_from(res)?)))
+                .collect::<Result<_, Error>>()?,
+        })
+    }
+}
+
+fn to_datetime(ts: prost_types::Timestamp) -> chrono::DateTime<chrono::Utc> {
+    use chrono::TimeZone;
+    chrono::Utc.timestamp(ts.seconds, ts.nanos as u32)
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Share {
+    principal_id: String,
+    actions: Vec<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct CreateBody {
+    #[serde(default)]
+    parent_id: Option<String>,
+    #[serde(default)]
+    permission_parent_id: Option<String>,
+    data: serde_json::Value,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    #[serde(default)]
+    shares: Vec<Share>,
+    #[serde(default)]
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// Distinguishes an explicit null from a missing field.
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: serde::Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PatchType {
+    Merge,
+    Json,
+    Replace,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct UpdateBody {
+    #[serde(default)]
+    data: Option<serde_json::Value>,
+    #[serde(default)]
+    patch_type: Option<PatchType>,
+    #[serde(default)]
+    update_mask: Vec<String>,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+    /// null removes the expiry, a missing field keeps it
+    #[serde(default, deserialize_with = "deserialize_some")]
+    expires_at: Option<Option<chrono::DateTime<chrono::Utc>>>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct UpdateStatusBody {
+    status: serde_json::Value,
+    #[serde(default)]
+    patch_type: Option<PatchType>,
+    /// 0 keeps the observed generation
+    #[serde(default)]
+    observed_generation: i64,
+}
+
+fn parse_uuid(id: &str) -> Result<Uuid, Error> {
+    match Uuid::parse_str(id) {
+        Ok(id) => Ok(id),
+        Err(err) => Err(Error::new(
+            StatusCode::BAD_REQUEST,
+            &format!("failed to parse uuid: {}", err),
+        )),
+    }
+}
+
+// Splits the comma separated `fields` query parameter.
+fn parse_fields(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter(|field| !field.is_empty())
+        .map(|field| field.to_string())
+        .collect()
+}
+
+/// Fetches a resource and makes sure it is of the kind addressed by the path.
+async fn fetch(
+    state: &State,
+    headers: &HeaderMap,
+    kind: &str,
+    id: &Uuid,
+    fields: &[String],
+) -> Result<Resource, Error> {
+    let claims = state.validator.get_context_from_headers(headers)?.claims;
+    let res = state.resources.get_with_fields(&claims, id, fields).await?;
+    if res.kind != kind {
+        return Err(Error::new(StatusCode::NOT_FOUND, "not found"));
+    }
+    Ok(res)
+}
+
+/// Lists resources of a kind. Supported query parameters are `filter` (JSONPath),
+/// `query` (full text search, `highlight=true` returns snippets of the matching
+/// text), `label.<key>=<value>` to select by labels and
+/// `parent_id` or `descendants_of` (with an optional `max_depth`) to select by
+/// position in the hierarchy. Admins may pass `include_deleted=true` to also get
+/// deleted resources. `fields` limits the returned data to a comma separated list
+/// of dot separated paths, `expand` embeds the resources referenced by a comma
+/// separated list of parent_id, permission_parent_id and reference properties.
+/// `where` takes a typed filter as JSON, e.g.
+/// `{"path":"meta.owner","operator":"EQ","value":"alice"}`.
+#[tracing::instrument(name = "rest::resources::list", skip(state))]
+pub async fn list(
+    Path(kind): Path<String>,
+    Query(params): Query<Vec<(String, String)>>,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<State>>,
+) -> Result<Json<Vec<ResourceDocument>>, Error> {
+    let claims = state.validator.get_context_from_headers(&headers)?.claims;
+
+    let mut labels = HashMap::new();
+    let mut filter = String::new();
+    let mut query = String::new();
+    let mut parent_id = None;
+    let mut descendants_of = None;
+    let mut max_depth = 0;
+    let mut include_deleted = false;
+    let mut highlight = false;
+    let mut fields = vec![];
+    let mut expand = vec![];
+    let mut where_filter = None;
+    for (key, value) in params {
+        match key.as_str() {
+            "filter" => filter = value,
+            "query" => query = value,
+            "parent_id" => parent_id = Some(parse_uuid(&value)?),
+            "descendants_of" => descendants_of = Some(parse_uuid(&value)?),
+            "max_depth" => {
+                max_depth = value.parse().map_err(|_| {
+                    Error::new(StatusCode::BAD_REQUEST, "max_depth must be a number")
+                })?
+            }
+            "fields" => fields = parse_fields(&value),
+            "expand" => expand = parse_fields(&value),
+            "where" => {
+                let value = serde_json::from_str(&value).map_err(|err| {
+                    Error::new(
+                        StatusCode::BAD_REQUEST,
+                        &format!("where is no valid JSON: {}", err),
+                    )
+                })?;
+                where_filter = Some(managers::resources::filter_from_json(&value)?)
+            }
+            "highlight" => {
+                highlight = value.parse().map_err(|_| {
+                    Error::new(StatusCode::BAD_REQUEST, "highlight must be a boolean")
+                })?
+            }
+            "include_deleted" => {
+                include_deleted = value.parse().map_err(|_| {
+                    Error::new(StatusCode::BAD_REQUEST, "include_deleted must be a boolean")
+                })?
+            }
+            _ => match key.strip_prefix("label.") {
+                Some(label) => {
+                    labels.insert(label.to_string(), value);
+                }
+                None => {
+                    return Err(Error::new(
+                        StatusCode::BAD_REQUEST,
+                        &format!("unknown query parameter '{}'", key),
+                    ))
+                }
+            },
+        }
+    }
+
+    let resources: Vec<Resource> = state
+        .resources
+        .list(managers::resources::ListOptions {
+            claims: &claims,
+            labels: &labels,
+            filter: &filter,
+            kind: &kind,
+            search_term: &query,
+            highlight,
+            parent_id: parent_id.as_ref(),
+            descendants_of: descendants_of.as_ref(),
+            max_depth,
+            include_deleted,
+            fields: &fields,
+            where_filter: where_filter.as_ref(),
+            expand: &expand,
+        })
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut res = vec![];
+    for resource in resources {
+        res.push(ResourceDocument::try_from(resource)?);
+    }
+    Ok(Json(res))
+}
+
+/// Retrieves a resource. `fields` limits the returned data to a comma separated
+/// list of dot separated paths, `expand` embeds referenced resources like in list.
+#[tracing::instrument(name = "rest::resources::get", skip(state))]
+pub async fn get(
+    Path((kind, id)): Path<(String, String)>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<State>>,
+) -> Result<Json<ResourceDocument>, Error> {
+    let id = parse_uuid(&id)?;
+    let fields = params
+        .get("fields")
+        .map(|value| parse_fields(value))
+        .unwrap_or_default();
+    let expand = params
+        .get("expand")
+        .map(|value| parse_fields(value))
+        .unwrap_or_default();
+    let mut res = fetch(&state, &headers, &kind, &id, &fields).await?;
+    let claims = state.validator.get_context_from_headers(&headers)?.claims;
+    state
+        .resources
+        .expand(&claims, std::slice::from_mut(&mut res), &expand)
+        .await?;
+    Ok(Json(ResourceDocument::try_from(res)?))
+}
+
+#[tracing::instrument(name = "rest::resources::create", skip(state))]
+pub async fn create(
+    Path(kind): Path<String>,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<State>>,
+    Json(body): Json<CreateBody>,
+) -> Result<(StatusCode, Json<ResourceDocument>), Error> {
+    let context = state.validator.get_context_from_headers(&headers)?;
+
+    let parent_id = match &body.parent_id {
+        Some(id) if !id.is_empty() => Some(parse_uuid(id)?),
+        _ => None,
+    };
+    let permission_parent_id = match &body.permission_parent_id {
+        Some(id) if !id.is_empty() => Some(parse_uuid(id)?),
+        _ => None,
+    };
+    let shares: Vec<ShareRequest> = body
+        .shares
+        .iter()
+        .map(|share| ShareRequest {
+            resource_id: String::new(),
+            principal_id: share.principal_id.clone(),
+            actions: share.actions.clone(),
+        })
+        .collect();
+
+    let res = state
+        .resources
+        .create(managers::resources::CreateOptions {
+            context: &context,
+            kind: &kind,
+            parent_id: parent_id.as_ref(),
+            permission_parent_id: permission_parent_id.as_ref(),
+            data: &body.data,
+            labels: &body.labels,
+            shares: &shares,
+            expires_at: body.expires_at.as_ref(),
+        })
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(ResourceDocument::try_from(res)?)))
+}
+
+/// Applies a patch to the data of a resource. By default `data` is a JSON merge
+/// patch, `patch_type` selects a JSON patch (`json`) or a full replacement
+/// (`replace`) instead. Labels with an empty value are removed. `expires_at`
+/// changes the expiry of the resource, null removes it.
+#[tracing::instrument(name = "rest::resources::update", skip(state))]
+pub async fn update(
+    Path((kind, id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<State>>,
+    Json(body): Json<UpdateBody>,
+) -> Result<Json<ResourceDocument>, Error> {
+    let context = state.validator.get_context_from_headers(&headers)?;
+    let id = parse_uuid(&id)?;
+    fetch(&state, &headers, &kind, &id, &[]).await?;
+
+    let data = body
+        .data
+        .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+    let patch = to_patch(body.patch_type, data)?;
+    let res = state
+        .resources
+        .update(
+            &context,
+            &id,
+            &patch,
+            &body.update_mask,
+            &body.labels,
+            body.expires_at,
+        )
+        .await?;
+    Ok(Json(ResourceDocument::try_from(res)?))
+}
+
+/// Applies a patch to the status of a resource like update does to the data.
+/// `observed_generation` records the generation the status was computed from.
+#[tracing::instrument(name = "rest::resources::update_status", skip(state))]
+pub async fn update_status(
+    Path((kind, id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<State>>,
+    Json(body): Json<UpdateStatusBody>,
+) -> Result<Json<ResourceDocument>, Error> {
+    let context = state.validator.get_context_from_headers(&headers)?;
+    let id = parse_uuid(&id)?;
+    fetch(&state, &headers, &kind, &id, &[]).await?;
+
+    let patch = to_patch(body.patch_type, body.status)?;
+    let res = state
+        .resources
+        .update_status(&context, &id, &patch, body.observed_generation)
+        .await?;
+    Ok(Json(ResourceDocument::try_from(res)?))
+}
+
+fn to_patch(patch_type: Option<PatchType>, doc: serde_json::Value) -> Result<Patch, Error> {
+    match patch_type.unwrap_or(PatchType::Merge) {
+        PatchType::Merge => Ok(Patch::Merge(doc)),
+        PatchType::Json => match json_patch::from_value(doc) {
+            Ok(patch) => Ok(Patch::Json(patch)),
+            Err(err) => Err(Error::new(
+                StatusCode::BAD_REQUEST,
+                &format!("failed to parse json patch: {}", err),
+            )),
+        },
+        PatchType::Replace => Ok(Patch::Replace(doc)),
+    }
+}
+
+#[tracing::instrument(name = "rest::resources::delete", skip(state))]
+pub async fn delete(
+    Path((kind, id)): Path<(String, String)>,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<State>>,
+) -> Result<Json<ResourceDocument>, Error> {
+    let context = state.validator.get_context_from_headers(&headers)?;
+    let id = parse_uuid(&id)?;
+    fetch(&state, &headers, &kind, &id, &[]).await?;
+
+    let res = state.resources.delete(&context, &id).await?;
+    Ok(Json(ResourceDocument::try_from(res)?))
+}
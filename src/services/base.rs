@@ -27,4 +27,18 @@ pub trait BaseService {
             }
         }
     }
+
+    #[tracing::instrument]
+    fn parse_timestamp(
+        ts: &prost_types::Timestamp,
+    ) -> Result<chrono::DateTime<chrono::Utc>, Status> {
+        use chrono::TimeZone;
+        match chrono::Utc
+            .timestamp_opt(ts.seconds, ts.nanos as u32)
+            .single()
+        {
+            Some(ts) => Ok(ts),
+            None => Err(Status::invalid_argument("invalid timestamp")),
+        }
+    }
 }
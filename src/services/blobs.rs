@@ -0,0 +1,151 @@
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::api;
+use crate::token;
+use api::catalog::blobs_server::Blobs;
+use api::catalog::{
+    download_blob_response, upload_blob_request, Blob, DeleteBlobRequest, DownloadBlobRequest,
+    DownloadBlobResponse, GetBlobRequest, ListBlobsRequest, UploadBlobRequest,
+};
+
+use crate::managers;
+use crate::managers::errors::Error;
+
+use super::base::BaseService;
+
+#[derive(Debug)]
+pub struct Service {
+    mgr: Arc<managers::blobs::Manager>,
+    validator: Arc<token::Validator>,
+}
+
+impl BaseService for Service {}
+
+impl Service {
+    pub fn new(
+        mgr: Arc<managers::blobs::Manager>,
+        validator: Arc<token::Validator>,
+    ) -> Result<Service, sqlx::Error> {
+        let res = Service { mgr, validator };
+        Ok(res)
+    }
+}
+
+#[tonic::async_trait]
+impl Blobs for Service {
+    #[tracing::instrument(name = "svc::blobs::upload", skip(self))]
+    async fn upload(
+        &self,
+        request: Request<Streaming<UploadBlobRequest>>,
+    ) -> Result<Response<Blob>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let mut stream = request.into_inner();
+
+        let info = match stream.message().await? {
+            Some(UploadBlobRequest {
+                content: Some(upload_blob_request::Content::Info(info)),
+            }) => info,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "the first message must contain the blob info",
+                ))
+            }
+        };
+        let resource_id = Self::parse_uuid(&info.resource_id)?;
+
+        let chunks = stream.map(|msg| match msg {
+            Ok(UploadBlobRequest {
+                content: Some(upload_blob_request::Content::Chunk(chunk)),
+            }) => Ok(chunk),
+            Ok(_) => Err(Error::InvalidArgument(
+                "all messages after the first one must contain a chunk".into(),
+            )),
+            Err(status) => Err(Error::Send(format!(
+                "failed to receive chunk: {}",
+                status.message()
+            ))),
+        });
+
+        let res = self
+            .mgr
+            .upload(
+                &claims,
+                &resource_id,
+                &info.name,
+                &info.mime_type,
+                Box::pin(chunks),
+            )
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    type DownloadStream =
+        Pin<Box<dyn Stream<Item = Result<DownloadBlobResponse, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::blobs::download", skip(self))]
+    async fn download(
+        &self,
+        request: Request<DownloadBlobRequest>,
+    ) -> Result<Response<Self::DownloadStream>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let (blob, mut chunks) = self.mgr.download(&claims, &id).await?;
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let first = DownloadBlobResponse {
+                content: Some(download_blob_response::Content::Blob(blob)),
+            };
+            if tx.send(Ok(first)).await.is_err() {
+                return;
+            }
+            while let Some(chunk) = chunks.next().await {
+                let msg = chunk
+                    .map(|chunk| DownloadBlobResponse {
+                        content: Some(download_blob_response::Content::Chunk(chunk)),
+                    })
+                    .map_err(Status::from);
+                if tx.send(msg).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(
+            tokio_stream::wrappers::ReceiverStream::new(rx),
+        )))
+    }
+
+    #[tracing::instrument(name = "svc::blobs::get", skip(self))]
+    async fn get(&self, request: Request<GetBlobRequest>) -> Result<Response<Blob>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.get(&claims, &id).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::blobs::delete", skip(self))]
+    async fn delete(&self, request: Request<DeleteBlobRequest>) -> Result<Response<Blob>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.delete(&claims, &id).await?;
+        Ok(Response::new(res))
+    }
+
+    type ListStream = Pin<Box<dyn Stream<Item = Result<Blob, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::blobs::list", skip(self))]
+    async fn list(
+        &self,
+        request: Request<ListBlobsRequest>,
+    ) -> Result<Response<Self::ListStream>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let resource_id = Self::parse_uuid(&request.get_ref().resource_id)?;
+        let res = self.mgr.list(&claims, &resource_id).await?;
+        Ok(Response::new(res))
+    }
+}
@@ -0,0 +1,76 @@
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+use crate::api;
+use api::catalog::elections_server::Elections;
+use api::catalog::{CampaignRequest, CampaignResponse, Leader, ObserveRequest, ResignRequest};
+
+use crate::managers;
+use crate::token;
+
+use super::base::BaseService;
+
+#[derive(Debug)]
+pub struct Service {
+    mgr: Arc<managers::elections::Manager>,
+    validator: Arc<token::Validator>,
+}
+
+impl BaseService for Service {}
+
+impl Service {
+    pub fn new(
+        mgr: Arc<managers::elections::Manager>,
+        validator: Arc<token::Validator>,
+    ) -> Result<Service, sqlx::Error> {
+        let res = Service { mgr, validator };
+        Ok(res)
+    }
+}
+
+#[tonic::async_trait]
+impl Elections for Service {
+    type CampaignStream =
+        Pin<Box<dyn Stream<Item = Result<CampaignResponse, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::elections::campaign", skip(self))]
+    async fn campaign(
+        &self,
+        request: Request<CampaignRequest>,
+    ) -> Result<Response<Self::CampaignStream>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        let resource_id = match r.resource_id.as_str() {
+            "" => None,
+            id => Some(Self::parse_uuid(id)?),
+        };
+        let res = self
+            .mgr
+            .campaign(&claims, &r.name, &r.candidate, resource_id)
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    type ObserveStream =
+        Pin<Box<dyn Stream<Item = Result<Leader, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::elections::observe", skip(self))]
+    async fn observe(
+        &self,
+        request: Request<ObserveRequest>,
+    ) -> Result<Response<Self::ObserveStream>, Status> {
+        self.validator.get_access_token_claims(&request)?;
+        let res = self.mgr.observe(&request.get_ref().name).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::elections::resign", skip(self))]
+    async fn resign(&self, request: Request<ResignRequest>) -> Result<Response<Leader>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        let res = self.mgr.resign(&claims, &r.name, r.term).await?;
+        Ok(Response::new(res))
+    }
+}
@@ -11,6 +11,11 @@ impl From<Error> for tonic::Status {
             Error::InvalidFencingToken => {
                 tonic::Status::resource_exhausted("invalid fencing token")
             }
+            Error::Rejected(cause) => {
+                tonic::Status::failed_precondition(format!("rejected by admission hook: {}", cause))
+            }
+            Error::FailedPrecondition(cause) => tonic::Status::failed_precondition(cause),
+            Error::ResourceExhausted(cause) => tonic::Status::resource_exhausted(cause),
         }
     }
 }
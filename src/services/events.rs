@@ -82,6 +82,15 @@ impl Events for Service {
             filters.push(SubscribeFilter::Type(event_type));
         }
 
+        if request.get_ref().exclude_status {
+            if event_type == EventType::StatusUpdate {
+                return Err(Status::invalid_argument(
+                    "'exclude_status' can't be combined with the event type STATUS_UPDATE",
+                ));
+            }
+            filters.push(SubscribeFilter::ExcludeStatus);
+        }
+
         let stream = self.manager.subscribe(&claims, &filters).await?;
         Ok(Response::new(stream))
     }
@@ -93,6 +102,8 @@ impl From<i32> for api::catalog::EventType {
             1 => api::catalog::EventType::Create,
             2 => api::catalog::EventType::Update,
             3 => api::catalog::EventType::Delete,
+            4 => api::catalog::EventType::Scheduled,
+            5 => api::catalog::EventType::StatusUpdate,
             _ => api::catalog::EventType::None,
         }
     }
@@ -0,0 +1,106 @@
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+use crate::api;
+use crate::token;
+use api::catalog::hooks_server::Hooks;
+use api::catalog::{
+    CreateHookRequest, DeleteHookRequest, GetHookRequest, Hook, HookFailurePolicy, HookProtocol,
+    HookType, ListHooksRequest,
+};
+
+use crate::managers;
+
+use super::base::BaseService;
+
+#[derive(Debug)]
+pub struct Service {
+    mgr: Arc<managers::hooks::Manager>,
+    validator: Arc<token::Validator>,
+}
+
+impl BaseService for Service {}
+
+impl Service {
+    pub fn new(
+        mgr: Arc<managers::hooks::Manager>,
+        validator: Arc<token::Validator>,
+    ) -> Result<Service, sqlx::Error> {
+        let res = Service { mgr, validator };
+        Ok(res)
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn check_admin<T: std::fmt::Debug>(&self, req: &Request<T>) -> Result<(), Status> {
+        let claims = self.validator.get_access_token_claims(req)?;
+        if !claims.adm {
+            return Err(Status::permission_denied(
+                "only admins are allowed to access the hook service",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Hooks for Service {
+    #[tracing::instrument(name = "svc::hooks::create", skip(self))]
+    async fn create(&self, request: Request<CreateHookRequest>) -> Result<Response<Hook>, Status> {
+        self.check_admin(&request)?;
+        let r = request.get_ref();
+        let hook_type = match HookType::from_i32(r.hook_type) {
+            Some(val) => val,
+            None => return Err(Status::invalid_argument("unknown hook type")),
+        };
+        let protocol = match HookProtocol::from_i32(r.protocol) {
+            Some(val) => val,
+            None => return Err(Status::invalid_argument("unknown hook protocol")),
+        };
+        let failure_policy = match HookFailurePolicy::from_i32(r.failure_policy) {
+            Some(val) => val,
+            None => return Err(Status::invalid_argument("unknown hook failure policy")),
+        };
+        let res = self
+            .mgr
+            .create(
+                &r.kind,
+                hook_type,
+                protocol,
+                &r.url,
+                r.timeout_ms,
+                failure_policy,
+            )
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::hooks::get", skip(self))]
+    async fn get(&self, request: Request<GetHookRequest>) -> Result<Response<Hook>, Status> {
+        self.check_admin(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.get(&id).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::hooks::delete", skip(self))]
+    async fn delete(&self, request: Request<DeleteHookRequest>) -> Result<Response<Hook>, Status> {
+        self.check_admin(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.delete(&id).await?;
+        Ok(Response::new(res))
+    }
+
+    type ListStream = Pin<Box<dyn Stream<Item = Result<Hook, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::hooks::list", skip(self))]
+    async fn list(
+        &self,
+        request: Request<ListHooksRequest>,
+    ) -> Result<Response<Self::ListStream>, Status> {
+        self.check_admin(&request)?;
+        let res = self.mgr.list(&request.get_ref().kind).await?;
+        Ok(Response::new(res))
+    }
+}
@@ -1,17 +1,29 @@
 use futures::Stream;
+use sqlx::types::Uuid;
 use std::pin::Pin;
 use std::sync::Arc;
 use tonic::{Request, Response, Status};
 
 use crate::api;
 use api::catalog::locks_server::Locks;
-use api::catalog::{LockRequest, LockResponse};
+use api::catalog::{
+    ForceReleaseRequest, GetLockRequest, Lease, ListLocksRequest, LockInfo, LockMode, LockRequest,
+    LockResponse, ReleaseLeaseRequest, RenewLeaseRequest,
+};
 
 use crate::managers;
+use crate::managers::errors::Error;
+use crate::managers::locks::LockOptions;
 use crate::token;
 
 use super::base::BaseService;
 
+// Namespaces of the locks held by other services on behalf of their callers.
+const RESERVED_NAMESPACES: [&str; 2] = [
+    managers::elections::NAMESPACE,
+    managers::schedules::NAMESPACE,
+];
+
 #[derive(Debug)]
 pub struct Service {
     mgr: Arc<managers::locks::Manager>,
@@ -28,6 +40,49 @@ impl Service {
         let res = Service { mgr, validator };
         Ok(res)
     }
+
+    fn check_namespace(namespace: &str) -> Result<(), Error> {
+        match RESERVED_NAMESPACES.contains(&namespace) {
+            true => Err(Error::InvalidArgument(format!(
+                "namespace '{}' is reserved",
+                namespace
+            ))),
+            false => Ok(()),
+        }
+    }
+
+    fn lock_options<'a>(
+        claims: &'a token::Claims,
+        r: &'a LockRequest,
+    ) -> Result<LockOptions<'a>, Error> {
+        Self::check_namespace(&r.namespace)?;
+        let mode = match LockMode::from_i32(r.mode) {
+            Some(mode) => mode,
+            None => return Err(Error::InvalidArgument("invalid lock mode".into())),
+        };
+        let resource_id = match r.resource_id.as_str() {
+            "" => None,
+            id => match Uuid::parse_str(id) {
+                Ok(id) => Some(id),
+                Err(err) => {
+                    return Err(Error::InvalidArgument(format!(
+                        "failed to parse uuid: {}",
+                        err
+                    )))
+                }
+            },
+        };
+        Ok(LockOptions {
+            claims,
+            holder_name: &r.holder_name,
+            namespace: &r.namespace,
+            lock_id: &r.lock_id,
+            resource_id,
+            mode,
+            limit: r.limit,
+            acquire_timeout_ms: r.acquire_timeout_ms,
+        })
+    }
 }
 
 #[tonic::async_trait]
@@ -40,8 +95,9 @@ impl Locks for Service {
         &self,
         request: Request<LockRequest>,
     ) -> Result<Response<Self::LockStream>, Status> {
-        self.validator.get_access_token_claims(&request)?;
-        let res = self.mgr.lock(&request.get_ref().lock_id).await?;
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let opts = Self::lock_options(&claims, request.get_ref())?;
+        let res = self.mgr.lock(&opts).await?;
         Ok(Response::new(res))
     }
 
@@ -53,8 +109,85 @@ impl Locks for Service {
         &self,
         request: Request<LockRequest>,
     ) -> Result<Response<Self::TryLockStream>, Status> {
-        self.validator.get_access_token_claims(&request)?;
-        let res = self.mgr.try_lock(&request.get_ref().lock_id).await?;
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let opts = Self::lock_options(&claims, request.get_ref())?;
+        let res = self.mgr.try_lock(&opts).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::locks::acquire", skip(self))]
+    async fn acquire(&self, request: Request<LockRequest>) -> Result<Response<Lease>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        let opts = Self::lock_options(&claims, r)?;
+        let res = self.mgr.acquire(&opts, r.ttl_ms).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::locks::renew", skip(self))]
+    async fn renew(&self, request: Request<RenewLeaseRequest>) -> Result<Response<Lease>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        Self::check_namespace(&r.namespace)?;
+        let res = self
+            .mgr
+            .renew(&claims, &r.namespace, &r.lock_id, r.fencing_token, r.ttl_ms)
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::locks::release", skip(self))]
+    async fn release(
+        &self,
+        request: Request<ReleaseLeaseRequest>,
+    ) -> Result<Response<Lease>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        Self::check_namespace(&r.namespace)?;
+        let res = self
+            .mgr
+            .release(&claims, &r.namespace, &r.lock_id, r.fencing_token)
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::locks::get", skip(self))]
+    async fn get(&self, request: Request<GetLockRequest>) -> Result<Response<LockInfo>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        Self::check_namespace(&r.namespace)?;
+        let res = self.mgr.get(&claims, &r.namespace, &r.lock_id).await?;
+        Ok(Response::new(res))
+    }
+
+    type ListStream = Pin<Box<dyn Stream<Item = Result<LockInfo, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::locks::list", skip(self))]
+    async fn list(
+        &self,
+        request: Request<ListLocksRequest>,
+    ) -> Result<Response<Self::ListStream>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let namespace = &request.get_ref().namespace;
+        Self::check_namespace(namespace)?;
+        let res = self.mgr.list(&claims, namespace).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::locks::force_release", skip(self))]
+    async fn force_release(
+        &self,
+        request: Request<ForceReleaseRequest>,
+    ) -> Result<Response<LockInfo>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        if !claims.adm {
+            return Err(Status::permission_denied(
+                "only admins are allowed to force release locks",
+            ));
+        }
+        // admins may also free the stuck locks of elections and schedules
+        let r = request.get_ref();
+        let res = self.mgr.force_release(&r.namespace, &r.lock_id).await?;
         Ok(Response::new(res))
     }
 }
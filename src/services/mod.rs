@@ -1,11 +1,16 @@
 pub mod auth;
+pub mod blobs;
+pub mod elections;
 pub mod events;
 pub mod groups;
+pub mod hooks;
 pub mod locks;
 pub mod permissions;
 pub mod resources;
+pub mod schedules;
 pub mod schemas;
 pub mod service_accounts;
+pub mod tasks;
 pub mod users;
 
 mod base;
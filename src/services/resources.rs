@@ -7,13 +7,17 @@ use tonic::{Request, Response, Status};
 use crate::api;
 use api::catalog::resources_server::Resources;
 use api::catalog::{
-    CreateResourceRequest, DeleteResourceRequest, GetResourceRequest, ListResourcesRequest,
-    Resource, UpdateResourceRequest,
+    AggregateResourcesRequest, AggregateResourcesResponse, CreateResourceRequest,
+    DeleteResourceRequest, GetResourceRequest, GetResourceTreeRequest, ListReferrersRequest,
+    ListResourcesRequest, PatchType, PurgeResourceRequest, Resource, ResourceTree,
+    RestoreResourceRequest, UpdateResourceRequest, UpdateResourceStatusRequest, WatchEvent,
 };
 
 use crate::managers;
 use crate::token;
 
+use managers::resources::Patch;
+
 use super::base::BaseService;
 
 #[derive(Debug)]
@@ -32,6 +36,23 @@ impl Service {
         let res = Service { mgr, validator };
         Ok(res)
     }
+
+    // Interprets the document of an update according to the patch type.
+    fn patch(patch_type: i32, doc: serde_json::Value) -> Result<Patch, managers::errors::Error> {
+        use managers::errors::Error;
+        match PatchType::from_i32(patch_type) {
+            Some(PatchType::MergePatch) => Ok(Patch::Merge(doc)),
+            Some(PatchType::JsonPatch) => match json_patch::from_value(doc) {
+                Ok(patch) => Ok(Patch::Json(patch)),
+                Err(err) => Err(Error::InvalidArgument(format!(
+                    "failed to parse json patch: {}",
+                    err
+                ))),
+            },
+            Some(PatchType::Replace) => Ok(Patch::Replace(doc)),
+            None => Err(Error::InvalidArgument("unknown patch type".into())),
+        }
+    }
 }
 
 #[tonic::async_trait]
@@ -58,6 +79,11 @@ impl Resources for Service {
             permission_parent_id_value = Self::parse_uuid(&r.permission_parent_id)?;
             permission_parent_id = Some(&permission_parent_id_value);
         }
+
+        let expires_at = match &r.expires_at {
+            Some(ts) => Some(Self::parse_timestamp(ts)?),
+            None => None,
+        };
         let res = self
             .mgr
             .create(crate::managers::resources::CreateOptions {
@@ -68,6 +94,7 @@ impl Resources for Service {
                 data: &data,
                 labels: &request.get_ref().labels,
                 shares: &request.get_ref().shares,
+                expires_at: expires_at.as_ref(),
             })
             .await?;
         Ok(Response::new(res))
@@ -80,7 +107,17 @@ impl Resources for Service {
     ) -> Result<Response<Resource>, Status> {
         let claims = self.validator.get_access_token_claims(&request)?;
         let id = Self::parse_uuid(&request.get_ref().id)?;
-        let res = self.mgr.get(&claims, &id).await?;
+        let mut res = self
+            .mgr
+            .get_with_fields(&claims, &id, &request.get_ref().fields)
+            .await?;
+        self.mgr
+            .expand(
+                &claims,
+                std::slice::from_mut(&mut res),
+                &request.get_ref().expand,
+            )
+            .await?;
         Ok(Response::new(res))
     }
 
@@ -95,6 +132,28 @@ impl Resources for Service {
         Ok(Response::new(res))
     }
 
+    #[tracing::instrument(name = "svc::resources::restore", skip(self))]
+    async fn restore(
+        &self,
+        request: Request<RestoreResourceRequest>,
+    ) -> Result<Response<Resource>, Status> {
+        let context = self.validator.get_context(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.restore(&context, &id).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::resources::purge", skip(self))]
+    async fn purge(
+        &self,
+        request: Request<PurgeResourceRequest>,
+    ) -> Result<Response<Resource>, Status> {
+        let context = self.validator.get_context(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.purge(&context, &id).await?;
+        Ok(Response::new(res))
+    }
+
     #[tracing::instrument(name = "svc::resources::update", skip(self))]
     async fn update(
         &self,
@@ -102,11 +161,21 @@ impl Resources for Service {
     ) -> Result<Response<Resource>, Status> {
         let context = self.validator.get_context(&request)?;
         let id = Self::parse_uuid(&request.get_ref().id)?;
-        let patch_object = Self::parse_json(&request.get_ref().data)?;
-        let labels = &request.get_ref().labels;
+        let r = request.get_ref();
+        let patch = Self::patch(r.patch_type, Self::parse_json(&r.data)?)?;
+        let expires_at = match (&r.expires_at, r.clear_expires_at) {
+            (Some(_), true) => {
+                return Err(Status::invalid_argument(
+                    "expires_at and clear_expires_at are mutually exclusive",
+                ))
+            }
+            (Some(ts), false) => Some(Some(Self::parse_timestamp(ts)?)),
+            (None, true) => Some(None),
+            (None, false) => None,
+        };
         let result = self
             .mgr
-            .update(&context, &id, &patch_object, labels)
+            .update(&context, &id, &patch, &r.update_mask, &r.labels, expires_at)
             .await?;
         Ok(Response::new(result))
     }
@@ -119,16 +188,162 @@ impl Resources for Service {
         request: Request<ListResourcesRequest>,
     ) -> Result<Response<Self::ListStream>, Status> {
         let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+
+        let parent_id = match r.parent_id.is_empty() {
+            true => None,
+            false => Some(Self::parse_uuid(&r.parent_id)?),
+        };
+        let descendants_of = match r.descendants_of.is_empty() {
+            true => None,
+            false => Some(Self::parse_uuid(&r.descendants_of)?),
+        };
+
         let res = self
             .mgr
-            .list(
-                &claims,
-                &request.get_ref().labels,
-                &request.get_ref().filter,
-                &request.get_ref().kind,
-                &request.get_ref().query,
+            .list(managers::resources::ListOptions {
+                claims: &claims,
+                labels: &r.labels,
+                filter: &r.filter,
+                kind: &r.kind,
+                search_term: &r.query,
+                highlight: r.highlight,
+                parent_id: parent_id.as_ref(),
+                descendants_of: descendants_of.as_ref(),
+                max_depth: r.max_depth,
+                include_deleted: r.include_deleted,
+                fields: &r.fields,
+                where_filter: r.r#where.as_ref(),
+                expand: &r.expand,
+            })
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::resources::get_tree", skip(self))]
+    async fn get_tree(
+        &self,
+        request: Request<GetResourceTreeRequest>,
+    ) -> Result<Response<ResourceTree>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self
+            .mgr
+            .tree(&claims, &id, request.get_ref().max_depth)
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::resources::aggregate", skip(self))]
+    async fn aggregate(
+        &self,
+        request: Request<AggregateResourcesRequest>,
+    ) -> Result<Response<AggregateResourcesResponse>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+
+        let parent_id = match r.parent_id.is_empty() {
+            true => None,
+            false => Some(Self::parse_uuid(&r.parent_id)?),
+        };
+        let descendants_of = match r.descendants_of.is_empty() {
+            true => None,
+            false => Some(Self::parse_uuid(&r.descendants_of)?),
+        };
+
+        let res = self
+            .mgr
+            .aggregate(
+                managers::resources::ListOptions {
+                    claims: &claims,
+                    labels: &r.labels,
+                    filter: &r.filter,
+                    kind: &r.kind,
+                    search_term: &r.query,
+                    highlight: false,
+                    parent_id: parent_id.as_ref(),
+                    descendants_of: descendants_of.as_ref(),
+                    max_depth: r.max_depth,
+                    include_deleted: false,
+                    fields: &[],
+                    where_filter: r.r#where.as_ref(),
+                    expand: &[],
+                },
+                &r.group_by,
+                &r.aggregations,
             )
             .await?;
         Ok(Response::new(res))
     }
+
+    type WatchStream =
+        Pin<Box<dyn Stream<Item = Result<WatchEvent, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::resources::watch", skip(self))]
+    async fn watch(
+        &self,
+        request: Request<ListResourcesRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+
+        let parent_id = match r.parent_id.is_empty() {
+            true => None,
+            false => Some(Self::parse_uuid(&r.parent_id)?),
+        };
+        let descendants_of = match r.descendants_of.is_empty() {
+            true => None,
+            false => Some(Self::parse_uuid(&r.descendants_of)?),
+        };
+
+        let res = self
+            .mgr
+            .watch(managers::resources::ListOptions {
+                claims: &claims,
+                labels: &r.labels,
+                filter: &r.filter,
+                kind: &r.kind,
+                search_term: &r.query,
+                highlight: r.highlight,
+                parent_id: parent_id.as_ref(),
+                descendants_of: descendants_of.as_ref(),
+                max_depth: r.max_depth,
+                include_deleted: r.include_deleted,
+                fields: &r.fields,
+                where_filter: r.r#where.as_ref(),
+                expand: &r.expand,
+            })
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    type ListReferrersStream =
+        Pin<Box<dyn Stream<Item = Result<Resource, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::resources::list_referrers", skip(self))]
+    async fn list_referrers(
+        &self,
+        request: Request<ListReferrersRequest>,
+    ) -> Result<Response<Self::ListReferrersStream>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.list_referrers(&claims, &id).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::resources::update_status", skip(self))]
+    async fn update_status(
+        &self,
+        request: Request<UpdateResourceStatusRequest>,
+    ) -> Result<Response<Resource>, Status> {
+        let context = self.validator.get_context(&request)?;
+        let r = request.get_ref();
+        let id = Self::parse_uuid(&r.id)?;
+        let patch = Self::patch(r.patch_type, Self::parse_json(&r.status)?)?;
+        let res = self
+            .mgr
+            .update_status(&context, &id, &patch, r.observed_generation)
+            .await?;
+        Ok(Response::new(res))
+    }
 }
@@ -0,0 +1,117 @@
+use futures::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+use crate::api;
+use api::catalog::schedules_server::Schedules;
+use api::catalog::{
+    CreateScheduleRequest, DeleteScheduleRequest, GetScheduleRequest, ListSchedulesRequest,
+    MissedRunPolicy, Schedule,
+};
+
+use crate::managers;
+use crate::managers::schedules::ScheduleOptions;
+use crate::token;
+
+use super::base::BaseService;
+
+#[derive(Debug)]
+pub struct Service {
+    mgr: Arc<managers::schedules::Manager>,
+    validator: Arc<token::Validator>,
+}
+
+impl BaseService for Service {}
+
+impl Service {
+    pub fn new(
+        mgr: Arc<managers::schedules::Manager>,
+        validator: Arc<token::Validator>,
+    ) -> Result<Service, sqlx::Error> {
+        let res = Service { mgr, validator };
+        Ok(res)
+    }
+}
+
+#[tonic::async_trait]
+impl Schedules for Service {
+    #[tracing::instrument(name = "svc::schedules::create", skip(self))]
+    async fn create(
+        &self,
+        request: Request<CreateScheduleRequest>,
+    ) -> Result<Response<Schedule>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        let run_at = match &r.run_at {
+            Some(ts) => Some(Self::parse_timestamp(ts)?),
+            None => None,
+        };
+        let resource_id = match r.resource_id.as_str() {
+            "" => None,
+            id => Some(Self::parse_uuid(id)?),
+        };
+        // an empty payload is passed as null
+        let payload = match r.payload.as_str() {
+            "" => serde_json::Value::Null,
+            doc => Self::parse_json(doc)?,
+        };
+        let missed_run_policy = match MissedRunPolicy::from_i32(r.missed_run_policy) {
+            Some(policy) => policy,
+            None => return Err(Status::invalid_argument("invalid missed_run_policy")),
+        };
+        let res = self
+            .mgr
+            .create(
+                &claims,
+                &ScheduleOptions {
+                    cron: &r.cron,
+                    run_at,
+                    resource_id,
+                    kind: &r.kind,
+                    payload: &payload,
+                    missed_run_policy,
+                },
+            )
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::schedules::get", skip(self))]
+    async fn get(
+        &self,
+        request: Request<GetScheduleRequest>,
+    ) -> Result<Response<Schedule>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.get(&claims, &id).await?;
+        Ok(Response::new(res))
+    }
+
+    type ListStream = Pin<Box<dyn Stream<Item = Result<Schedule, Status>> + Send + Sync + 'static>>;
+
+    #[tracing::instrument(name = "svc::schedules::list", skip(self))]
+    async fn list(
+        &self,
+        request: Request<ListSchedulesRequest>,
+    ) -> Result<Response<Self::ListStream>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let resource_id = match request.get_ref().resource_id.as_str() {
+            "" => None,
+            id => Some(Self::parse_uuid(id)?),
+        };
+        let res = self.mgr.list(&claims, resource_id).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::schedules::delete", skip(self))]
+    async fn delete(
+        &self,
+        request: Request<DeleteScheduleRequest>,
+    ) -> Result<Response<Schedule>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let id = Self::parse_uuid(&request.get_ref().id)?;
+        let res = self.mgr.delete(&claims, &id).await?;
+        Ok(Response::new(res))
+    }
+}
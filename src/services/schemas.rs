@@ -12,6 +12,7 @@ use api::catalog::{
 };
 
 use crate::managers;
+use crate::managers::errors::Error;
 
 use super::base::BaseService;
 
@@ -42,6 +43,17 @@ impl Service {
         }
         Ok(())
     }
+
+    // The schema of the task resources is managed by the tasks manager.
+    fn check_kind(kind: &str) -> Result<(), Error> {
+        if kind == managers::tasks::KIND {
+            return Err(Error::InvalidArgument(format!(
+                "kind '{}' is reserved for tasks",
+                kind
+            )));
+        }
+        Ok(())
+    }
 }
 
 #[tonic::async_trait]
@@ -52,6 +64,7 @@ impl Schemas for Service {
         request: Request<CreateSchemaRequest>,
     ) -> Result<Response<Schema>, Status> {
         self.check_admin(&request)?;
+        Self::check_kind(&request.get_ref().kind)?;
         let data = Self::parse_json(&request.get_ref().data)?;
         let res = self.mgr.create(&request.get_ref().kind, &data).await?;
         Ok(Response::new(res))
@@ -72,6 +85,7 @@ impl Schemas for Service {
     ) -> Result<Response<Schema>, Status> {
         self.check_admin(&request)?;
         let id = Self::parse_uuid(&request.get_ref().id)?;
+        Self::check_kind(&self.mgr.get(&id).await?.kind)?;
         let res = self.mgr.delete(&id).await?;
         Ok(Response::new(res))
     }
@@ -83,6 +97,7 @@ impl Schemas for Service {
     ) -> Result<Response<Schema>, Status> {
         self.check_admin(&request)?;
         let id = Self::parse_uuid(&request.get_ref().id)?;
+        Self::check_kind(&self.mgr.get(&id).await?.kind)?;
         let patch_object = Self::parse_json(&request.get_ref().data)?;
         let result = self.mgr.update(&id, &patch_object).await?;
         Ok(Response::new(result))
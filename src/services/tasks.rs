@@ -0,0 +1,104 @@
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+use crate::api;
+use api::catalog::tasks_server::Tasks;
+use api::catalog::{
+    ClaimTaskRequest, ClaimTaskResponse, CompleteTaskRequest, EnqueueTaskRequest, FailTaskRequest,
+    Task,
+};
+
+use crate::managers;
+use crate::token;
+
+use super::base::BaseService;
+
+#[derive(Debug)]
+pub struct Service {
+    mgr: Arc<managers::tasks::Manager>,
+    validator: Arc<token::Validator>,
+}
+
+impl BaseService for Service {}
+
+impl Service {
+    pub fn new(
+        mgr: Arc<managers::tasks::Manager>,
+        validator: Arc<token::Validator>,
+    ) -> Result<Service, sqlx::Error> {
+        let res = Service { mgr, validator };
+        Ok(res)
+    }
+}
+
+#[tonic::async_trait]
+impl Tasks for Service {
+    #[tracing::instrument(name = "svc::tasks::enqueue", skip(self))]
+    async fn enqueue(
+        &self,
+        request: Request<EnqueueTaskRequest>,
+    ) -> Result<Response<Task>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        let resource_id = Self::parse_uuid(&r.resource_id)?;
+        // an empty document is passed as null
+        let payload = match r.payload.as_str() {
+            "" => serde_json::Value::Null,
+            doc => Self::parse_json(doc)?,
+        };
+        let res = self
+            .mgr
+            .enqueue(
+                &claims,
+                &resource_id,
+                &r.queue,
+                &payload,
+                r.max_attempts,
+                r.delay_ms,
+            )
+            .await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::tasks::claim", skip(self))]
+    async fn claim(
+        &self,
+        request: Request<ClaimTaskRequest>,
+    ) -> Result<Response<ClaimTaskResponse>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        let task = self
+            .mgr
+            .claim(&claims, &r.queue, r.visibility_timeout_ms)
+            .await?;
+        Ok(Response::new(ClaimTaskResponse { task }))
+    }
+
+    #[tracing::instrument(name = "svc::tasks::complete", skip(self))]
+    async fn complete(
+        &self,
+        request: Request<CompleteTaskRequest>,
+    ) -> Result<Response<Task>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        let id = Self::parse_uuid(&r.id)?;
+        let claim_id = Self::parse_uuid(&r.claim_id)?;
+        // an empty document is passed as null
+        let result = match r.result.as_str() {
+            "" => serde_json::Value::Null,
+            doc => Self::parse_json(doc)?,
+        };
+        let res = self.mgr.complete(&claims, &id, &claim_id, &result).await?;
+        Ok(Response::new(res))
+    }
+
+    #[tracing::instrument(name = "svc::tasks::fail", skip(self))]
+    async fn fail(&self, request: Request<FailTaskRequest>) -> Result<Response<Task>, Status> {
+        let claims = self.validator.get_access_token_claims(&request)?;
+        let r = request.get_ref();
+        let id = Self::parse_uuid(&r.id)?;
+        let claim_id = Self::parse_uuid(&r.claim_id)?;
+        let res = self.mgr.fail(&claims, &id, &claim_id, &r.error).await?;
+        Ok(Response::new(res))
+    }
+}
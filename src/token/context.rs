@@ -3,5 +3,13 @@ use crate::token::claims::Claims;
 #[derive(Debug, Clone)]
 pub struct Context {
     pub claims: Claims,
-    pub fencing_token: Option<(String, i64)>,
+    pub fencing_token: Option<FencingToken>,
+}
+
+/// Fencing token of an exclusive lease passed along with a write.
+#[derive(Debug, Clone)]
+pub struct FencingToken {
+    pub namespace: String,
+    pub lock_id: String,
+    pub token: i64,
 }
@@ -4,5 +4,5 @@ pub mod error;
 pub mod validator;
 
 pub use claims::Claims;
-pub use context::Context;
+pub use context::{Context, FencingToken};
 pub use validator::Validator;
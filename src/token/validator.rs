@@ -1,8 +1,9 @@
 use jsonwebtoken::{decode, DecodingKey, Validation};
+use tonic::metadata::MetadataMap;
 
 use super::claims::Claims;
 use super::error::Error;
-use crate::token::context::Context;
+use crate::token::context::{Context, FencingToken};
 
 
 pub struct Validator {
@@ -23,21 +24,21 @@ impl Validator {
     }
 
     pub fn get_context<T>(&self, req: &tonic::Request<T>) -> Result<Context, Error> {
-        let claims = self.get_access_token_claims(req)?;
-        let fencing_token = match req.metadata().get("X-Fencing-Token") {
+        self.get_context_from_metadata(req.metadata())
+    }
+
+    /// Same as get_context but for plain HTTP requests, e.g. from the REST API.
+    pub fn get_context_from_headers(&self, headers: &hyper::HeaderMap) -> Result<Context, Error> {
+        self.get_context_from_metadata(&MetadataMap::from_headers(headers.clone()))
+    }
+
+    fn get_context_from_metadata(&self, metadata: &MetadataMap) -> Result<Context, Error> {
+        let claims = self.get_access_token_claims_from_metadata(metadata)?;
+        let fencing_token = match metadata.get("X-Fencing-Token") {
             Some(data) => {
                 let token = std::str::from_utf8(data.as_bytes())?;
                 tracing::debug!("X-Fencing-Token: {}", &token);
-
-                let parts = token.split('#').collect::<Vec<&str>>();
-                if parts.len() != 2 {
-                    return Err(Error::Parse(
-                        "failed to parse X-Fencing-Token header".into(),
-                    ));
-                }
-                let lock_id = parts[0];
-                let fence_id = parts[1].parse::<i64>()?;
-                Some((lock_id.to_string(), fence_id))
+                Some(parse_fencing_token(token)?)
             }
             None => None,
         };
@@ -48,7 +49,14 @@ impl Validator {
     }
 
     pub fn get_access_token_claims<T>(&self, req: &tonic::Request<T>) -> Result<Claims, Error> {
-        let token = Validator::get_token(req)?;
+        self.get_access_token_claims_from_metadata(req.metadata())
+    }
+
+    fn get_access_token_claims_from_metadata(
+        &self,
+        metadata: &MetadataMap,
+    ) -> Result<Claims, Error> {
+        let token = Validator::get_token(metadata)?;
         let claims = self.validate(&token)?;
         match claims.rfs {
             false => Ok(claims),
@@ -58,8 +66,8 @@ impl Validator {
         }
     }
 
-    fn get_token<T>(req: &tonic::Request<T>) -> Result<String, Error> {
-        let value = match req.metadata().get("Authorization") {
+    fn get_token(metadata: &MetadataMap) -> Result<String, Error> {
+        let value = match metadata.get("Authorization") {
             Some(data) => data,
             None => return Err(Error::NotFound),
         };
@@ -80,3 +88,54 @@ impl Validator {
         Ok(token_data.claims)
     }
 }
+
+// Parses the X-Fencing-Token header, `[<namespace>#]<lock id>#<token>`.
+fn parse_fencing_token(token: &str) -> Result<FencingToken, Error> {
+    let parts = token.split('#').collect::<Vec<&str>>();
+    let (namespace, lock_id, fence_id) = match parts[..] {
+        [lock_id, fence_id] => ("", lock_id, fence_id),
+        [namespace, lock_id, fence_id] => (namespace, lock_id, fence_id),
+        _ => {
+            return Err(Error::Parse(
+                "failed to parse X-Fencing-Token header".into(),
+            ))
+        }
+    };
+    Ok(FencingToken {
+        namespace: namespace.to_string(),
+        lock_id: lock_id.to_string(),
+        token: fence_id.parse::<i64>()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_fencing_token_without_namespace() {
+        let token = parse_fencing_token("lock#42").unwrap();
+        assert_eq!(token.namespace, "");
+        assert_eq!(token.lock_id, "lock");
+        assert_eq!(token.token, 42);
+    }
+
+    #[test]
+    fn parse_fencing_token_with_namespace() {
+        let token = parse_fencing_token("elections#leader#7").unwrap();
+        assert_eq!(token.namespace, "elections");
+        assert_eq!(token.lock_id, "leader");
+        assert_eq!(token.token, 7);
+    }
+
+    #[test]
+    fn parse_fencing_token_rejects_malformed_headers() {
+        for token in ["", "42", "a#b#c#1", "lock#", "lock#x", "ns#lock#1.5"] {
+            assert!(
+                matches!(parse_fencing_token(token), Err(Error::Parse(_))),
+                "{}",
+                token
+            );
+        }
+    }
+}